@@ -0,0 +1,187 @@
+//! Send-path throughput and CPU cost.
+//!
+//! The generator sends over loopback TCP to a thread which discards the
+//! data. `generator/*` reports wall-clock throughput. `generator-cpu/*`
+//! times the CPU used for sending instead (by the whole process, less the
+//! discarding thread), so its throughput is bytes per CPU-second:
+//! 1 / (its Gbit/s) is CPU-seconds per Gbit.
+//!
+//! With `--features io-uring`, the io_uring backend is measured too.
+
+#![cfg(target_os = "linux")]
+
+use std::{
+    io,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::{net::TcpStream, runtime};
+
+use seismic::{
+    generator::Generator,
+    stream::{self, BoxedStream},
+    zero_copy::Socket,
+};
+
+/// How long each iteration sends for
+const LENGTH: Duration = Duration::from_millis(100);
+
+/// Bytes each reported time is scaled to, since
+/// the bytes sent per iteration vary
+const NOMINAL_BYTES: u64 = 1 << 30;
+
+/// Chunk sizes to send with
+const CHUNK_SIZES: &[usize] = &[1024, 64 * 1024];
+
+/// How the generator writes
+#[derive(Debug, Clone, Copy)]
+enum Path {
+    Write,
+    Sendfile,
+    #[cfg(feature = "io-uring")]
+    IoUring,
+}
+
+impl Path {
+    const ALL: &'static [Path] = &[
+        Path::Write,
+        Path::Sendfile,
+        #[cfg(feature = "io-uring")]
+        Path::IoUring,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Path::Write => "write",
+            Path::Sendfile => "sendfile",
+            #[cfg(feature = "io-uring")]
+            Path::IoUring => "io-uring",
+        }
+    }
+}
+
+struct Sent {
+    bytes: u64,
+    wall: Duration,
+    cpu: Duration,
+}
+
+impl Sent {
+    /// Scale a duration to the time for [`NOMINAL_BYTES`]
+    fn per_nominal(&self, time: Duration) -> Duration {
+        time.mul_f64(NOMINAL_BYTES as f64 / self.bytes.max(1) as f64)
+    }
+}
+
+/// CPU time used so far, by `clock` (the process or the calling thread)
+fn cpu_time(clock: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid timespec to write to
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Send for [`LENGTH`] to a discarding thread
+async fn send(chunk_size: usize, path: Path) -> Sent {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let drain = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let start = cpu_time(libc::CLOCK_THREAD_CPUTIME_ID);
+        io::copy(&mut stream, &mut io::sink()).unwrap();
+        cpu_time(libc::CLOCK_THREAD_CPUTIME_ID) - start
+    });
+
+    let stream: BoxedStream = Box::new(TcpStream::connect(addr).await.unwrap());
+    let socket = Socket::of(&stream).unwrap().unwrap();
+    #[cfg(feature = "io-uring")]
+    let fd = stream::socket_fd(&stream)
+        .unwrap()
+        .try_clone_to_owned()
+        .unwrap();
+    let (_read_half, write_half) = stream::split(stream);
+    let counter = Arc::new(AtomicU64::new(0));
+    let generator = Generator::new(LENGTH, write_half, chunk_size, counter.clone());
+    let generator = match path {
+        Path::Write => generator,
+        Path::Sendfile => generator.with_zero_copy(socket).unwrap(),
+        #[cfg(feature = "io-uring")]
+        Path::IoUring => generator.with_io_uring(fd),
+    };
+
+    let (wall, cpu) = (Instant::now(), cpu_time(libc::CLOCK_PROCESS_CPUTIME_ID));
+    generator.run().await.unwrap();
+    let wall = wall.elapsed();
+    let drain_cpu = drain.join().unwrap();
+    let cpu = cpu_time(libc::CLOCK_PROCESS_CPUTIME_ID) - cpu;
+
+    Sent {
+        bytes: counter.load(Ordering::SeqCst),
+        wall,
+        cpu: cpu.saturating_sub(drain_cpu),
+    }
+}
+
+fn cases() -> impl Iterator<Item = (&'static Path, &'static usize)> {
+    Path::ALL
+        .iter()
+        .flat_map(|path| CHUNK_SIZES.iter().map(move |size| (path, size)))
+}
+
+fn generator(c: &mut Criterion) {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("generator");
+    group.throughput(Throughput::Bytes(NOMINAL_BYTES));
+    for (&path, &chunk_size) in cases() {
+        group.bench_function(BenchmarkId::new(path.name(), chunk_size), |b| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        let sent = runtime.block_on(send(chunk_size, path));
+                        sent.per_nominal(sent.wall)
+                    })
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("generator-cpu");
+    group.throughput(Throughput::Bytes(NOMINAL_BYTES));
+    for (&path, &chunk_size) in cases() {
+        group.bench_function(BenchmarkId::new(path.name(), chunk_size), |b| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        let sent = runtime.block_on(send(chunk_size, path));
+                        sent.per_nominal(sent.cpu)
+                    })
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .sample_size(10)
+        .warm_up_time(Duration::from_millis(500))
+        .measurement_time(Duration::from_secs(3));
+    targets = generator
+}
+criterion_main!(benches);
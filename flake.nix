@@ -27,7 +27,7 @@
             tag = "latest";
             # Config options reference:
             # https://github.com/moby/moby/blob/master/image/spec/v1.2.md#image-json-field-descriptions
-            config.Cmd = [ "${defaultPackage}/bin/server" ];
+            config.Cmd = [ "${defaultPackage}/bin/seismic" "serve" ];
             copyToRoot = pkgs.buildEnv {
               name = "image-root";
               pathsToLink = [ "/bin" ];
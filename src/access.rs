@@ -0,0 +1,121 @@
+//! Server access control.
+//!
+//! Connections are first checked against CIDR allow/deny lists.
+//! If the server has a pre-shared token, clients must then answer an
+//! HMAC-SHA256 challenge on the control channel before they get a
+//! session, so the token itself never crosses the wire.
+
+use std::{net::IpAddr, path::PathBuf};
+
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes of randomness in each challenge
+const NONCE_LEN: usize = 32;
+
+/// Command-line access control options for the server
+#[derive(clap::Args, Debug, Clone)]
+pub struct AccessArgs {
+    /// Require clients to prove knowledge of this pre-shared token
+    #[clap(long, env = "SEISMIC_AUTH_TOKEN", hide_env_values = true)]
+    pub auth_token: Option<String>,
+    /// Read the pre-shared token from this file
+    #[clap(long, conflicts_with = "auth-token", env = "SEISMIC_AUTH_TOKEN_FILE")]
+    pub auth_token_file: Option<PathBuf>,
+    /// Only accept connections from this network (CIDR), may be repeated
+    #[clap(long)]
+    pub allow: Vec<IpNet>,
+    /// Reject connections from this network (CIDR), may be repeated
+    #[clap(long)]
+    pub deny: Vec<IpNet>,
+}
+
+impl AccessArgs {
+    pub fn config(&self) -> anyhow::Result<AccessConfig> {
+        Ok(AccessConfig {
+            token: read_token(&self.auth_token, &self.auth_token_file)?,
+            allow: self.allow.clone(),
+            deny: self.deny.clone(),
+        })
+    }
+}
+
+/// Command-line authentication options for the client
+#[derive(clap::Args, Debug, Clone)]
+pub struct AuthArgs {
+    /// Pre-shared token, if the server requires authentication
+    #[clap(long, env = "SEISMIC_AUTH_TOKEN", hide_env_values = true)]
+    pub auth_token: Option<String>,
+    /// Read the pre-shared token from this file
+    #[clap(long, conflicts_with = "auth-token", env = "SEISMIC_AUTH_TOKEN_FILE")]
+    pub auth_token_file: Option<PathBuf>,
+}
+
+impl AuthArgs {
+    pub fn token(&self) -> anyhow::Result<Option<String>> {
+        read_token(&self.auth_token, &self.auth_token_file)
+    }
+}
+
+fn read_token(token: &Option<String>, file: &Option<PathBuf>) -> anyhow::Result<Option<String>> {
+    let token = match file {
+        Some(path) => Some(std::fs::read_to_string(path)?.trim().to_string()),
+        None => token.clone(),
+    };
+    if let Some(token) = &token {
+        anyhow::ensure!(!token.is_empty(), "auth token is empty");
+    }
+    Ok(token)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessConfig {
+    /// Pre-shared token; authentication is required if set
+    pub token: Option<String>,
+    /// Networks allowed to connect; empty means all
+    pub allow: Vec<IpNet>,
+    /// Networks never allowed to connect, even if in `allow`
+    pub deny: Vec<IpNet>,
+}
+
+impl AccessConfig {
+    /// Whether connections from this address are allowed
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Generate a random, hex-encoded challenge
+pub fn nonce() -> String {
+    let mut bytes = [0; NONCE_LEN];
+    thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Answer a challenge: the hex-encoded HMAC-SHA256 of the nonce
+pub fn respond(token: &str, nonce: &str) -> String {
+    hex::encode(hmac(token, nonce).finalize().into_bytes())
+}
+
+/// Check a response to a challenge (in constant time)
+pub fn verify(token: &str, nonce: &str, response: &str) -> bool {
+    match hex::decode(response) {
+        Ok(mac) => hmac(token, nonce).verify_slice(&mac).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn hmac(token: &str, nonce: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(token.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(nonce.as_bytes());
+    mac
+}
@@ -0,0 +1,507 @@
+//! Builder-style API for running seismic tests in-process.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use std::time::Duration;
+//! use seismic::Seismic;
+//!
+//! let server = Seismic::server([127, 0, 0, 1]).data_port(0).control_port(0).spawn().await?;
+//! let mset = Seismic::client("127.0.0.1")
+//!     .control_port(server.control_addr().port())
+//!     .data_port(server.data_addr().port())
+//!     .duration(Duration::from_secs(2))
+//!     .run()
+//!     .await?;
+//! server.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{net::TcpListener, sync::broadcast, task::JoinHandle};
+pub use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::{
+    access::AccessConfig,
+    limits::Limits,
+    measurement::{Measurement, MeasurementSet, Warmup},
+    measurer::SamplingConfig,
+    metrics::MetricsConfig,
+    quic,
+    receiver::ReceiverConfig,
+    sender::{Backend, Sender, SenderConfig, Transport},
+    server::{listen_control, listen_data, listen_quic, ServerState},
+    sink::{BroadcastSink, MeasurementSink},
+    store::Store,
+    tls::{TlsClientConfig, TlsServerConfig},
+    transport::{self, UNIX_CONTROL_SOCKET, UNIX_DATA_SOCKET},
+};
+
+/// Default TCP port for control commands
+pub const DEFAULT_CONTROL_PORT: u16 = 7224;
+
+/// Default port for test data
+pub const DEFAULT_DATA_PORT: u16 = 7225;
+
+/// Entry point for embedding seismic
+pub struct Seismic;
+
+impl Seismic {
+    /// A test against the server at `target`: a host or IP address,
+    /// or a socket directory with `Transport::Unix`
+    pub fn client(target: impl Into<String>) -> ClientBuilder {
+        ClientBuilder::new(target.into())
+    }
+
+    /// A server listening on the address `bind`
+    pub fn server(bind: impl Into<IpAddr>) -> ServerBuilder {
+        ServerBuilder::new(bind.into())
+    }
+}
+
+/// Configures and runs a single test
+pub struct ClientBuilder {
+    target: String,
+    control_port: u16,
+    data_port: u16,
+    config: SenderConfig,
+    live: BroadcastSink,
+    sinks: Vec<Box<dyn MeasurementSink>>,
+    cancel: CancellationToken,
+}
+
+impl ClientBuilder {
+    fn new(target: String) -> Self {
+        Self {
+            target,
+            control_port: DEFAULT_CONTROL_PORT,
+            data_port: DEFAULT_DATA_PORT,
+            config: SenderConfig::default(),
+            live: BroadcastSink::new(),
+            sinks: Vec::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Length of transmission (5s by default)
+    pub fn duration(mut self, length: Duration) -> Self {
+        self.config.length = length;
+        self
+    }
+
+    /// Send exactly this many bytes (rounded up to whole chunks),
+    /// however long it takes, instead of for the duration
+    pub fn total_bytes(mut self, bytes: u64) -> Self {
+        self.config.total_bytes = Some(bytes);
+        self
+    }
+
+    /// How much of the start of the run to leave out of its
+    /// summary statistics (none by default)
+    pub fn warmup(mut self, warmup: Warmup) -> Self {
+        self.config.warmup = warmup;
+        self
+    }
+
+    /// Measurement frequency (200ms by default)
+    pub fn freq(mut self, freq: Duration) -> Self {
+        self.config.freq = freq;
+        self
+    }
+
+    /// Adaptive sampling and sample limit
+    pub fn sampling(mut self, sampling: SamplingConfig) -> Self {
+        self.config.sampling = sampling;
+        self
+    }
+
+    /// Bytes per chunk (1024 by default)
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.config.chunk_size = chunk_size;
+        self
+    }
+
+    /// Number of concurrent streams (QUIC only)
+    pub fn streams(mut self, streams: usize) -> Self {
+        self.config.streams = streams;
+        self
+    }
+
+    /// How to carry the test data (TCP by default)
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    /// TCP port of the server's control channel
+    pub fn control_port(mut self, port: u16) -> Self {
+        self.control_port = port;
+        self
+    }
+
+    /// Port of the server's data channel
+    pub fn data_port(mut self, port: u16) -> Self {
+        self.data_port = port;
+        self
+    }
+
+    /// Encrypt the control and data connections
+    pub fn tls(mut self, tls: TlsClientConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Pre-shared token, for servers that require authentication
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.config.auth_token = Some(token.into());
+        self
+    }
+
+    /// Where to push measurements as metrics
+    pub fn metrics(mut self, metrics: MetricsConfig) -> Self {
+        self.config.metrics = metrics;
+        self
+    }
+
+    /// Print measurements as they're recorded
+    pub fn print_live(mut self, print_live: bool) -> Self {
+        self.config.print_live = print_live;
+        self
+    }
+
+    /// Send each stream from a thread pinned to one of these cores
+    pub fn pin_cores(mut self, cores: Vec<usize>) -> Self {
+        self.config.pin_cores = cores;
+        self
+    }
+
+    /// Send with `sendfile` over plain TCP and Unix sockets (Linux only)
+    pub fn zero_copy(mut self, zero_copy: bool) -> Self {
+        self.config.zero_copy = zero_copy;
+        self
+    }
+
+    /// How to send and receive the test data
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Also hand each measurement to `sink` as it's taken
+    pub fn sink(mut self, sink: impl MeasurementSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// End the test early once `cancel` is cancelled
+    pub fn cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Receive each measurement as it's taken
+    pub fn subscribe(&self) -> broadcast::Receiver<Measurement> {
+        self.live.subscribe()
+    }
+
+    /// The sender's configuration, with the server's addresses filled in
+    pub fn config(&self) -> SenderConfig {
+        let address = |port: u16, socket: &str| match self.config.transport {
+            Transport::Unix => Path::new(&self.target).join(socket).display().to_string(),
+            // IPv6 literals need brackets before the port
+            _ => match self.target.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, port).to_string(),
+                Err(_) => format!("{}:{}", self.target, port),
+            },
+        };
+        SenderConfig {
+            addr: address(self.data_port, UNIX_DATA_SOCKET),
+            control_addr: address(self.control_port, UNIX_CONTROL_SOCKET),
+            ..self.config.clone()
+        }
+    }
+
+    /// Run the test to completion. A cancelled test returns what was
+    /// measured so far, with `cancelled=true` in its metadata.
+    pub async fn run(self) -> anyhow::Result<MeasurementSet> {
+        let sender = tokio::select! {
+            sender = Sender::new(self.config()) => sender?,
+            _ = self.cancel.cancelled() => anyhow::bail!("cancelled before the test started"),
+        };
+        let mut sender = sender.with_cancel(self.cancel).with_sink(self.live);
+        for sink in self.sinks {
+            sender = sender.with_sink(sink);
+        }
+        sender.run().await
+    }
+
+    /// Run the test in the background
+    pub fn spawn(self) -> ClientHandle {
+        let cancel = self.cancel.clone();
+        let task = tokio::spawn(self.run());
+        ClientHandle { cancel, task }
+    }
+}
+
+/// A test running in the background
+pub struct ClientHandle {
+    cancel: CancellationToken,
+    task: JoinHandle<anyhow::Result<MeasurementSet>>,
+}
+
+impl ClientHandle {
+    /// End the test early
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Wait for the test to finish
+    pub async fn join(self) -> anyhow::Result<MeasurementSet> {
+        self.task.await?
+    }
+}
+
+/// Configures and starts a server
+pub struct ServerBuilder {
+    bind: IpAddr,
+    control_port: u16,
+    data_port: u16,
+    config: ReceiverConfig,
+    quic: bool,
+    unix_dir: Option<PathBuf>,
+    tls: Option<TlsServerConfig>,
+    access: AccessConfig,
+    limits: Limits,
+    store: Option<Store>,
+    report: bool,
+    cancel: CancellationToken,
+}
+
+impl ServerBuilder {
+    fn new(bind: IpAddr) -> Self {
+        Self {
+            bind,
+            control_port: DEFAULT_CONTROL_PORT,
+            data_port: DEFAULT_DATA_PORT,
+            config: ReceiverConfig::default(),
+            quic: false,
+            unix_dir: None,
+            tls: None,
+            access: AccessConfig::default(),
+            limits: Limits::default(),
+            store: None,
+            report: false,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// TCP port for control commands; 0 picks a free one
+    pub fn control_port(mut self, port: u16) -> Self {
+        self.control_port = port;
+        self
+    }
+
+    /// Port for test data; 0 picks a free one
+    pub fn data_port(mut self, port: u16) -> Self {
+        self.data_port = port;
+        self
+    }
+
+    /// Measurement settings for each session
+    pub fn config(mut self, config: ReceiverConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Also accept test data over QUIC; requires `tls`
+    pub fn quic(mut self, quic: bool) -> Self {
+        self.quic = quic;
+        self
+    }
+
+    /// Also listen on Unix domain sockets in this directory
+    pub fn unix_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.unix_dir = Some(dir.into());
+        self
+    }
+
+    /// Encrypt the control and data connections
+    pub fn tls(mut self, tls: TlsServerConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Who may connect, and whether they must authenticate
+    pub fn access(mut self, access: AccessConfig) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Caps on sessions and the resources they use
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Record each run
+    pub fn store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Print and plot each finished run
+    pub fn report(mut self, report: bool) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Stop accepting connections once `cancel` is cancelled
+    pub fn cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Bind the listeners and serve until cancelled
+    pub async fn serve(self) -> anyhow::Result<()> {
+        self.spawn().await?.join().await
+    }
+
+    /// Bind the listeners, then serve in the background
+    pub async fn spawn(self) -> anyhow::Result<ServerHandle> {
+        let control_listener = TcpListener::bind((self.bind, self.control_port)).await?;
+        let data_listener = TcpListener::bind((self.bind, self.data_port)).await?;
+        let control_addr = control_listener.local_addr()?;
+        let data_addr = data_listener.local_addr()?;
+
+        if self.access.token.is_none() {
+            warn!("no auth token configured; anyone allowed to connect can start a test");
+        }
+        let unix_listeners = match &self.unix_dir {
+            Some(dir) => Some(transport::bind_unix(dir)?),
+            None => None,
+        };
+        let quic_endpoint = match (&self.tls, self.quic) {
+            (Some(tls), true) => Some(quic::server_endpoint(
+                SocketAddr::new(self.bind, data_addr.port()),
+                tls,
+            )?),
+            (None, true) => anyhow::bail!("QUIC requires a TLS certificate"),
+            (_, false) => None,
+        };
+
+        let store = self.store.map(|store| Arc::new(Mutex::new(store)));
+        let mut state = ServerState::new(self.config, store)
+            .with_access(self.access)
+            .with_limits(self.limits);
+        if let Some(tls) = &self.tls {
+            state = state.with_tls(tls.acceptor()?);
+        }
+        if !self.report {
+            state = state.without_report();
+        }
+
+        let control_fut = listen_control(control_listener, state.clone());
+        let data_fut = listen_data(data_listener, state.clone());
+        let unix_state = state.clone();
+        let unix_fut = async move {
+            match unix_listeners {
+                Some((control, data)) => {
+                    let (control_res, data_res) = tokio::join!(
+                        listen_control(control, unix_state.clone()),
+                        listen_data(data, unix_state)
+                    );
+                    control_res.and(data_res)
+                }
+                None => Ok(()),
+            }
+        };
+        let quic_fut = async move {
+            match quic_endpoint {
+                Some(endpoint) => listen_quic(endpoint, state).await,
+                None => Ok(()),
+            }
+        };
+        let listen = async move {
+            let (data_res, control_res, quic_res, unix_res) =
+                tokio::join!(data_fut, control_fut, quic_fut, unix_fut);
+            let mut listen_res = Ok(());
+            for (name, res) in [
+                ("Unix socket", unix_res),
+                ("QUIC", quic_res),
+                ("Data", data_res),
+                ("Control", control_res),
+            ] {
+                if let Err(err) = res {
+                    error!("{} error: {}", name, err);
+                    listen_res =
+                        listen_res.and(Err(err.context(format!("{} listener failed", name))));
+                }
+            }
+            listen_res
+        };
+
+        let cancel = self.cancel.clone();
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                listen_res = listen => listen_res,
+                _ = cancel.cancelled() => Ok(()),
+            }
+        });
+
+        Ok(ServerHandle {
+            control_addr,
+            data_addr,
+            cancel: self.cancel,
+            task,
+        })
+    }
+}
+
+/// A server running in the background. Cancelling it stops
+/// accepting connections; tests in progress run to completion.
+pub struct ServerHandle {
+    control_addr: SocketAddr,
+    data_addr: SocketAddr,
+    cancel: CancellationToken,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl ServerHandle {
+    /// Address of the control listener
+    pub fn control_addr(&self) -> SocketAddr {
+        self.control_addr
+    }
+
+    /// Address of the TCP data listener
+    pub fn data_addr(&self) -> SocketAddr {
+        self.data_addr
+    }
+
+    /// Stop accepting connections
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Wait for the server to stop, returning the first
+    /// listener's error if one failed
+    pub async fn join(self) -> anyhow::Result<()> {
+        self.task.await?
+    }
+
+    /// Stop accepting connections and wait for the listeners to close
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        self.cancel();
+        self.join().await
+    }
+}
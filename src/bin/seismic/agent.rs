@@ -0,0 +1,137 @@
+//! `seismic agent`: serve tests, and test peers on a schedule
+//!
+//! Agents on every host of a mesh, each listing the others as peers,
+//! measure every path in both directions. Each test's results are
+//! recorded with `--db` and pushed to the metrics backends.
+
+use std::time::Duration;
+
+use clap::ArgMatches;
+use tokio::time::MissedTickBehavior;
+
+use seismic::{
+    api::ClientBuilder, config::ConfigFile, stats, store::Store, tls::TlsClientConfig, Seismic,
+};
+use tracing::{info, warn};
+
+use crate::{serve::ServeOpts, test::resolve, Exit};
+
+#[derive(clap::Args)]
+pub struct AgentOpts {
+    /// Peer to test (host, or the name of a target list in the
+    /// config file), may be repeated
+    #[clap(long = "peer", required = true)]
+    peers: Vec<String>,
+    /// How often to test the peers (e.g. 5m)
+    #[clap(
+        long,
+        default_value = "5m",
+        parse(try_from_str = humantime::parse_duration),
+        env = "SEISMIC_EVERY"
+    )]
+    every: Duration,
+    /// Duration (in seconds) of each test
+    #[clap(short, long, default_value = "5", env = "SEISMIC_LENGTH_SECS")]
+    length_secs: u16,
+    /// Bytes per chunk in each test
+    #[clap(short, long, default_value = "1024", env = "SEISMIC_CHUNK_SIZE")]
+    chunk_size: usize,
+    #[clap(flatten)]
+    serve: ServeOpts,
+}
+
+impl AgentOpts {
+    /// Apply the config file's [server] section, if any,
+    /// to options not given explicitly
+    pub fn configure(&mut self, config: Option<&ConfigFile>, matches: &ArgMatches) {
+        self.serve.configure(config, matches);
+    }
+
+    pub async fn run(&self, config: Option<&ConfigFile>) -> anyhow::Result<Exit> {
+        let peers = resolve(&self.peers, config);
+        let mut store = self.serve.db.as_ref().map(Store::open).transpose()?;
+        let server = self.serve.server()?.spawn().await?;
+        info!("Agent listening on {}", server.control_addr());
+
+        let mut ticks = tokio::time::interval(self.every);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let shutdown = tokio::signal::ctrl_c();
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = &mut shutdown => break,
+            }
+            tokio::select! {
+                _ = self.round(&peers, store.as_mut()) => {}
+                _ = &mut shutdown => break,
+            }
+        }
+
+        server.shutdown().await?;
+        Ok(Exit::Success)
+    }
+
+    /// Test each peer in turn
+    async fn round(&self, peers: &[String], mut store: Option<&mut Store>) {
+        for peer in peers {
+            self.test(peer, store.as_deref_mut()).await;
+        }
+    }
+
+    /// Test one peer, logging rather than returning failures
+    /// so that one unreachable peer doesn't stop the agent
+    async fn test(&self, peer: &str, store: Option<&mut Store>) {
+        let result = match self.client(peer) {
+            Ok(client) => client.run().await,
+            Err(err) => Err(err),
+        };
+        let mset = match result {
+            Ok(mset) => mset,
+            Err(err) => {
+                warn!("test of {} failed: {:#}", peer, err);
+                return;
+            }
+        };
+
+        info!(
+            peer,
+            throughput_mbps = mset.mean_throughput() / 1e6,
+            rtt_p99_ms = stats::percentile(&mset.rtt(), 99.0).map(|s| s * 1e3),
+            loss_pct = 100.0 * mset.loss(),
+            "Tested {}",
+            peer
+        );
+        if let Some(store) = store {
+            if let Err(err) = store.insert(&mset) {
+                warn!("failed to record run {}: {:#}", mset.id, err);
+            }
+        }
+    }
+
+    /// A client for testing `peer`, which is expected to run
+    /// an agent set up like this one: on the same ports, and
+    /// with certificates from the same CA
+    fn client(&self, peer: &str) -> anyhow::Result<ClientBuilder> {
+        let serve = &self.serve;
+        let mut client = Seismic::client(peer)
+            .control_port(serve.control_port)
+            .data_port(serve.data_port)
+            .duration(Duration::from_secs(self.length_secs as u64))
+            .freq(serve.freq)
+            .sampling(serve.sampling.clone().into())
+            .chunk_size(self.chunk_size)
+            .metrics(serve.metrics.clone().into())
+            .print_live(false);
+        if let (Some(cert), Some(key)) = (&serve.tls.tls_cert, &serve.tls.tls_key) {
+            let mut tls = TlsClientConfig::new(peer);
+            tls.ca = serve.tls.tls_client_ca.clone();
+            tls.identity = Some((cert.clone(), key.clone()));
+            client = client.tls(tls);
+        }
+        if let Some(token) = serve.access.config()?.token {
+            client = client.auth_token(token);
+        }
+        Ok(client)
+    }
+}
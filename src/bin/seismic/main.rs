@@ -0,0 +1,445 @@
+//! The `seismic` command-line tool
+
+mod agent;
+mod mesh;
+mod report;
+mod serve;
+mod test;
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{ArgEnum, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use serde::Serialize;
+use tokio::net::{TcpListener, UdpSocket};
+use uuid::Uuid;
+
+use seismic::{
+    compare::{Comparison, Run},
+    config::ConfigFile,
+    impair::{self, ImpairArgs, Impairment},
+    measurement::MeasurementSet,
+    measurer::{parse_interval, SamplingConfig},
+    metrics::MetricsConfig,
+    receiver::ReceiverConfig,
+    sender::{Sender, SenderConfig},
+    server::{listen_control, listen_data, ServerState},
+    tracing::{init_tracing, TracingArgs},
+    transport::{self, Connector, UnixConnector, UNIX_CONTROL_SOCKET, UNIX_DATA_SOCKET},
+};
+
+use crate::{
+    agent::AgentOpts,
+    mesh::MeshOpts,
+    report::{ExportOpts, ReportOpts},
+    serve::ServeOpts,
+    test::TestOpts,
+};
+
+/// Size of each direction's buffer for in-memory pipes
+const DUPLEX_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Network throughput and latency testing
+#[derive(Parser)]
+#[clap(name = "seismic", version, propagate_version = true)]
+struct Opts {
+    #[clap(flatten)]
+    tracing: TracingArgs,
+    /// Read options from this TOML file; the command line and
+    /// environment take precedence
+    #[clap(long, global = true, env = "SEISMIC_CONFIG")]
+    config: Option<PathBuf>,
+    /// How to print results
+    #[clap(long, arg_enum, default_value = "text", global = true)]
+    format: Format,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Accept tests from clients
+    Serve(ServeOpts),
+    /// Send test data to a server and measure what arrives
+    #[clap(after_help = EXIT_CODES)]
+    Test(TestOpts),
+    /// Test several targets in turn and tabulate the results
+    #[clap(after_help = EXIT_CODES)]
+    Mesh(MeshOpts),
+    /// Accept tests, and test peers on a schedule
+    Agent(AgentOpts),
+    /// List runs recorded with --db, or show one of them
+    Report(ReportOpts),
+    /// Compare exported measurement sets against the first one
+    #[clap(after_help = "Exits with 3 if any run significantly regressed from the baseline.")]
+    Compare {
+        /// Exported measurement files (the first is the baseline)
+        #[clap(required = true, min_values = 2)]
+        files: Vec<PathBuf>,
+        /// Significance level for statistical tests
+        #[clap(short, long, default_value = "0.05")]
+        alpha: f64,
+        /// Don't plot the overlaid curves
+        #[clap(long)]
+        no_plot: bool,
+    },
+    /// Measure the host's own overhead by running the client
+    /// and server in this process, without a network
+    Baseline {
+        /// Where the data goes
+        #[clap(long, arg_enum, default_value = "duplex")]
+        transport: BaselineTransport,
+        /// Duration (in seconds) of transmission
+        #[clap(short, default_value = "5")]
+        length_secs: u16,
+        /// Measurement interval, in milliseconds or with a unit (e.g. 250us)
+        #[clap(short, default_value = "200", parse(try_from_str = parse_interval))]
+        freq: Duration,
+        /// Bytes per chunk
+        #[clap(short, default_value = "1024")]
+        chunk_size: usize,
+        /// Export measurements to a JSON file
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Forward traffic to a server, injecting delay,
+    /// jitter, bandwidth caps, stalls and loss
+    Impair {
+        /// Address to forward to (host:port)
+        upstream: String,
+        /// Address to listen on
+        #[clap(long, default_value = "0.0.0.0:8225")]
+        listen: String,
+        /// Also forward UDP on the same port (e.g. for QUIC)
+        #[clap(long)]
+        udp: bool,
+        /// Forget a UDP client after this long without datagrams (e.g. 30s)
+        #[clap(
+            long,
+            default_value = "60s",
+            parse(try_from_str = humantime::parse_duration)
+        )]
+        udp_idle: Duration,
+        #[clap(flatten)]
+        impairment: ImpairArgs,
+    },
+    /// Export a run recorded with --db as JSON
+    Export(ExportOpts),
+    /// Print a shell completion script
+    Completions {
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+    /// Write man pages for seismic and its subcommands
+    Man {
+        /// Directory to write the pages to
+        #[clap(default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+/// How results are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Format {
+    /// Tables and plots
+    Text,
+    /// JSON, for other tools
+    Json,
+}
+
+/// Process exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exit {
+    /// Everything worked, and all thresholds were met
+    Success = 0,
+    /// Something failed (e.g. transmission, or writing results)
+    Error = 1,
+    /// Transmission succeeded, but thresholds were violated, or
+    /// `compare` found a regression (clap already exits with 2
+    /// for usage errors)
+    ThresholdViolated = 3,
+}
+
+/// How `test` and `mesh` report their results to scripts
+const EXIT_CODES: &str = "Exits with 0 when all thresholds are met, 1 if the \
+    transmission (or writing results) failed, 2 for usage errors and 3 if any \
+    threshold was violated.";
+
+impl Exit {
+    /// The worse of two results, from testing several targets
+    pub fn worst(self, other: Exit) -> Exit {
+        match (self, other) {
+            (Exit::Error, _) | (_, Exit::Error) => Exit::Error,
+            (Exit::ThresholdViolated, _) | (_, Exit::ThresholdViolated) => Exit::ThresholdViolated,
+            _ => Exit::Success,
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+/// Print `value` as a line of JSON
+pub fn print_json(value: &impl Serialize) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+enum BaselineTransport {
+    /// In-memory pipe
+    Duplex,
+    /// Unix domain sockets in a temporary directory
+    Unix,
+}
+
+fn compare(files: Vec<PathBuf>, alpha: f64, no_plot: bool, format: Format) -> anyhow::Result<Exit> {
+    let runs = files
+        .into_iter()
+        .map(|path| {
+            let mset = MeasurementSet::load(&path)?;
+            let label = path.display().to_string();
+            Ok(Run { label, mset })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let comparison = Comparison::new(runs, alpha)?;
+    if format == Format::Json {
+        let runs = comparison.runs();
+        let deltas: Vec<_> = runs[1..]
+            .iter()
+            .zip(comparison.deltas())
+            .map(|(run, deltas)| serde_json::json!({ "label": run.label, "deltas": deltas }))
+            .collect();
+        print_json(&serde_json::json!({ "baseline": runs[0].label, "runs": deltas }))?;
+    } else {
+        comparison.print();
+        if !no_plot {
+            comparison.plot();
+        }
+    }
+
+    if comparison.has_regression() {
+        Ok(Exit::ThresholdViolated)
+    } else {
+        Ok(Exit::Success)
+    }
+}
+
+async fn baseline(
+    transport: BaselineTransport,
+    config: SenderConfig,
+    output: Option<PathBuf>,
+    format: Format,
+) -> anyhow::Result<()> {
+    let receiver_config = ReceiverConfig {
+        freq: config.freq,
+        sampling: SamplingConfig::default(),
+        chunk_size: config.chunk_size,
+        echo: true,
+        print_live: false,
+        metrics: MetricsConfig::default(),
+    };
+    let state = ServerState::new(receiver_config, None).without_report();
+
+    let mset = match transport {
+        BaselineTransport::Duplex => {
+            let (control, control_listener) = transport::duplex("control", DUPLEX_BUFFER_SIZE);
+            let (data, data_listener) = transport::duplex("data", DUPLEX_BUFFER_SIZE);
+            tokio::spawn(listen_control(control_listener, state.clone()));
+            tokio::spawn(listen_data(data_listener, state));
+
+            let sender = Sender::connect(config, &control, Arc::new(data)).await?;
+            sender.run().await?
+        }
+        BaselineTransport::Unix => {
+            let dir = std::env::temp_dir().join(format!("seismic-baseline-{}", Uuid::new_v4()));
+            let (control_listener, data_listener) = transport::bind_unix(&dir)?;
+            tokio::spawn(listen_control(control_listener, state.clone()));
+            tokio::spawn(listen_data(data_listener, state));
+
+            let control = UnixConnector::new(dir.join(UNIX_CONTROL_SOCKET), None);
+            let data: Arc<dyn Connector> =
+                Arc::new(UnixConnector::new(dir.join(UNIX_DATA_SOCKET), None));
+            let result = match Sender::connect(config, &control, data).await {
+                Ok(sender) => sender.run().await,
+                Err(err) => Err(err),
+            };
+            std::fs::remove_dir_all(&dir).ok();
+            result?
+        }
+    };
+
+    match format {
+        Format::Text => {
+            mset.print();
+            mset.plot();
+        }
+        Format::Json => print_json(&mset)?,
+    }
+    if let Some(path) = output {
+        mset.save(path)?;
+    }
+
+    Ok(())
+}
+
+async fn impair(
+    upstream: String,
+    listen: String,
+    udp: bool,
+    udp_idle: Duration,
+    impairment: Impairment,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&listen).await?;
+    let udp_fut = async {
+        if !udp {
+            return Ok(());
+        }
+        let socket = UdpSocket::bind(&listen).await?;
+        let upstream = tokio::net::lookup_host(&upstream)
+            .await?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("could not resolve {}", upstream))?;
+        impair::proxy_udp(socket, upstream, impairment.clone(), udp_idle).await
+    };
+    let tcp_fut = impair::proxy_tcp(listener, upstream.clone(), impairment.clone());
+
+    tokio::try_join!(tcp_fut, udp_fut)?;
+    Ok(())
+}
+
+/// Write a man page for seismic and one for each subcommand
+fn man(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut command = Opts::command();
+    command.build();
+    let pages = command
+        .get_subcommands()
+        .filter(|sub| sub.get_name() != "help")
+        .map(|sub| {
+            let name = format!("seismic-{}", sub.get_name());
+            sub.clone().name(name)
+        });
+    for page in std::iter::once(command.clone()).chain(pages) {
+        let path = dir.join(format!("{}.1", page.get_name()));
+        let mut file = std::fs::File::create(&path)?;
+        clap_mangen::Man::new(page).render(&mut file)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Load the config file, if any, and apply it to the
+/// options of the subcommand (matched by `matches`)
+fn configure(opts: &mut Opts, matches: &ArgMatches) -> anyhow::Result<Option<ConfigFile>> {
+    let config = opts.config.as_ref().map(ConfigFile::load).transpose()?;
+    match &mut opts.command {
+        Command::Serve(serve) => serve.configure(config.as_ref(), matches),
+        Command::Test(test) => test.configure(config.as_ref(), matches)?,
+        Command::Mesh(mesh) => mesh.configure(config.as_ref(), matches)?,
+        Command::Agent(agent) => agent.configure(config.as_ref(), matches),
+        _ => {}
+    }
+    Ok(config)
+}
+
+impl Command {
+    /// Service name in logs and traces
+    fn service(&self) -> &'static str {
+        match self {
+            Command::Serve(_) | Command::Agent(_) => "seismic_server",
+            Command::Test(_) | Command::Mesh(_) => "seismic_client",
+            Command::Impair { .. } => "seismic_impair",
+            _ => "seismic",
+        }
+    }
+
+    async fn run(self, config: Option<&ConfigFile>, format: Format) -> anyhow::Result<Exit> {
+        match self {
+            Command::Serve(opts) => opts.run().await,
+            Command::Test(opts) => opts.run(config, format).await,
+            Command::Mesh(opts) => opts.run(config, format).await,
+            Command::Agent(opts) => opts.run(config).await,
+            Command::Report(opts) => opts.run(format),
+            Command::Export(opts) => opts.run(),
+            Command::Compare {
+                files,
+                alpha,
+                no_plot,
+            } => compare(files, alpha, no_plot, format),
+            Command::Baseline {
+                transport,
+                length_secs,
+                freq,
+                chunk_size,
+                output,
+            } => {
+                // The connectors decide where the data goes
+                let config = SenderConfig {
+                    freq,
+                    length: Duration::from_secs(length_secs.into()),
+                    chunk_size,
+                    ..SenderConfig::default()
+                };
+                baseline(transport, config, output, format)
+                    .await
+                    .map(|()| Exit::Success)
+            }
+            Command::Impair {
+                upstream,
+                listen,
+                udp,
+                udp_idle,
+                impairment,
+            } => impair(upstream, listen, udp, udp_idle, impairment.into())
+                .await
+                .map(|()| Exit::Success),
+            Command::Completions { shell } => {
+                clap_complete::generate(shell, &mut Opts::command(), "seismic", &mut io::stdout());
+                Ok(Exit::Success)
+            }
+            Command::Man { dir } => man(&dir).map(|()| Exit::Success),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let matches = Opts::command().get_matches();
+    let mut opts = Opts::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let (_, sub_matches) = matches.subcommand().expect("subcommand is required");
+    let config = match configure(&mut opts, sub_matches) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            return Exit::Error.into();
+        }
+    };
+
+    let _tracing = match init_tracing(opts.command.service(), opts.tracing.config()) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("error: failed to set up tracing: {:#}", err);
+            return Exit::Error.into();
+        }
+    };
+
+    match opts.command.run(config.as_ref(), opts.format).await {
+        Ok(exit) => exit.into(),
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            Exit::Error.into()
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! `seismic mesh`: test several targets in turn and tabulate the results
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use seismic::{
+    config::{ConfigFile, Layer},
+    measurement::MeasurementSet,
+    stats,
+};
+
+use crate::{
+    print_json,
+    test::{resolve, ClientArgs},
+    Exit, Format,
+};
+
+#[derive(clap::Args)]
+pub struct MeshOpts {
+    /// Targets (hosts, or names of target lists in the config file);
+    /// defaults to the config file's target
+    targets: Vec<String>,
+    #[clap(flatten)]
+    client: ClientArgs,
+}
+
+/// One target's results
+#[derive(Serialize)]
+struct Row {
+    target: String,
+    /// Mean throughput, in MB/s
+    throughput: Option<f64>,
+    /// 99th percentile RTT, in milliseconds
+    rtt_p99_ms: Option<f64>,
+    loss_pct: Option<f64>,
+    /// "ok", "thresholds violated", or what went wrong
+    result: String,
+}
+
+impl Row {
+    fn new(target: &str, mset: Option<&MeasurementSet>, result: String) -> Self {
+        Self {
+            target: target.to_string(),
+            throughput: mset.map(|mset| mset.mean_throughput() / 1e6),
+            rtt_p99_ms: mset.and_then(|mset| stats::percentile(&mset.rtt(), 99.0).map(|s| s * 1e3)),
+            loss_pct: mset.map(|mset| 100.0 * mset.loss()),
+            result,
+        }
+    }
+
+    fn print(&self) {
+        let cell = |value: Option<f64>| value.map_or("-".into(), |value| format!("{:.2}", value));
+        println!(
+            "{:<32} {:>10} {:>12} {:>8}  {}",
+            self.target,
+            cell(self.throughput),
+            cell(self.rtt_p99_ms),
+            cell(self.loss_pct),
+            self.result,
+        );
+    }
+}
+
+impl MeshOpts {
+    /// Apply the config file, if any, to options not given explicitly
+    pub fn configure(
+        &mut self,
+        config: Option<&ConfigFile>,
+        matches: &ArgMatches,
+    ) -> anyhow::Result<()> {
+        if let Some(mut file) = self.client.file(config)? {
+            if self.targets.is_empty() {
+                self.targets.extend(file.target.take());
+            }
+            self.client.apply(file, &Layer::new(matches));
+        }
+        Ok(())
+    }
+
+    pub async fn run(&self, config: Option<&ConfigFile>, format: Format) -> anyhow::Result<Exit> {
+        let targets = resolve(&self.targets, config);
+        anyhow::ensure!(
+            !targets.is_empty(),
+            "no targets given, on the command line or in the config file"
+        );
+
+        let several = targets.len() > 1;
+        let mut rows = Vec::new();
+        let mut exit = Exit::Success;
+        for target in &targets {
+            let client = self.client.client(target)?.print_live(false);
+            let row = match self.client.measure(client, target, several).await {
+                Ok(mset) => {
+                    let result = self.client.evaluate(&mset, target, several, false);
+                    exit = exit.worst(result);
+                    let label = match result {
+                        Exit::Success => "ok".into(),
+                        Exit::ThresholdViolated => "thresholds violated".into(),
+                        Exit::Error => "failed to write the JUnit report".into(),
+                    };
+                    Row::new(target, Some(&mset), label)
+                }
+                Err(err) => {
+                    exit = exit.worst(self.client.failed(&err, target, several));
+                    Row::new(target, None, format!("error: {:#}", err))
+                }
+            };
+            if format == Format::Text {
+                if rows.is_empty() {
+                    println!(
+                        "{:<32} {:>10} {:>12} {:>8}  result",
+                        "target", "MB/s", "RTT p99 ms", "loss %"
+                    );
+                }
+                row.print();
+            }
+            rows.push(row);
+        }
+
+        if format == Format::Json {
+            print_json(&rows)?;
+        }
+        Ok(exit)
+    }
+}
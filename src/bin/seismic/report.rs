@@ -0,0 +1,101 @@
+//! `seismic report` and `seismic export`: look at runs recorded with `--db`
+
+use std::{path::PathBuf, time::SystemTime};
+
+use uuid::Uuid;
+
+use seismic::{
+    measurement::MeasurementSet,
+    store::{RunFilter, Store},
+};
+
+use crate::{print_json, Exit, Format};
+
+#[derive(clap::Args)]
+pub struct ReportOpts {
+    /// Database written by `--db`
+    #[clap(long, env = "SEISMIC_DB")]
+    db: PathBuf,
+    /// Show this run in full, rather than listing runs
+    id: Option<Uuid>,
+    /// Only runs with this peer (host or host:port)
+    #[clap(long, conflicts_with = "id")]
+    peer: Option<String>,
+    /// Only runs started after this time
+    /// (RFC 3339 timestamp, or a duration such as "2h" meaning that long ago)
+    #[clap(long, parse(try_from_str = parse_time), conflicts_with = "id")]
+    since: Option<SystemTime>,
+    /// Only runs started before this time
+    /// (RFC 3339 timestamp, or a duration such as "2h" meaning that long ago)
+    #[clap(long, parse(try_from_str = parse_time), conflicts_with = "id")]
+    until: Option<SystemTime>,
+}
+
+#[derive(clap::Args)]
+pub struct ExportOpts {
+    /// Database written by `--db`
+    #[clap(long, env = "SEISMIC_DB")]
+    db: PathBuf,
+    /// Run ID, as shown by `report`
+    id: Uuid,
+    /// Write to this JSON file, rather than to stdout
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn parse_time(s: &str) -> anyhow::Result<SystemTime> {
+    if let Ok(time) = humantime::parse_rfc3339_weak(s) {
+        return Ok(time);
+    }
+    let ago = humantime::parse_duration(s)?;
+    SystemTime::now()
+        .checked_sub(ago)
+        .ok_or_else(|| anyhow::anyhow!("{} ago is out of range", s))
+}
+
+fn load(store: &Store, id: Uuid) -> anyhow::Result<MeasurementSet> {
+    store
+        .load(id)?
+        .ok_or_else(|| anyhow::anyhow!("no run with id {}", id))
+}
+
+impl ReportOpts {
+    pub fn run(&self, format: Format) -> anyhow::Result<Exit> {
+        let store = Store::open(&self.db)?;
+
+        if let Some(id) = self.id {
+            let mset = load(&store, id)?;
+            match format {
+                Format::Text => {
+                    mset.print();
+                    mset.plot();
+                }
+                Format::Json => print_json(&mset)?,
+            }
+            return Ok(Exit::Success);
+        }
+
+        let filter = RunFilter {
+            peer: self.peer.clone(),
+            since: self.since,
+            until: self.until,
+        };
+        let runs = store.list(&filter)?;
+        match format {
+            Format::Text => runs.iter().for_each(|run| run.print()),
+            Format::Json => print_json(&runs)?,
+        }
+        Ok(Exit::Success)
+    }
+}
+
+impl ExportOpts {
+    pub fn run(&self) -> anyhow::Result<Exit> {
+        let mset = load(&Store::open(&self.db)?, self.id)?;
+        match &self.output {
+            Some(path) => mset.save(path)?,
+            None => print_json(&mset)?,
+        }
+        Ok(Exit::Success)
+    }
+}
@@ -0,0 +1,185 @@
+//! `seismic serve`: accept tests from clients
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::ArgMatches;
+
+use seismic::{
+    access::AccessArgs,
+    api::ServerBuilder,
+    config::{self, ConfigFile, Layer, ServerFile},
+    limits::LimitsArgs,
+    measurer::{parse_interval, SamplingArgs},
+    metrics::MetricsArgs,
+    receiver::ReceiverConfig,
+    store::Store,
+    tls::{TlsServerArgs, TlsServerConfig},
+    Seismic,
+};
+use tracing::info;
+
+use crate::Exit;
+
+#[derive(clap::Args)]
+pub struct ServeOpts {
+    /// TCP port for control commands.
+    #[clap(long, default_value = "7224", env = "SEISMIC_CONTROL_PORT")]
+    pub control_port: u16,
+    /// Port for data transfer (TCP, and UDP with --quic).
+    #[clap(long, default_value = "7225", env = "SEISMIC_DATA_PORT")]
+    pub data_port: u16,
+    /// Also accept test data over QUIC; requires --tls-cert
+    #[clap(long, requires = "tls-cert", env = "SEISMIC_QUIC")]
+    pub quic: bool,
+    /// Also listen on Unix domain sockets in this directory
+    #[clap(long, env = "SEISMIC_UNIX_DIR")]
+    pub unix_dir: Option<PathBuf>,
+    /// Measurement interval, in milliseconds or with a unit (e.g. 250us)
+    #[clap(
+        short,
+        long,
+        default_value = "200",
+        parse(try_from_str = parse_interval),
+        env = "SEISMIC_FREQ"
+    )]
+    pub freq: Duration,
+    /// Don't print measurements as they're recorded
+    #[clap(short, long, env = "SEISMIC_QUIET")]
+    pub quiet: bool,
+    /// Don't echo test data back, so that clients only measure sending
+    #[clap(long, env = "SEISMIC_NO_ECHO")]
+    pub no_echo: bool,
+    /// Record each run in this SQLite database
+    #[clap(long, env = "SEISMIC_DB")]
+    pub db: Option<PathBuf>,
+    #[clap(flatten)]
+    pub sampling: SamplingArgs,
+    #[clap(flatten)]
+    pub metrics: MetricsArgs,
+    #[clap(flatten)]
+    pub tls: TlsServerArgs,
+    #[clap(flatten)]
+    pub access: AccessArgs,
+    #[clap(flatten)]
+    pub limits: LimitsArgs,
+}
+
+impl ServeOpts {
+    /// Fill in options that weren't given explicitly from the config file
+    fn apply(&mut self, file: ServerFile, layer: &Layer) {
+        layer.set("control-port", &mut self.control_port, file.control_port);
+        layer.set("data-port", &mut self.data_port, file.data_port);
+        layer.set("quic", &mut self.quic, file.quic);
+        layer.set_opt("unix-dir", &mut self.unix_dir, file.unix_dir);
+        layer.set("freq", &mut self.freq, file.freq);
+        layer.set("quiet", &mut self.quiet, file.quiet);
+        layer.set("no-echo", &mut self.no_echo, file.no_echo);
+        layer.set_opt("db", &mut self.db, file.db);
+
+        let sampling = &mut self.sampling;
+        layer.set_opt(
+            "adaptive-max",
+            &mut sampling.adaptive_max,
+            file.adaptive_max,
+        );
+        layer.set_opt("max-samples", &mut sampling.max_samples, file.max_samples);
+
+        let metrics = &mut self.metrics;
+        layer.set_opt("influx-udp", &mut metrics.influx_udp, file.influx_udp);
+        layer.set_opt("influx-http", &mut metrics.influx_http, file.influx_http);
+        layer.set_opt("influx-token", &mut metrics.influx_token, file.influx_token);
+        layer.set_opt("statsd", &mut metrics.statsd, file.statsd);
+        layer.set_opt("otlp-metrics", &mut metrics.otlp_metrics, file.otlp_metrics);
+        layer.set("tags", &mut metrics.tags, config::tags(&file.tag));
+        layer.set("tag-keys", &mut metrics.tag_keys, file.tag_keys);
+
+        let tls = &mut self.tls;
+        layer.set_opt("tls-cert", &mut tls.tls_cert, file.tls_cert);
+        layer.set_opt("tls-key", &mut tls.tls_key, file.tls_key);
+        layer.set_opt("tls-client-ca", &mut tls.tls_client_ca, file.tls_client_ca);
+
+        // A token on the command line overrides a token file in the
+        // config, and vice versa
+        let access = &mut self.access;
+        if access.auth_token.is_none() && access.auth_token_file.is_none() {
+            access.auth_token = file.auth_token;
+            access.auth_token_file = file.auth_token_file;
+        }
+        layer.set("allow", &mut access.allow, file.allow);
+        layer.set("deny", &mut access.deny, file.deny);
+
+        let limits = &mut self.limits;
+        layer.set_opt("max-sessions", &mut limits.max_sessions, file.max_sessions);
+        layer.set_opt(
+            "max-sessions-per-client",
+            &mut limits.max_sessions_per_client,
+            file.max_sessions_per_client,
+        );
+        layer.set_opt(
+            "max-duration-secs",
+            &mut limits.max_duration_secs,
+            file.max_duration_secs,
+        );
+        layer.set_opt(
+            "max-bandwidth",
+            &mut limits.max_bandwidth,
+            file.max_bandwidth,
+        );
+        layer.set(
+            "max-chunk-size",
+            &mut limits.max_chunk_size,
+            file.max_chunk_size,
+        );
+        layer.set("max-streams", &mut limits.max_streams, file.max_streams);
+    }
+
+    /// Apply the config file's [server] section, if any,
+    /// to options not given explicitly
+    pub fn configure(&mut self, config: Option<&ConfigFile>, matches: &ArgMatches) {
+        if let Some(config) = config {
+            self.apply(config.server().clone(), &Layer::new(matches));
+        }
+    }
+
+    /// A server set up with these options
+    pub fn server(&self) -> anyhow::Result<ServerBuilder> {
+        let mut server = Seismic::server([0, 0, 0, 0])
+            .control_port(self.control_port)
+            .data_port(self.data_port)
+            .quic(self.quic)
+            .access(self.access.config()?)
+            .limits(self.limits.clone().into())
+            .config(self.into())
+            .report(true);
+        if let Some(dir) = &self.unix_dir {
+            server = server.unix_dir(dir);
+        }
+        if let Some(tls) = Option::<TlsServerConfig>::from(self.tls.clone()) {
+            server = server.tls(tls);
+        }
+        if let Some(path) = &self.db {
+            server = server.store(Store::open(path)?);
+        }
+        Ok(server)
+    }
+
+    pub async fn run(&self) -> anyhow::Result<Exit> {
+        info!("Hello, server!");
+        self.server()?.serve().await?;
+        Ok(Exit::Success)
+    }
+}
+
+impl From<&ServeOpts> for ReceiverConfig {
+    fn from(opts: &ServeOpts) -> Self {
+        Self {
+            freq: opts.freq,
+            sampling: opts.sampling.clone().into(),
+            echo: !opts.no_echo,
+            print_live: !opts.quiet,
+            metrics: opts.metrics.clone().into(),
+            // Each session uses the chunk size its client asks for
+            ..Self::default()
+        }
+    }
+}
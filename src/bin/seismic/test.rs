@@ -0,0 +1,413 @@
+//! `seismic test`: send test data to a server and measure what arrives
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use clap::ArgMatches;
+
+use seismic::{
+    access::AuthArgs,
+    api::ClientBuilder,
+    config::{self, ClientFile, ConfigFile, Layer},
+    generator::parse_size,
+    measurement::{MeasurementSet, Warmup},
+    measurer::{parse_interval, SamplingArgs},
+    metrics::MetricsArgs,
+    sender::{Backend, Transport},
+    slo::{self, Thresholds},
+    store::Store,
+    tls::TlsClientArgs,
+    Seismic,
+};
+use tracing::{error, info, instrument};
+
+use crate::{print_json, Exit, Format};
+
+/// Name of the JUnit test suite
+const JUNIT_SUITE: &str = "seismic";
+
+#[derive(clap::Args)]
+pub struct TestOpts {
+    /// Target IP / host (or socket directory with --transport unix),
+    /// or the name of a target list in the config file
+    #[clap(env = "SEISMIC_TARGET")]
+    target: Option<String>,
+    #[clap(flatten)]
+    client: ClientArgs,
+}
+
+/// Options for testing against a server, shared by `test` and `mesh`
+#[derive(clap::Args)]
+pub struct ClientArgs {
+    /// Apply this profile from the config file
+    #[clap(long, env = "SEISMIC_PROFILE")]
+    profile: Option<String>,
+    /// Duration (in seconds) of transmission
+    #[clap(short, long, default_value = "5", env = "SEISMIC_LENGTH_SECS")]
+    length_secs: u16,
+    /// Send this much data (e.g. 10GiB or 500MB) instead of
+    /// for a duration, and measure how long it takes
+    #[clap(
+        long,
+        parse(try_from_str = parse_size),
+        conflicts_with = "chunks",
+        env = "SEISMIC_BYTES"
+    )]
+    bytes: Option<u64>,
+    /// Send this many chunks instead of for a duration
+    #[clap(long, env = "SEISMIC_CHUNKS")]
+    chunks: Option<u64>,
+    /// Leave the start of the run out of summary statistics and
+    /// thresholds: seconds or a duration (e.g. 1.5 or 500ms), or
+    /// "auto" to leave out the slow start, until throughput is steady
+    #[clap(long, env = "SEISMIC_WARMUP")]
+    warmup: Option<Warmup>,
+    /// Measurement interval, in milliseconds or with a unit (e.g. 250us)
+    #[clap(
+        short,
+        long,
+        default_value = "200",
+        parse(try_from_str = parse_interval),
+        env = "SEISMIC_FREQ"
+    )]
+    freq: Duration,
+    /// Bytes per chunk
+    #[clap(short, long, default_value = "1024", env = "SEISMIC_CHUNK_SIZE")]
+    chunk_size: usize,
+    /// Port for data transfer (TCP, or UDP with QUIC).
+    #[clap(short = 'p', long, default_value = "7225", env = "SEISMIC_DATA_PORT")]
+    data_port: u16,
+    /// Transport for the test data
+    #[clap(long, arg_enum, default_value = "tcp", env = "SEISMIC_TRANSPORT")]
+    transport: Transport,
+    /// Number of concurrent streams (QUIC only)
+    #[clap(long, default_value = "1", env = "SEISMIC_STREAMS")]
+    streams: usize,
+    /// Send each stream from a thread pinned to one of these CPU cores
+    #[clap(long, use_value_delimiter = true, env = "SEISMIC_PIN_CORES")]
+    pin_cores: Vec<usize>,
+    /// Send with sendfile over plain TCP or Unix sockets (Linux only)
+    #[clap(long, env = "SEISMIC_ZERO_COPY")]
+    zero_copy: bool,
+    /// I/O backend for sending and receiving
+    #[clap(long, arg_enum, default_value = "tokio", env = "SEISMIC_BACKEND")]
+    backend: Backend,
+    /// TCP port for control commands.
+    #[clap(long, default_value = "7224", env = "SEISMIC_CONTROL_PORT")]
+    control_port: u16,
+    /// Don't print measurements as they're recorded
+    #[clap(short, long, env = "SEISMIC_QUIET")]
+    quiet: bool,
+    /// Export measurements to a JSON file
+    #[clap(short, long, env = "SEISMIC_OUTPUT")]
+    output: Option<PathBuf>,
+    /// Fail unless mean throughput is at least this many MB/s
+    #[clap(long, env = "SEISMIC_MIN_THROUGHPUT")]
+    min_throughput: Option<f64>,
+    /// Fail if the 99th percentile RTT exceeds this many milliseconds
+    #[clap(long, env = "SEISMIC_MAX_RTT_P99_MS")]
+    max_rtt_p99_ms: Option<u64>,
+    /// Fail if more than this percentage of chunks is lost
+    #[clap(long, env = "SEISMIC_MAX_LOSS_PCT")]
+    max_loss_pct: Option<f64>,
+    /// Fail if no data is received for longer than this many milliseconds
+    #[clap(long, env = "SEISMIC_MAX_STALL_MS")]
+    max_stall_ms: Option<u64>,
+    /// Write threshold results as a JUnit XML report
+    #[clap(long, env = "SEISMIC_JUNIT")]
+    junit: Option<PathBuf>,
+    /// Record the run in this SQLite database
+    #[clap(long, env = "SEISMIC_DB")]
+    db: Option<PathBuf>,
+    #[clap(flatten)]
+    sampling: SamplingArgs,
+    #[clap(flatten)]
+    metrics: MetricsArgs,
+    #[clap(flatten)]
+    tls: TlsClientArgs,
+    #[clap(flatten)]
+    auth: AuthArgs,
+}
+
+impl TestOpts {
+    /// Apply the config file, if any, to options not given explicitly
+    pub fn configure(
+        &mut self,
+        config: Option<&ConfigFile>,
+        matches: &ArgMatches,
+    ) -> anyhow::Result<()> {
+        if let Some(mut file) = self.client.file(config)? {
+            let layer = Layer::new(matches);
+            layer.set_opt("target", &mut self.target, file.target.take());
+            self.client.apply(file, &layer);
+        }
+        Ok(())
+    }
+
+    pub async fn run(&self, config: Option<&ConfigFile>, format: Format) -> anyhow::Result<Exit> {
+        let target = self
+            .target
+            .clone()
+            .context("no target given, on the command line or in the config file")?;
+        let targets = resolve(&[target], config);
+
+        info!("Hello, client!");
+
+        let several = targets.len() > 1;
+        let mut exit = Exit::Success;
+        for target in &targets {
+            if several {
+                info!("Testing {}", target);
+            }
+            exit = exit.worst(self.test(target, several, format).await);
+        }
+        Ok(exit)
+    }
+
+    /// Test one target, printing the results in `format`
+    async fn test(&self, target: &str, several: bool, format: Format) -> Exit {
+        let client = &self.client;
+        let live = format == Format::Text && !client.quiet;
+        let result = match client.client(target) {
+            Ok(builder) => {
+                client
+                    .measure(builder.print_live(live), target, several)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        let mset = match result {
+            Ok(mset) => mset,
+            Err(err) => return client.failed(&err, target, several),
+        };
+
+        let text = format == Format::Text;
+        if text {
+            mset.print();
+            mset.plot();
+        } else if let Err(err) = print_json(&mset) {
+            error!("failed to print measurements: {}", err);
+            return Exit::Error;
+        }
+        client.evaluate(&mset, target, several, text)
+    }
+}
+
+impl ClientArgs {
+    /// The client's options from the config file, with the profile on top
+    pub fn file(&self, config: Option<&ConfigFile>) -> anyhow::Result<Option<ClientFile>> {
+        match (config, &self.profile) {
+            (Some(config), profile) => config.client(profile.as_deref()).map(Some),
+            (None, Some(_)) => anyhow::bail!("--profile requires --config"),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Fill in options that weren't given explicitly from the config file
+    pub fn apply(&mut self, file: ClientFile, layer: &Layer) {
+        layer.set("length-secs", &mut self.length_secs, file.length_secs);
+        // How much to send, given in any way on the command line,
+        // overrides how much to send in the config
+        if !["length-secs", "bytes", "chunks"]
+            .iter()
+            .any(|id| layer.is_explicit(id))
+        {
+            self.bytes = file.bytes;
+            self.chunks = file.chunks;
+        }
+        layer.set_opt("warmup", &mut self.warmup, file.warmup);
+        layer.set("freq", &mut self.freq, file.freq);
+        layer.set("chunk-size", &mut self.chunk_size, file.chunk_size);
+        layer.set("data-port", &mut self.data_port, file.data_port);
+        layer.set("control-port", &mut self.control_port, file.control_port);
+        layer.set("transport", &mut self.transport, file.transport);
+        layer.set("streams", &mut self.streams, file.streams);
+        layer.set("pin-cores", &mut self.pin_cores, file.pin_cores);
+        layer.set("zero-copy", &mut self.zero_copy, file.zero_copy);
+        layer.set("backend", &mut self.backend, file.backend);
+        layer.set("quiet", &mut self.quiet, file.quiet);
+        layer.set_opt("output", &mut self.output, file.output);
+        layer.set_opt(
+            "min-throughput",
+            &mut self.min_throughput,
+            file.min_throughput,
+        );
+        layer.set_opt(
+            "max-rtt-p99-ms",
+            &mut self.max_rtt_p99_ms,
+            file.max_rtt_p99_ms,
+        );
+        layer.set_opt("max-loss-pct", &mut self.max_loss_pct, file.max_loss_pct);
+        layer.set_opt("max-stall-ms", &mut self.max_stall_ms, file.max_stall_ms);
+        layer.set_opt("junit", &mut self.junit, file.junit);
+        layer.set_opt("db", &mut self.db, file.db);
+
+        let sampling = &mut self.sampling;
+        layer.set_opt(
+            "adaptive-max",
+            &mut sampling.adaptive_max,
+            file.adaptive_max,
+        );
+        layer.set_opt("max-samples", &mut sampling.max_samples, file.max_samples);
+
+        let metrics = &mut self.metrics;
+        layer.set_opt("influx-udp", &mut metrics.influx_udp, file.influx_udp);
+        layer.set_opt("influx-http", &mut metrics.influx_http, file.influx_http);
+        layer.set_opt("influx-token", &mut metrics.influx_token, file.influx_token);
+        layer.set_opt("statsd", &mut metrics.statsd, file.statsd);
+        layer.set_opt("otlp-metrics", &mut metrics.otlp_metrics, file.otlp_metrics);
+        layer.set("tags", &mut metrics.tags, config::tags(&file.tag));
+        layer.set("tag-keys", &mut metrics.tag_keys, file.tag_keys);
+
+        let tls = &mut self.tls;
+        layer.set("tls", &mut tls.tls, file.tls);
+        layer.set_opt("tls-ca", &mut tls.tls_ca, file.tls_ca);
+        layer.set_opt("tls-cert", &mut tls.tls_cert, file.tls_cert);
+        layer.set_opt("tls-key", &mut tls.tls_key, file.tls_key);
+        layer.set_opt(
+            "tls-server-name",
+            &mut tls.tls_server_name,
+            file.tls_server_name,
+        );
+
+        // A token on the command line overrides a token file in the
+        // config, and vice versa
+        let auth = &mut self.auth;
+        if auth.auth_token.is_none() && auth.auth_token_file.is_none() {
+            auth.auth_token = file.auth_token;
+            auth.auth_token_file = file.auth_token_file;
+        }
+    }
+
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            min_throughput: self.min_throughput,
+            max_rtt_p99: self.max_rtt_p99_ms.map(Duration::from_millis),
+            max_loss_pct: self.max_loss_pct,
+            max_stall: self.max_stall_ms.map(Duration::from_millis),
+        }
+    }
+
+    pub fn client(&self, target: &str) -> anyhow::Result<ClientBuilder> {
+        let mut client = Seismic::client(target)
+            .control_port(self.control_port)
+            .data_port(self.data_port)
+            .freq(self.freq)
+            .sampling(self.sampling.clone().into())
+            .duration(Duration::from_secs(self.length_secs as u64))
+            .chunk_size(self.chunk_size)
+            .print_live(!self.quiet)
+            .metrics(self.metrics.clone().into())
+            .transport(self.transport)
+            .streams(self.streams)
+            .pin_cores(self.pin_cores.clone())
+            .zero_copy(self.zero_copy)
+            .backend(self.backend);
+        let chunks = self
+            .chunks
+            .map(|chunks| chunks.saturating_mul(self.chunk_size as u64));
+        if let Some(bytes) = self.bytes.or(chunks) {
+            client = client.total_bytes(bytes);
+        }
+        if let Some(warmup) = self.warmup {
+            client = client.warmup(warmup);
+        }
+        if let Some(tls) = self.tls.config(target) {
+            client = client.tls(tls);
+        }
+        if let Some(token) = self.auth.token()? {
+            client = client.auth_token(token);
+        }
+        Ok(client)
+    }
+
+    /// Run a test, saving the measurements where requested
+    #[instrument(skip(self, client))]
+    pub async fn measure(
+        &self,
+        client: ClientBuilder,
+        target: &str,
+        several: bool,
+    ) -> anyhow::Result<MeasurementSet> {
+        let mset = client.run().await?;
+
+        if let Some(path) = per_target(&self.output, target, several) {
+            mset.save(&path)?;
+            info!("Saved measurements to {:?}", path);
+        }
+        if let Some(path) = &self.db {
+            Store::open(path)?.insert(&mset)?;
+            info!("Recorded run {} in {:?}", mset.id, path);
+        }
+
+        Ok(mset)
+    }
+
+    /// Check thresholds and write the JUnit report, if requested
+    pub fn evaluate(
+        &self,
+        mset: &MeasurementSet,
+        target: &str,
+        several: bool,
+        print: bool,
+    ) -> Exit {
+        let thresholds = self.thresholds();
+        let report = thresholds.check(mset);
+        if print && !thresholds.is_empty() {
+            report.print();
+        }
+
+        if let Some(path) = per_target(&self.junit, target, several) {
+            if let Err(err) = report.write_junit(JUNIT_SUITE, &path) {
+                error!("failed to write JUnit report to {:?}: {}", path, err);
+                return Exit::Error;
+            }
+        }
+
+        if report.passed() {
+            Exit::Success
+        } else {
+            Exit::ThresholdViolated
+        }
+    }
+
+    /// Report a failed test, in the JUnit report too if requested
+    pub fn failed(&self, err: &anyhow::Error, target: &str, several: bool) -> Exit {
+        error!("test of {} failed: {:#}", target, err);
+        if let Some(path) = per_target(&self.junit, target, several) {
+            let message = format!("send error: {}", err);
+            if let Err(err) = slo::write_junit_error(JUNIT_SUITE, &message, &path) {
+                error!("failed to write JUnit report to {:?}: {}", path, err);
+            }
+        }
+        Exit::Error
+    }
+}
+
+/// Expand the names of target lists in the config file
+pub fn resolve(names: &[String], config: Option<&ConfigFile>) -> Vec<String> {
+    names
+        .iter()
+        .flat_map(|name| {
+            config
+                .and_then(|config| config.targets(name))
+                .map(<[String]>::to_vec)
+                .unwrap_or_else(|| vec![name.clone()])
+        })
+        .collect()
+}
+
+/// With several targets, tell their output files apart by target
+fn per_target(path: &Option<PathBuf>, target: &str, several: bool) -> Option<PathBuf> {
+    let path = path.as_ref()?;
+    if !several {
+        return Some(path.clone());
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = target.replace(['/', ':'], "_");
+    let mut file = format!("{}-{}", stem, name);
+    if let Some(ext) = path.extension() {
+        file = format!("{}.{}", file, ext.to_string_lossy());
+    }
+    Some(path.with_file_name(file))
+}
@@ -0,0 +1,348 @@
+use ansi_rgb::Foreground;
+use rgb::RGB8;
+use serde::Serialize;
+use textplots::{Chart, ColorPlot, Shape};
+
+use crate::{
+    measurement::MeasurementSet,
+    stats::{self, ProportionTest, WelchTest},
+};
+
+const COLORS: [[u8; 3]; 6] = [
+    [255, 0, 0],
+    [0, 255, 0],
+    [0, 128, 255],
+    [255, 255, 0],
+    [255, 0, 255],
+    [0, 255, 255],
+];
+
+/// Whether a run differs meaningfully from the baseline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    NoChange,
+    /// Not enough data to run the statistical test
+    Unknown,
+}
+
+impl Verdict {
+    /// `worse_if_higher` indicates whether an increase
+    /// in the metric is a regression (e.g. RTT, loss).
+    fn new(significant: Option<bool>, delta: f64, worse_if_higher: bool) -> Self {
+        match significant {
+            None => Self::Unknown,
+            Some(false) => Self::NoChange,
+            Some(true) if (delta > 0.0) == worse_if_higher => Self::Regression,
+            Some(true) => Self::Improvement,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Regression => "REGRESSION",
+            Self::Improvement => "improvement",
+            Self::NoChange => "no significant change",
+            Self::Unknown => "insufficient data",
+        }
+    }
+}
+
+/// Comparison of a single metric between the baseline and another run
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricDelta {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub baseline: f64,
+    pub value: f64,
+    pub p_value: Option<f64>,
+    pub verdict: Verdict,
+}
+
+impl MetricDelta {
+    pub fn delta(&self) -> f64 {
+        self.value - self.baseline
+    }
+
+    fn print(&self) {
+        let pct = if self.baseline != 0.0 {
+            format!("{:+.1}%", 100.0 * self.delta() / self.baseline)
+        } else {
+            "-".to_string()
+        };
+        let p_value = self
+            .p_value
+            .map(|p| format!("{:.4}", p))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "  {:<16} {:>12.3} {:>12.3} {:>+12.3} {:>8} {:>8}  {}",
+            format!("{} ({})", self.name, self.unit),
+            self.baseline,
+            self.value,
+            self.delta(),
+            pct,
+            p_value,
+            self.verdict.label()
+        );
+    }
+}
+
+/// A labelled measurement set, e.g. loaded from an exported file
+pub struct Run {
+    pub label: String,
+    pub mset: MeasurementSet,
+}
+
+/// Compares two or more runs against the first one (the baseline)
+pub struct Comparison {
+    runs: Vec<Run>,
+    /// Significance level for statistical tests
+    alpha: f64,
+    /// Common time offsets (seconds) at which all runs are compared
+    times: Vec<f64>,
+}
+
+impl Comparison {
+    pub fn new(runs: Vec<Run>, alpha: f64) -> anyhow::Result<Self> {
+        if runs.len() < 2 {
+            anyhow::bail!("at least two runs are required for comparison");
+        }
+        for run in &runs {
+            if run.mset.measurements.len() < 2 {
+                anyhow::bail!("run '{}' has fewer than two measurements", run.label);
+            }
+        }
+
+        // Align all runs on a common grid spanning the range covered
+        // by every run after its warm-up, spaced by the baseline's
+        // mean sampling interval.
+        let first = |run: &Run| run.mset.measurements[0].dt.as_secs_f64();
+        let last = |run: &Run| run.mset.time().last().copied().unwrap_or(0.0);
+        let warm = |run: &Run| first(run).max(run.mset.warmup().as_secs_f64());
+        let start = runs.iter().map(warm).fold(0.0, f64::max);
+        let end = runs.iter().map(last).fold(f64::INFINITY, f64::min);
+        let n = runs[0].mset.measurements.len() as f64;
+        let step = (last(&runs[0]) - first(&runs[0])) / (n - 1.0);
+        if step <= 0.0 || end <= start {
+            anyhow::bail!("runs do not overlap in time");
+        }
+        let times = (0..)
+            .map(|i| start + i as f64 * step)
+            .take_while(|&t| t <= end)
+            .collect();
+
+        Ok(Self { runs, alpha, times })
+    }
+
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    /// Received throughput (MB/s) of each run over each
+    /// interval between consecutive aligned time offsets.
+    pub fn aligned_throughput(&self) -> Vec<Vec<f64>> {
+        self.runs
+            .iter()
+            .map(|run| {
+                // Keep each time with its sample, so that a missing
+                // sample drops its intervals instead of shifting the rest
+                let received: Vec<(f64, Option<f64>)> = self
+                    .times
+                    .iter()
+                    .map(|&t| (t, run.mset.interpolate_bytes(t).map(|(_s, r)| r)))
+                    .collect();
+                received
+                    .windows(2)
+                    .filter_map(|w| match (w[0], w[1]) {
+                        ((t0, Some(r0)), (t1, Some(r1))) => Some((r1 - r0) / (t1 - t0) / 1e6),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Compare each non-baseline run to the baseline
+    pub fn deltas(&self) -> Vec<Vec<MetricDelta>> {
+        let throughput = self.aligned_throughput();
+        let baseline = &self.runs[0].mset;
+        let base_rtt: Vec<f64> = baseline.rtt().iter().map(|s| s * 1e3).collect();
+
+        self.runs
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, run)| {
+                let mut deltas = Vec::new();
+
+                let welch = WelchTest::new(&throughput[0], &throughput[i]);
+                let value = stats::mean(&throughput[i]);
+                let base = stats::mean(&throughput[0]);
+                deltas.push(MetricDelta {
+                    name: "throughput",
+                    unit: "MB/s",
+                    baseline: base,
+                    value,
+                    p_value: welch.map(|w| w.p_value),
+                    verdict: Verdict::new(
+                        welch.map(|w| w.is_significant(self.alpha)),
+                        value - base,
+                        false,
+                    ),
+                });
+
+                let rtt: Vec<f64> = run.mset.rtt().iter().map(|s| s * 1e3).collect();
+                let welch = WelchTest::new(&base_rtt, &rtt);
+                let value = stats::mean(&rtt);
+                let base = stats::mean(&base_rtt);
+                deltas.push(MetricDelta {
+                    name: "rtt",
+                    unit: "ms",
+                    baseline: base,
+                    value,
+                    p_value: welch.map(|w| w.p_value),
+                    verdict: Verdict::new(
+                        welch.map(|w| w.is_significant(self.alpha)),
+                        value - base,
+                        true,
+                    ),
+                });
+
+                let lost = |mset: &MeasurementSet| {
+                    mset.measurements
+                        .last()
+                        .map(|m| {
+                            let lost = m.sent_bytes.saturating_sub(m.received_bytes);
+                            (lost, m.sent_bytes)
+                        })
+                        .unwrap_or((0, 0))
+                };
+                let (xa, na) = lost(baseline);
+                let (xb, nb) = lost(&run.mset);
+                let test = ProportionTest::new(xa, na, xb, nb);
+                let value = 100.0 * run.mset.loss();
+                let base = 100.0 * baseline.loss();
+                let significant = match test {
+                    Some(test) => Some(test.is_significant(self.alpha)),
+                    // Identical (all or nothing) loss on both sides
+                    None if na > 0 && nb > 0 => Some(false),
+                    None => None,
+                };
+                deltas.push(MetricDelta {
+                    name: "loss",
+                    unit: "%",
+                    baseline: base,
+                    value,
+                    p_value: test.map(|t| t.p_value),
+                    verdict: Verdict::new(significant, value - base, true),
+                });
+
+                deltas
+            })
+            .collect()
+    }
+
+    /// Whether any run regressed relative to the baseline
+    pub fn has_regression(&self) -> bool {
+        self.deltas()
+            .iter()
+            .flatten()
+            .any(|d| d.verdict == Verdict::Regression)
+    }
+
+    pub fn print(&self) {
+        println!("Runs");
+        for (i, run) in self.runs.iter().enumerate() {
+            let role = if i == 0 { " (baseline)" } else { "" };
+            println!(
+                "  [{}] {}{} - {} @ {:?}",
+                i, run.label, role, run.mset.id, run.mset.start_time
+            );
+        }
+        println!();
+
+        println!("Aligned throughput (MB/s)");
+        let throughput = self.aligned_throughput();
+        print!("  {:>8}", "dt");
+        for i in 0..self.runs.len() {
+            print!(" {:>10}", format!("[{}]", i));
+        }
+        for i in 1..self.runs.len() {
+            print!(" {:>10}", format!("[{}]-[0]", i));
+        }
+        println!();
+        for (step, t) in self.times.iter().skip(1).enumerate() {
+            print!("  {:>7.2}s", t);
+            for run in &throughput {
+                print!(" {:>10.3}", run[step]);
+            }
+            for run in throughput.iter().skip(1) {
+                print!(" {:>+10.3}", run[step] - throughput[0][step]);
+            }
+            println!();
+        }
+        println!();
+
+        for (i, deltas) in self.deltas().iter().enumerate() {
+            println!(
+                "[{}] {} vs baseline (alpha = {})",
+                i + 1,
+                self.runs[i + 1].label,
+                self.alpha
+            );
+            println!(
+                "  {:<16} {:>12} {:>12} {:>12} {:>8} {:>8}  verdict",
+                "metric", "baseline", "value", "delta", "delta%", "p-value"
+            );
+            for delta in deltas {
+                delta.print();
+            }
+            println!();
+        }
+    }
+
+    /// Overlay the received curves of all runs
+    pub fn plot(&self) {
+        let curves: Vec<Vec<(f32, f32)>> = self
+            .runs
+            .iter()
+            .map(|run| {
+                run.mset
+                    .measurements
+                    .iter()
+                    .map(|m| (m.dt.as_secs_f32(), m.received_bytes as f32 / 1e6))
+                    .collect()
+            })
+            .collect();
+        let shapes: Vec<Shape> = curves.iter().map(|c| Shape::Lines(c)).collect();
+
+        let width = 120;
+        let height = 60;
+        let xmax = curves
+            .iter()
+            .filter_map(|c| c.last())
+            .map(|(t, _)| *t)
+            .fold(0.0, f32::max);
+
+        let mut chart = Chart::new(width, height, 0.0, xmax);
+        let mut chart = &mut chart;
+        for (i, shape) in shapes.iter().enumerate() {
+            let color: RGB8 = COLORS[i % COLORS.len()].into();
+            chart = chart.linecolorplot(shape, color);
+        }
+        chart.nice();
+
+        let legend: Vec<String> = self
+            .runs
+            .iter()
+            .enumerate()
+            .map(|(i, run)| {
+                let color: RGB8 = COLORS[i % COLORS.len()].into();
+                format!("[{}] {}", i, run.label).fg(color).to_string()
+            })
+            .collect();
+        println!("received (MB): {}", legend.join(" "));
+    }
+}
@@ -0,0 +1,397 @@
+//! Configuration files.
+//!
+//! A TOML file sets the client's and server's options under the same
+//! names as their long command-line options, in `[client]` and
+//! `[server]` sections. Named `[profiles.<name>]` override `[client]`
+//! for particular kinds of test, and `[targets]` names lists of
+//! targets to test in turn. Options given on the command line or in
+//! `SEISMIC_*` environment variables take precedence over the file.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{ArgMatches, ValueSource};
+use ipnet::IpNet;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    generator::parse_size,
+    measurement::Warmup,
+    measurer::parse_interval,
+    sender::{Backend, Transport},
+};
+
+/// Client options, named like the client's long options
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ClientFile {
+    pub target: Option<String>,
+    pub length_secs: Option<u16>,
+    /// An amount of data like "10GiB", or a number of bytes
+    #[serde(deserialize_with = "size")]
+    pub bytes: Option<u64>,
+    pub chunks: Option<u64>,
+    /// Seconds, a duration like "500ms", or "auto"
+    #[serde(deserialize_with = "warmup")]
+    pub warmup: Option<Warmup>,
+    #[serde(deserialize_with = "interval")]
+    pub freq: Option<Duration>,
+    pub chunk_size: Option<usize>,
+    pub data_port: Option<u16>,
+    pub control_port: Option<u16>,
+    pub transport: Option<Transport>,
+    pub streams: Option<usize>,
+    pub pin_cores: Option<Vec<usize>>,
+    pub zero_copy: Option<bool>,
+    pub backend: Option<Backend>,
+    pub quiet: Option<bool>,
+    pub output: Option<PathBuf>,
+    pub min_throughput: Option<f64>,
+    pub max_rtt_p99_ms: Option<u64>,
+    pub max_loss_pct: Option<f64>,
+    pub max_stall_ms: Option<u64>,
+    pub junit: Option<PathBuf>,
+    pub db: Option<PathBuf>,
+    #[serde(deserialize_with = "interval")]
+    pub adaptive_max: Option<Duration>,
+    pub max_samples: Option<usize>,
+    pub influx_udp: Option<String>,
+    pub influx_http: Option<String>,
+    pub influx_token: Option<String>,
+    pub statsd: Option<String>,
+    pub otlp_metrics: Option<String>,
+    /// Extra metric tags, as a table rather than `key=value` strings
+    pub tag: Option<BTreeMap<String, String>>,
+    pub tag_keys: Option<Vec<String>>,
+    pub tls: Option<bool>,
+    pub tls_ca: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_server_name: Option<String>,
+    pub auth_token: Option<String>,
+    pub auth_token_file: Option<PathBuf>,
+}
+
+/// Server options, named like the server's long options
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServerFile {
+    pub control_port: Option<u16>,
+    pub data_port: Option<u16>,
+    pub quic: Option<bool>,
+    pub unix_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "interval")]
+    pub freq: Option<Duration>,
+    pub quiet: Option<bool>,
+    pub no_echo: Option<bool>,
+    pub db: Option<PathBuf>,
+    #[serde(deserialize_with = "interval")]
+    pub adaptive_max: Option<Duration>,
+    pub max_samples: Option<usize>,
+    pub influx_udp: Option<String>,
+    pub influx_http: Option<String>,
+    pub influx_token: Option<String>,
+    pub statsd: Option<String>,
+    pub otlp_metrics: Option<String>,
+    /// Extra metric tags, as a table rather than `key=value` strings
+    pub tag: Option<BTreeMap<String, String>>,
+    pub tag_keys: Option<Vec<String>>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub auth_token: Option<String>,
+    pub auth_token_file: Option<PathBuf>,
+    pub allow: Option<Vec<IpNet>>,
+    pub deny: Option<Vec<IpNet>>,
+    pub max_sessions: Option<usize>,
+    pub max_sessions_per_client: Option<usize>,
+    pub max_duration_secs: Option<u64>,
+    pub max_bandwidth: Option<f64>,
+    pub max_chunk_size: Option<usize>,
+    pub max_streams: Option<usize>,
+}
+
+/// The whole file, as written
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Document {
+    client: ClientFile,
+    server: ServerFile,
+    profiles: BTreeMap<String, ClientFile>,
+    targets: BTreeMap<String, Vec<String>>,
+}
+
+/// The client's sections as raw tables, for layering profiles
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Tables {
+    client: toml::Table,
+    profiles: BTreeMap<String, toml::Table>,
+}
+
+/// A loaded and validated configuration file
+#[derive(Debug)]
+pub struct ConfigFile {
+    document: Document,
+    tables: Tables,
+}
+
+impl ConfigFile {
+    /// Read and validate a configuration file
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        Self::parse(&text)
+            .map_err(|err| anyhow::anyhow!("invalid config file {}:\n{:#}", path.display(), err))
+    }
+
+    /// Parse and validate a configuration
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let config = Self {
+            document: toml::from_str(text)?,
+            tables: toml::from_str(text)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.document.client.validate().context("in [client]")?;
+        for name in self.document.profiles.keys() {
+            self.client(Some(name))?
+                .validate()
+                .with_context(|| format!("in [profiles.{}]", name))?;
+        }
+        self.document.server.validate().context("in [server]")?;
+        for (name, targets) in &self.document.targets {
+            anyhow::ensure!(!targets.is_empty(), "target list '{}' is empty", name);
+        }
+        Ok(())
+    }
+
+    /// The client's options, with those of `profile` (if any) on top
+    pub fn client(&self, profile: Option<&str>) -> anyhow::Result<ClientFile> {
+        let name = match profile {
+            Some(name) => name,
+            None => return Ok(self.document.client.clone()),
+        };
+        let overrides = self.tables.profiles.get(name).with_context(|| {
+            format!(
+                "no profile '{}'; available: {}",
+                name,
+                list(self.document.profiles.keys())
+            )
+        })?;
+        let mut table = self.tables.client.clone();
+        table.extend(overrides.clone());
+        Ok(table.try_into()?)
+    }
+
+    pub fn server(&self) -> &ServerFile {
+        &self.document.server
+    }
+
+    /// The targets listed under `name`, if it names a list
+    pub fn targets(&self, name: &str) -> Option<&[String]> {
+        self.document.targets.get(name).map(Vec::as_slice)
+    }
+}
+
+impl ClientFile {
+    fn validate(&self) -> anyhow::Result<()> {
+        positive("chunk-size", self.chunk_size)?;
+        positive("length-secs", self.length_secs)?;
+        positive("chunks", self.chunks)?;
+        positive("streams", self.streams)?;
+        positive("max-samples", self.max_samples)?;
+        if self.streams.unwrap_or(1) > 1 {
+            anyhow::ensure!(
+                self.transport == Some(Transport::Quic),
+                "streams > 1 requires transport = \"quic\""
+            );
+        }
+        together("tls-cert", &self.tls_cert, "tls-key", &self.tls_key)?;
+        exclusive("bytes", &self.bytes, "chunks", &self.chunks)?;
+        exclusive(
+            "auth-token",
+            &self.auth_token,
+            "auth-token-file",
+            &self.auth_token_file,
+        )?;
+        metrics(&self.influx_token, &self.influx_http)
+    }
+}
+
+impl ServerFile {
+    fn validate(&self) -> anyhow::Result<()> {
+        positive("max-samples", self.max_samples)?;
+        positive("max-sessions", self.max_sessions)?;
+        positive("max-sessions-per-client", self.max_sessions_per_client)?;
+        positive("max-duration-secs", self.max_duration_secs)?;
+        positive("max-chunk-size", self.max_chunk_size)?;
+        positive("max-streams", self.max_streams)?;
+        if let Some(bandwidth) = self.max_bandwidth {
+            anyhow::ensure!(bandwidth > 0.0, "max-bandwidth must be positive");
+        }
+        together("tls-cert", &self.tls_cert, "tls-key", &self.tls_key)?;
+        if self.tls_client_ca.is_some() {
+            anyhow::ensure!(self.tls_cert.is_some(), "tls-client-ca requires tls-cert");
+        }
+        if self.quic == Some(true) {
+            anyhow::ensure!(self.tls_cert.is_some(), "quic requires tls-cert");
+        }
+        exclusive(
+            "auth-token",
+            &self.auth_token,
+            "auth-token-file",
+            &self.auth_token_file,
+        )?;
+        metrics(&self.influx_token, &self.influx_http)
+    }
+}
+
+/// Applies values from a configuration file to options
+/// that weren't given on the command line or in the environment
+pub struct Layer<'a> {
+    matches: &'a ArgMatches,
+}
+
+impl<'a> Layer<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Self {
+        Self { matches }
+    }
+
+    /// Whether the option with id `id` was given on
+    /// the command line or in the environment
+    pub fn is_explicit(&self, id: &str) -> bool {
+        debug_assert!(self.matches.try_contains_id(id).is_ok(), "no option {}", id);
+        matches!(
+            self.matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    }
+
+    /// Set `field`, the option with id `id`, to `value` from the file,
+    /// unless it was given explicitly
+    pub fn set<T>(&self, id: &str, field: &mut T, value: Option<T>) {
+        if let (false, Some(value)) = (self.is_explicit(id), value) {
+            *field = value;
+        }
+    }
+
+    /// Like `set`, for options without a default
+    pub fn set_opt<T>(&self, id: &str, field: &mut Option<T>, value: Option<T>) {
+        self.set(id, field, value.map(Some));
+    }
+}
+
+/// Deserialize an interval like `parse_interval`: a number
+/// of milliseconds, or a string with a unit (e.g. "250us")
+fn interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let interval = match toml::Value::deserialize(deserializer)? {
+        toml::Value::Integer(ms) => parse_interval(&ms.to_string()),
+        toml::Value::String(text) => parse_interval(&text),
+        other => {
+            return Err(de::Error::custom(format!(
+                "expected milliseconds or a duration like \"250us\", got {}",
+                other.type_str()
+            )))
+        }
+    };
+    interval.map(Some).map_err(de::Error::custom)
+}
+
+/// Deserialize an amount of data like `parse_size`: a
+/// number of bytes, or a string with a unit (e.g. "10GiB")
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let size = match toml::Value::deserialize(deserializer)? {
+        toml::Value::Integer(bytes) => parse_size(&bytes.to_string()),
+        toml::Value::String(text) => parse_size(&text),
+        other => {
+            return Err(de::Error::custom(format!(
+                "expected bytes or an amount like \"10GiB\", got {}",
+                other.type_str()
+            )))
+        }
+    };
+    size.map(Some).map_err(de::Error::custom)
+}
+
+/// Deserialize a warm-up like `--warmup`: seconds,
+/// or a string with a unit (e.g. "500ms") or "auto"
+fn warmup<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Warmup>, D::Error> {
+    let warmup = match toml::Value::deserialize(deserializer)? {
+        toml::Value::Integer(secs) => secs.to_string().parse(),
+        toml::Value::Float(secs) => secs.to_string().parse(),
+        toml::Value::String(text) => text.parse(),
+        other => {
+            return Err(de::Error::custom(format!(
+                "expected seconds, a duration like \"500ms\" or \"auto\", got {}",
+                other.type_str()
+            )))
+        }
+    };
+    warmup.map(Some).map_err(de::Error::custom)
+}
+
+/// Tags from a `tag` table, as `--tag` would give them
+pub fn tags(tag: &Option<BTreeMap<String, String>>) -> Option<Vec<(String, String)>> {
+    tag.clone().map(|tags| tags.into_iter().collect())
+}
+
+fn metrics(influx_token: &Option<String>, influx_http: &Option<String>) -> anyhow::Result<()> {
+    if influx_token.is_some() {
+        anyhow::ensure!(influx_http.is_some(), "influx-token requires influx-http");
+    }
+    Ok(())
+}
+
+fn positive<T: Default + PartialEq>(name: &str, value: Option<T>) -> anyhow::Result<()> {
+    anyhow::ensure!(value != Some(T::default()), "{} must be positive", name);
+    Ok(())
+}
+
+fn together<A, B>(
+    a: &str,
+    a_value: &Option<A>,
+    b: &str,
+    b_value: &Option<B>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        a_value.is_some() == b_value.is_some(),
+        "{} and {} must be given together",
+        a,
+        b
+    );
+    Ok(())
+}
+
+fn exclusive<A, B>(
+    a: &str,
+    a_value: &Option<A>,
+    b: &str,
+    b_value: &Option<B>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        a_value.is_none() || b_value.is_none(),
+        "{} and {} can't both be given",
+        a,
+        b
+    );
+    Ok(())
+}
+
+fn list(names: impl Iterator<Item = impl Display>) -> String {
+    let names: Vec<_> = names.map(|name| name.to_string()).collect();
+    if names.is_empty() {
+        "none".into()
+    } else {
+        names.join(", ")
+    }
+}
@@ -0,0 +1,148 @@
+//! Control channel protocol.
+//!
+//! Before opening a data connection, the client connects to the
+//! server's control port and asks for a session. Messages are
+//! newline-delimited JSON. The data connection then starts with
+//! the 16-byte session id, so that the server can associate it
+//! with the session (and its trace context). With QUIC, each
+//! stream of the data connection starts with the session id.
+//!
+//! If the server requires authentication, it answers the hello with
+//! a challenge, which the client must answer before getting a session.
+//!
+//! Once the client has sent all of its data, it sends an end-of-test
+//! marker with the number of bytes it sent. A data stream that ends
+//! without the marker, or short of that count, was truncated.
+
+use std::collections::HashMap;
+
+use opentelemetry::global;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+/// Messages sent from client to server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Request a new session
+    Hello {
+        /// W3C trace context of the client's current span
+        trace_context: HashMap<String, String>,
+        /// Bytes per chunk
+        chunk_size: usize,
+        /// Planned length of the test; zero when sending `total_bytes`
+        duration_ms: u64,
+        /// Number of concurrent data streams
+        streams: usize,
+        /// Fixed amount of data to be sent over all streams, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        total_bytes: Option<u64>,
+    },
+    /// Answer to the server's challenge
+    Auth {
+        /// Hex-encoded HMAC-SHA256 of the nonce, keyed with the token
+        mac: String,
+    },
+    /// End of test: all data has been sent
+    Done {
+        /// Bytes sent over all of the session's streams
+        sent_bytes: u64,
+    },
+}
+
+/// Messages sent from server to client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The session was created; data connections
+    /// should start with this id.
+    Session {
+        session_id: Uuid,
+        /// Whether test data is echoed back; older servers always echo
+        #[serde(default = "echoes")]
+        echo: bool,
+    },
+    /// Prove knowledge of the pre-shared token
+    Challenge { nonce: String },
+    /// The session request was refused
+    Rejected { reason: String },
+    /// The server is at capacity; try again later
+    Busy { reason: String },
+}
+
+fn echoes() -> bool {
+    true
+}
+
+/// Newline-delimited JSON messages over a stream
+pub struct ControlChannel<S> {
+    stream: BufStream<S>,
+    line: String,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ControlChannel<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream: BufStream::new(stream),
+            line: String::new(),
+        }
+    }
+
+    pub async fn send<M: Serialize>(&mut self, msg: &M) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(msg)?;
+        line.push('\n');
+        self.stream.write_all(line.as_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Receive the next message, or `None` if the peer hung up
+    pub async fn recv<M: DeserializeOwned>(&mut self) -> anyhow::Result<Option<M>> {
+        self.line.clear();
+        let nbytes = self.stream.read_line(&mut self.line).await?;
+        if nbytes == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&self.line)?))
+    }
+
+    /// Receive the next message, treating a hang-up as an error
+    pub async fn expect<M: DeserializeOwned>(&mut self) -> anyhow::Result<M> {
+        self.recv()
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("control connection closed unexpectedly"))
+    }
+}
+
+/// Write the session id at the start of a data connection
+pub async fn write_session_id<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    session_id: Uuid,
+) -> std::io::Result<()> {
+    writer.write_all(session_id.as_bytes()).await?;
+    writer.flush().await
+}
+
+/// Read the session id at the start of a data connection
+pub async fn read_session_id<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Uuid> {
+    let mut bytes = [0; 16];
+    reader.read_exact(&mut bytes).await?;
+    Ok(Uuid::from_bytes(bytes))
+}
+
+/// Serialize the trace context of the current span
+pub fn current_trace_context() -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut carrier));
+    carrier
+}
+
+/// Make a span a child of a remote trace context
+pub fn set_remote_parent(span: &Span, trace_context: &HashMap<String, String>) {
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(trace_context));
+    span.set_parent(context);
+}
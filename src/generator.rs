@@ -0,0 +1,246 @@
+//! Test data generation.
+//!
+//! The payload is generated once and written over and over, in batches
+//! of many chunks, so that the send path costs little more than the
+//! system calls themselves.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use core_affinity::CoreId;
+use rand::{thread_rng, RngCore};
+use tokio::{io::AsyncWriteExt, sync::oneshot, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use crate::stream::StreamWriteHalf;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring;
+#[cfg(target_os = "linux")]
+use crate::zero_copy::{Socket, ZeroCopySender};
+
+/// Upper bound on the bytes in one batch; a batch
+/// holds as many whole chunks as fit (at least one)
+const BATCH_SIZE: usize = 256 * 1024;
+
+/// Generate data and send it over the wire
+pub struct Generator {
+    length: Duration,
+    write_half: StreamWriteHalf,
+    chunk_size: usize,
+    /// Random whole chunks, generated once and sent repeatedly
+    payload: Vec<u8>,
+    /// Counter for bytes sent
+    sent: Arc<AtomicU64>,
+    /// Send exactly this many bytes, rather than for `length`
+    total_bytes: Option<u64>,
+    /// Stop early, as if the length had run out
+    cancel: CancellationToken,
+    /// Send with `sendfile` instead of through `write_half`
+    #[cfg(target_os = "linux")]
+    zero_copy: Option<ZeroCopySender>,
+    /// Send with io_uring from this socket instead
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    io_uring: Option<std::os::fd::OwnedFd>,
+}
+
+impl Generator {
+    pub fn new(
+        length: Duration,
+        write_half: StreamWriteHalf,
+        chunk_size: usize,
+        sent: Arc<AtomicU64>,
+    ) -> Self {
+        let chunks = (BATCH_SIZE / chunk_size).max(1);
+        let mut payload = vec![0; chunks * chunk_size];
+        thread_rng().fill_bytes(&mut payload);
+
+        Self {
+            length,
+            write_half,
+            chunk_size,
+            payload,
+            sent,
+            total_bytes: None,
+            cancel: CancellationToken::new(),
+            #[cfg(target_os = "linux")]
+            zero_copy: None,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            io_uring: None,
+        }
+    }
+
+    /// Send exactly `bytes`, rounded up to whole chunks, however
+    /// long that takes, rather than for the length
+    pub fn with_total_bytes(mut self, bytes: u64) -> Self {
+        let chunk_size = self.chunk_size as u64;
+        self.total_bytes = Some(bytes.div_ceil(chunk_size) * chunk_size);
+        self
+    }
+
+    /// Stop early once `cancel` is cancelled, still finishing
+    /// the chunk in progress
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Send from `socket`, the one underneath the write half,
+    /// with `sendfile`
+    #[cfg(target_os = "linux")]
+    pub fn with_zero_copy(mut self, socket: Socket) -> io::Result<Self> {
+        self.zero_copy = Some(ZeroCopySender::new(socket, &self.payload)?);
+        Ok(self)
+    }
+
+    /// Send with io_uring from `socket`, the one underneath the write half
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn with_io_uring(mut self, socket: std::os::fd::OwnedFd) -> Self {
+        self.io_uring = Some(socket);
+        self
+    }
+
+    /// Run on a thread of its own, pinned to a CPU core
+    pub fn spawn_pinned(self, core: usize) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+        let (done_send, done) = oneshot::channel();
+        std::thread::Builder::new()
+            .name(format!("seismic-send-{}", core))
+            .spawn(move || {
+                if !core_affinity::set_for_current(CoreId { id: core }) {
+                    warn!("failed to pin sender thread to core {}", core);
+                }
+                let result = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|runtime| runtime.block_on(self.run()));
+                done_send.send(result).ok();
+            })?;
+        Ok(tokio::spawn(async move { done.await? }))
+    }
+
+    #[instrument(name = "Generator::run", skip(self))]
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        // A fixed amount of data takes as long as it takes
+        let deadline = match self.total_bytes {
+            Some(_) => None,
+            None => Some(Instant::now() + self.length),
+        };
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(socket) = self.io_uring.take() {
+            let payload = std::mem::take(&mut self.payload);
+            let (chunk_size, total_bytes, sent, cancel) = (
+                self.chunk_size,
+                self.total_bytes,
+                self.sent.clone(),
+                self.cancel.clone(),
+            );
+            uring::run_blocking(move || {
+                uring::send(
+                    socket,
+                    payload,
+                    chunk_size,
+                    deadline,
+                    total_bytes,
+                    &cancel,
+                    sent,
+                )
+            })
+            .await?;
+            self.write_half.shutdown().await?;
+            info!("End Generator::run");
+            return Ok(());
+        }
+
+        let mut remaining = self.total_bytes;
+        let mut stopping = false;
+        while !stopping && remaining != Some(0) && !self.is_over(deadline) {
+            let mut written = 0;
+            let mut end = batch_end(self.payload.len(), remaining);
+            while written < end {
+                let nbytes = self.write(written, end).await?;
+                if nbytes == 0 {
+                    return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+                }
+                written += nbytes;
+                // One update per write, however many chunks it held
+                self.sent.fetch_add(nbytes as u64, Ordering::Relaxed);
+
+                if !stopping && self.is_over(deadline) {
+                    // Finish the chunk in progress, so none is cut short
+                    stopping = true;
+                    end = written.div_ceil(self.chunk_size) * self.chunk_size;
+                }
+            }
+            if let Some(remaining) = &mut remaining {
+                *remaining -= written as u64;
+            }
+        }
+
+        // Let the receiver know we're done
+        self.write_half.flush().await?;
+        self.write_half.shutdown().await?;
+
+        info!("End Generator::run");
+        Ok(())
+    }
+
+    /// Whether it's time to stop sending
+    fn is_over(&self, deadline: Option<Instant>) -> bool {
+        deadline.is_some_and(|deadline| Instant::now() >= deadline) || self.cancel.is_cancelled()
+    }
+
+    /// Write part of the payload, returning the bytes written
+    async fn write(&mut self, start: usize, end: usize) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        if let Some(zero_copy) = &self.zero_copy {
+            return zero_copy.send(start, end - start).await;
+        }
+        self.write_half.write(&self.payload[start..end]).await
+    }
+}
+
+/// End of the next batch of a `len`-byte payload,
+/// cut short if fewer bytes remain to be sent
+pub(crate) fn batch_end(len: usize, remaining: Option<u64>) -> usize {
+    remaining.map_or(len, |remaining| remaining.min(len as u64) as usize)
+}
+
+/// Parse an amount of data: a number of bytes, optionally with a
+/// decimal (e.g. `500MB`) or binary (e.g. `10GiB`) unit
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => anyhow::bail!("unknown unit in size '{}'", s),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid size '{}'", s))?;
+    let bytes = number * multiplier as f64;
+    anyhow::ensure!(
+        bytes >= 1.0 && bytes < u64::MAX as f64,
+        "size must be positive, got '{}'",
+        s
+    );
+    Ok(bytes.round() as u64)
+}
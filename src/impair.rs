@@ -0,0 +1,433 @@
+//! Userspace proxy injecting network impairments.
+//!
+//! Sits between the client and the server and forwards TCP (and
+//! optionally UDP, for QUIC) with added delay, jitter, a bandwidth cap,
+//! periodic stalls and, for UDP, random loss. This makes it possible to
+//! see how seismic behaves on a bad network without tc/netem privileges.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use rand::{thread_rng, Rng};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::{debug, info, instrument, warn};
+
+use crate::limits::RateLimiter;
+
+/// Bytes read from a TCP connection at once
+const TCP_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Largest UDP datagram we forward
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Reads (TCP) or datagrams (UDP) held back per direction, like a
+/// router's buffer; once it's full, a TCP sender is slowed down and
+/// UDP datagrams are dropped
+const QUEUE_LENGTH: usize = 64;
+
+/// Command-line impairment options
+#[derive(clap::Args, Debug, Clone)]
+pub struct ImpairArgs {
+    /// Delay added in each direction, in milliseconds
+    #[clap(long, default_value = "0")]
+    pub delay_ms: u64,
+    /// Random variation of the delay (+/-), in milliseconds
+    #[clap(long, default_value = "0")]
+    pub jitter_ms: u64,
+    /// Cap on the bandwidth in each direction, in MB/s
+    #[clap(long)]
+    pub bandwidth: Option<f64>,
+    /// Percentage of UDP datagrams to drop
+    #[clap(long, default_value = "0")]
+    pub loss_pct: f64,
+    /// Stop forwarding periodically, this many milliseconds apart
+    #[clap(long, requires = "stall-ms")]
+    pub stall_every_ms: Option<u64>,
+    /// Length of each stall, in milliseconds
+    #[clap(long, requires = "stall-every-ms")]
+    pub stall_ms: Option<u64>,
+}
+
+impl From<ImpairArgs> for Impairment {
+    fn from(args: ImpairArgs) -> Self {
+        Self {
+            delay: Duration::from_millis(args.delay_ms),
+            jitter: Duration::from_millis(args.jitter_ms),
+            bandwidth: args.bandwidth,
+            loss_pct: args.loss_pct,
+            stall: args
+                .stall_every_ms
+                .zip(args.stall_ms)
+                .map(|(every, length)| Stall {
+                    every: Duration::from_millis(every),
+                    length: Duration::from_millis(length),
+                }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Impairment {
+    /// Added in each direction
+    pub delay: Duration,
+    /// Maximum random variation of the delay
+    pub jitter: Duration,
+    /// In MB/s, per direction of each connection (or UDP client)
+    pub bandwidth: Option<f64>,
+    /// Percentage of UDP datagrams dropped
+    pub loss_pct: f64,
+    pub stall: Option<Stall>,
+}
+
+/// Forwarding stops for `length` at the start of every `every`
+#[derive(Debug, Clone, Copy)]
+pub struct Stall {
+    pub every: Duration,
+    pub length: Duration,
+}
+
+impl Impairment {
+    /// Delay for a single packet or read, including jitter
+    fn sample_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.delay;
+        }
+        let jitter = self.jitter.as_secs_f64();
+        let offset = thread_rng().gen_range(-jitter..=jitter);
+        Duration::from_secs_f64((self.delay.as_secs_f64() + offset).max(0.0))
+    }
+
+    fn drops(&self) -> bool {
+        self.loss_pct > 0.0 && thread_rng().gen_bool((self.loss_pct / 100.0).min(1.0))
+    }
+
+    fn rate_limiter(&self) -> Option<RateLimiter> {
+        self.bandwidth
+            .map(|mb_per_sec| RateLimiter::new(mb_per_sec * 1e6))
+    }
+
+    /// When forwarding may resume, if it's stalled at `now`
+    fn stalled_until(&self, start: Instant, now: Instant) -> Option<Instant> {
+        let stall = self.stall?;
+        if stall.every.is_zero() {
+            return None;
+        }
+        let elapsed = now.duration_since(start).as_nanos();
+        let into_period = Duration::from_nanos((elapsed % stall.every.as_nanos()) as u64);
+        (into_period < stall.length).then(|| now + (stall.length - into_period))
+    }
+
+    /// Wait until `at`, and then for any stall in progress
+    async fn release_at(&self, start: Instant, at: Instant) {
+        tokio::time::sleep_until(at).await;
+        if let Some(until) = self.stalled_until(start, Instant::now()) {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+}
+
+/// Forward TCP connections accepted on `listener` to `upstream`
+#[instrument(skip(listener, impairment))]
+pub async fn proxy_tcp(
+    listener: TcpListener,
+    upstream: String,
+    impairment: Impairment,
+) -> anyhow::Result<()> {
+    info!(
+        "Forwarding TCP from {} to {} with {:?}",
+        listener.local_addr()?,
+        upstream,
+        impairment
+    );
+    let impairment = Arc::new(impairment);
+    let start = Instant::now();
+
+    loop {
+        let (client, addr) = listener.accept().await?;
+        let upstream = upstream.clone();
+        let impairment = impairment.clone();
+        tokio::spawn(async move {
+            info!("Forwarding TCP connection from {}", addr);
+            if let Err(err) = forward_tcp(client, &upstream, impairment, start).await {
+                warn!("TCP connection from {} failed: {}", addr, err);
+            }
+        });
+    }
+}
+
+async fn forward_tcp(
+    client: TcpStream,
+    upstream: &str,
+    impairment: Arc<Impairment>,
+    start: Instant,
+) -> anyhow::Result<()> {
+    let server = TcpStream::connect(upstream).await?;
+    client.set_nodelay(true)?;
+    server.set_nodelay(true)?;
+
+    let (client_read, client_write) = client.into_split();
+    let (server_read, server_write) = server.into_split();
+    let (up, down) = tokio::join!(
+        pump(client_read, server_write, impairment.clone(), start),
+        pump(server_read, client_write, impairment, start),
+    );
+    up.and(down)
+}
+
+/// Copy one direction of a TCP connection, holding each read back
+/// until it's due. Reads stay in order, so jitter never reorders data.
+async fn pump<R, W>(
+    mut reader: R,
+    mut writer: W,
+    impairment: Arc<Impairment>,
+    start: Instant,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let (tx, mut rx) = mpsc::channel::<(Instant, Vec<u8>)>(QUEUE_LENGTH);
+    let read_impairment = impairment.clone();
+    let read = tokio::spawn(async move {
+        let mut due = Instant::now();
+        loop {
+            let mut buf = vec![0; TCP_BUFFER_SIZE];
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok::<_, std::io::Error>(());
+            }
+            buf.truncate(n);
+            due = due.max(Instant::now() + read_impairment.sample_delay());
+            if tx.send((due, buf)).await.is_err() {
+                return Ok(());
+            }
+        }
+    });
+
+    let rate_limit = impairment.rate_limiter();
+    while let Some((due, buf)) = rx.recv().await {
+        impairment.release_at(start, due).await;
+        if let Some(rate_limit) = &rate_limit {
+            rate_limit.acquire(buf.len()).await;
+        }
+        writer.write_all(&buf).await?;
+    }
+    writer.shutdown().await?;
+
+    read.await??;
+    Ok(())
+}
+
+/// Forward UDP datagrams received on `socket` to `upstream`,
+/// and the replies back to each client. Clients are forgotten
+/// once no datagrams have passed for `idle_timeout` either way.
+#[instrument(skip(socket, impairment))]
+pub async fn proxy_udp(
+    socket: UdpSocket,
+    upstream: SocketAddr,
+    impairment: Impairment,
+    idle_timeout: Duration,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !idle_timeout.is_zero(),
+        "the UDP idle timeout must be positive"
+    );
+    info!(
+        "Forwarding UDP from {} to {} with {:?}",
+        socket.local_addr()?,
+        upstream,
+        impairment
+    );
+    let socket = Arc::new(socket);
+    let impairment = Arc::new(impairment);
+    let start = Instant::now();
+
+    // One upstream socket per client, so replies can be told apart
+    let mut clients: HashMap<SocketAddr, UdpClient> = HashMap::new();
+    let mut sweep = tokio::time::interval(idle_timeout / 2);
+    sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut buf = vec![0; UDP_BUFFER_SIZE];
+    loop {
+        let (n, client) = tokio::select! {
+            received = socket.recv_from(&mut buf) => received?,
+            _ = sweep.tick() => {
+                let now = Instant::now();
+                clients.retain(|client, entry| {
+                    let idle = now.duration_since(*entry.last_active.lock().expect("activity mutex poisoned"));
+                    if idle >= idle_timeout {
+                        debug!("Forgetting UDP client {} after {:?} idle", client, idle);
+                    }
+                    idle < idle_timeout
+                });
+                continue;
+            }
+        };
+
+        let (server, upstream_queue) = match clients.get(&client) {
+            Some(entry) => {
+                *entry.last_active.lock().expect("activity mutex poisoned") = Instant::now();
+                (entry.server.clone(), entry.upstream.clone())
+            }
+            None => {
+                let local: SocketAddr = if upstream.is_ipv6() {
+                    "[::]:0".parse()?
+                } else {
+                    "0.0.0.0:0".parse()?
+                };
+                let server = Arc::new(UdpSocket::bind(local).await?);
+                server.connect(upstream).await?;
+                let last_active = Arc::new(Mutex::new(Instant::now()));
+                let upstream = Arc::new(UdpQueue::new(&impairment));
+                let replies = tokio::spawn(udp_replies(
+                    server.clone(),
+                    socket.clone(),
+                    client,
+                    last_active.clone(),
+                    impairment.clone(),
+                    Arc::new(UdpQueue::new(&impairment)),
+                    start,
+                ));
+                clients.insert(
+                    client,
+                    UdpClient {
+                        server: server.clone(),
+                        last_active,
+                        upstream: upstream.clone(),
+                        replies,
+                    },
+                );
+                (server, upstream)
+            }
+        };
+
+        let datagram = buf[..n].to_vec();
+        let impairment = impairment.clone();
+        send_impaired(impairment, upstream_queue, start, n, async move {
+            if let Err(err) = server.send(&datagram).await {
+                debug!("failed to forward datagram: {}", err);
+            }
+        });
+    }
+}
+
+/// A client of the UDP proxy, with its own socket to the server
+struct UdpClient {
+    server: Arc<UdpSocket>,
+    /// When the last datagram passed, either way
+    last_active: Arc<Mutex<Instant>>,
+    /// Datagrams on their way to the server
+    upstream: Arc<UdpQueue>,
+    /// Forwards the server's replies to the client
+    replies: JoinHandle<()>,
+}
+
+impl Drop for UdpClient {
+    fn drop(&mut self) {
+        self.replies.abort();
+    }
+}
+
+/// Forward replies from the server back to one client
+async fn udp_replies(
+    server: Arc<UdpSocket>,
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    last_active: Arc<Mutex<Instant>>,
+    impairment: Arc<Impairment>,
+    downstream: Arc<UdpQueue>,
+    start: Instant,
+) {
+    let mut buf = vec![0; UDP_BUFFER_SIZE];
+    loop {
+        let n = match server.recv(&mut buf).await {
+            Ok(n) => n,
+            Err(err) => {
+                debug!("failed to receive reply for {}: {}", client, err);
+                return;
+            }
+        };
+        *last_active.lock().expect("activity mutex poisoned") = Instant::now();
+        let datagram = buf[..n].to_vec();
+        let socket = socket.clone();
+        send_impaired(
+            impairment.clone(),
+            downstream.clone(),
+            start,
+            n,
+            async move {
+                if let Err(err) = socket.send_to(&datagram, client).await {
+                    debug!("failed to forward reply to {}: {}", client, err);
+                }
+            },
+        );
+    }
+}
+
+/// One direction of a UDP client's traffic, queueing
+/// for its share of the bandwidth
+struct UdpQueue {
+    rate_limit: Option<RateLimiter>,
+    /// Datagrams waiting for bandwidth
+    waiting: AtomicUsize,
+}
+
+impl UdpQueue {
+    fn new(impairment: &Impairment) -> Self {
+        Self {
+            rate_limit: impairment.rate_limiter(),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for bandwidth to send `len` bytes, or return
+    /// false if the queue is full and the datagram is dropped
+    async fn acquire(&self, len: usize) -> bool {
+        let rate_limit = match &self.rate_limit {
+            Some(rate_limit) => rate_limit,
+            None => return true,
+        };
+        if self.waiting.fetch_add(1, Ordering::Relaxed) >= QUEUE_LENGTH {
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        rate_limit.acquire(len).await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Send a datagram after its delay, unless it's lost or its
+/// queue is full. Datagrams are independent, so jitter may
+/// reorder them.
+fn send_impaired(
+    impairment: Arc<Impairment>,
+    queue: Arc<UdpQueue>,
+    start: Instant,
+    len: usize,
+    send: impl Future<Output = ()> + Send + 'static,
+) {
+    if impairment.drops() {
+        return;
+    }
+    let due = Instant::now() + impairment.sample_delay();
+    tokio::spawn(async move {
+        impairment.release_at(start, due).await;
+        if queue.acquire(len).await {
+            send.await;
+        }
+    });
+}
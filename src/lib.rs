@@ -1,12 +1,36 @@
+pub mod access;
+pub mod api;
+pub mod compare;
+pub mod config;
+pub mod control;
+pub mod generator;
+pub mod impair;
+pub mod limits;
 pub mod measurement;
 pub mod measurer;
+pub mod metrics;
+pub mod quic;
 pub mod reader;
 pub mod receiver;
 pub mod sender;
+pub mod server;
+pub mod sink;
+pub mod slo;
+pub mod stats;
+pub mod store;
+pub mod stream;
+pub mod tls;
 pub mod tracing;
+pub mod transport;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+#[cfg(target_os = "linux")]
+pub mod zero_copy;
 
 use std::net::IpAddr;
 
+pub use api::Seismic;
+
 use uuid::Uuid;
 
 pub struct Node {
@@ -18,3 +42,11 @@ pub struct Node {
 pub struct Network {
     pub nodes: Vec<Node>,
 }
+
+/// Parse a `key=value` command-line option
+pub(crate) fn parse_key_value(s: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected key=value, got '{}'", s))?;
+    Ok((key.to_string(), value.to_string()))
+}
@@ -0,0 +1,179 @@
+//! Server-side admission control and resource limits.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Command-line resource limits for the server
+#[derive(clap::Args, Debug, Clone)]
+pub struct LimitsArgs {
+    /// Maximum number of concurrent sessions
+    #[clap(long, env = "SEISMIC_MAX_SESSIONS")]
+    pub max_sessions: Option<usize>,
+    /// Maximum number of concurrent sessions per client address
+    #[clap(long, env = "SEISMIC_MAX_SESSIONS_PER_CLIENT")]
+    pub max_sessions_per_client: Option<usize>,
+    /// Maximum test duration, in seconds
+    #[clap(long, env = "SEISMIC_MAX_DURATION_SECS")]
+    pub max_duration_secs: Option<u64>,
+    /// Maximum total bandwidth of incoming test data, in MB/s
+    #[clap(long, env = "SEISMIC_MAX_BANDWIDTH")]
+    pub max_bandwidth: Option<f64>,
+    /// Maximum bytes per chunk
+    #[clap(long, default_value = "1048576", env = "SEISMIC_MAX_CHUNK_SIZE")]
+    pub max_chunk_size: usize,
+    /// Maximum number of concurrent streams per session (QUIC)
+    #[clap(long, default_value = "16", env = "SEISMIC_MAX_STREAMS")]
+    pub max_streams: usize,
+}
+
+impl From<LimitsArgs> for Limits {
+    fn from(args: LimitsArgs) -> Self {
+        Self {
+            max_sessions: args.max_sessions,
+            max_sessions_per_client: args.max_sessions_per_client,
+            max_duration: args.max_duration_secs.map(Duration::from_secs),
+            max_bandwidth: args.max_bandwidth,
+            max_chunk_size: Some(args.max_chunk_size),
+            max_streams: Some(args.max_streams),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub max_sessions: Option<usize>,
+    pub max_sessions_per_client: Option<usize>,
+    pub max_duration: Option<Duration>,
+    /// Shared by all sessions, in MB/s
+    pub max_bandwidth: Option<f64>,
+    pub max_chunk_size: Option<usize>,
+    pub max_streams: Option<usize>,
+}
+
+impl Limits {
+    /// Check the parameters of a requested test,
+    /// returning the reason if they're not allowed
+    pub fn check_request(
+        &self,
+        chunk_size: usize,
+        duration: Duration,
+        streams: usize,
+        total_bytes: Option<u64>,
+    ) -> Result<(), String> {
+        if chunk_size == 0 {
+            return Err("chunk size must be positive".into());
+        }
+        if streams == 0 {
+            return Err("at least one stream is required".into());
+        }
+        if let Some(max) = self.max_streams {
+            if streams > max {
+                return Err(format!(
+                    "{} streams exceeds the maximum of {}",
+                    streams, max
+                ));
+            }
+        }
+        if let Some(max) = self.max_chunk_size {
+            if chunk_size > max {
+                return Err(format!(
+                    "chunk size {} exceeds the maximum of {} bytes",
+                    chunk_size, max
+                ));
+            }
+        }
+        if let Some(max) = self.max_duration {
+            if duration > max {
+                return Err(format!(
+                    "duration {:?} exceeds the maximum of {:?}",
+                    duration, max
+                ));
+            }
+            // A fixed amount of data has no duration, but can't
+            // arrive faster than the bandwidth limit
+            if let (Some(total), Some(mb_per_sec)) = (total_bytes, self.max_bandwidth) {
+                let fastest = Duration::from_secs_f64(total as f64 / (mb_per_sec * 1e6));
+                if fastest > max {
+                    return Err(format!(
+                        "{} bytes take at least {:?} at {} MB/s, over the maximum of {:?}",
+                        total, fastest, mb_per_sec, max
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rate limiter for incoming test data, if bandwidth is limited
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        self.max_bandwidth
+            .map(|mb_per_sec| RateLimiter::new(mb_per_sec * 1e6))
+    }
+}
+
+/// Token bucket limiting the rate of bytes, shared between tasks
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    /// Bytes that may be sent in a burst
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes available; negative when callers are waiting
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: f64) -> Self {
+        // Allow bursts of up to 10 ms worth of data
+        let capacity = bytes_per_sec / 100.0;
+        Self {
+            bytes_per_sec,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                available: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until `bytes` more bytes may be transferred
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.take(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Like `acquire`, but blocking the thread, for I/O
+    /// driven outside of the runtime (e.g. by io_uring)
+    pub fn acquire_blocking(&self, bytes: usize) {
+        let wait = self.take(bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Take the bytes now, returning how long to wait
+    /// to pay off any debt
+    fn take(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock().expect("rate limiter mutex poisoned");
+        let now = Instant::now();
+        let refill = (now - bucket.last_refill).as_secs_f64() * self.bytes_per_sec;
+        bucket.available = (bucket.available + refill).min(self.capacity);
+        bucket.last_refill = now;
+
+        bucket.available -= bytes as f64;
+        if bucket.available < 0.0 {
+            Duration::from_secs_f64(-bucket.available / self.bytes_per_sec)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
@@ -1,11 +1,30 @@
-use std::time::{Duration, Instant, SystemTime};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
 
 use ansi_rgb::Foreground;
 use rgb::RGB8;
+use serde::{Deserialize, Serialize};
 use textplots::{Chart, ColorPlot, Shape};
 use tracing::debug;
+use uuid::Uuid;
 
-#[derive(Debug)]
+use crate::stats;
+
+/// Fraction of the run's typical throughput at which
+/// it's considered to have reached steady state
+const STEADY_FRACTION: f64 = 0.9;
+
+/// Consecutive intervals that must reach steady
+/// throughput before the run is considered steady
+const STEADY_INTERVALS: usize = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Measurement {
     /// Time offset start beginning of measurement set
     pub dt: Duration,
@@ -13,68 +32,448 @@ pub struct Measurement {
     pub sent: u64,
     /// Number of chunks received
     pub received: u64,
+    /// Exact number of bytes sent, including partial chunks
+    #[serde(default)]
+    pub sent_bytes: u64,
+    /// Exact number of bytes received, including partial chunks
+    #[serde(default)]
+    pub received_bytes: u64,
+    /// Statistics reported by the transport (e.g. QUIC)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<TransportStats>,
+}
+
+/// Connection statistics from the transport itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportStats {
+    /// Smoothed round-trip time estimate
+    pub rtt: Duration,
+    /// Congestion window, in bytes
+    pub congestion_window: u64,
+    /// Packets sent so far
+    pub sent_packets: u64,
+    /// Packets declared lost so far
+    pub lost_packets: u64,
 }
 
 impl Measurement {
-    pub fn new(start: Instant, sent: u64, received: u64) -> Self {
+    /// Measure byte counters, also counting the whole chunks among them
+    pub fn new(start: Instant, chunk_size: usize, sent_bytes: u64, received_bytes: u64) -> Self {
         let now = Instant::now();
         let dt = now - start;
-        let measurement = Self { dt, sent, received };
+        let chunk_size = chunk_size.max(1) as u64;
+        let measurement = Self {
+            dt,
+            sent: sent_bytes / chunk_size,
+            received: received_bytes / chunk_size,
+            sent_bytes,
+            received_bytes,
+            transport: None,
+        };
         debug!("{:?}", measurement);
         measurement
     }
 
     pub fn print(&self) {
-        println!(
+        print!(
             "{:.2}s: {:10} sent / {:10} received",
             self.dt.as_secs_f32(),
             self.sent,
             self.received
         );
+        match &self.transport {
+            Some(stats) => println!(
+                " (rtt {:.2?}, {} / {} packets lost)",
+                stats.rtt, stats.lost_packets, stats.sent_packets
+            ),
+            None => println!(),
+        }
+    }
+}
+
+/// How much of the start of a run to leave out of its summary statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Warmup {
+    /// Summarize the whole run
+    #[default]
+    Off,
+    /// Leave out this long
+    Fixed(Duration),
+    /// Leave out the slow start, until throughput is steady
+    Auto,
+}
+
+impl FromStr for Warmup {
+    type Err = anyhow::Error;
+
+    /// `auto`, or seconds (e.g. `1.5`) or a duration with a unit (e.g. `500ms`)
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "auto" {
+            return Ok(Warmup::Auto);
+        }
+        let warmup = match s.parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs >= 0.0 => Duration::from_secs_f64(secs),
+            Ok(_) => anyhow::bail!("invalid warm-up: {}", s),
+            Err(_) => humantime::parse_duration(s)?,
+        };
+        Ok(if warmup.is_zero() {
+            Warmup::Off
+        } else {
+            Warmup::Fixed(warmup)
+        })
     }
 }
 
+/// The start of a run, until throughput is steady: TCP slow start,
+/// and the buffers along the path filling up
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SlowStart {
+    /// From the start of the run until steady state
+    pub duration: Duration,
+    /// Bytes received in that time
+    pub received_bytes: u64,
+    /// Mean received throughput in that time (bytes / second)
+    pub throughput: f64,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct MeasurementSet {
+    /// Unique identifier for this run
+    pub id: Uuid,
+    #[serde(skip, default = "Instant::now")]
     start: Instant,
-    start_time: SystemTime,
+    pub start_time: SystemTime,
+    /// Bytes per chunk
+    pub chunk_size: usize,
+    /// Address of the other end of the connection
+    #[serde(default)]
+    pub peer: Option<String>,
+    /// Free-form run metadata (e.g. role, hostname)
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
     pub measurements: Vec<Measurement>,
     /// Whether to print new measurements
     /// as they're recorded
+    #[serde(skip)]
     print_live: bool,
+    /// Keep only this many of the latest measurements
+    #[serde(skip)]
+    max_samples: Option<usize>,
+    /// Index of the oldest measurement, once
+    /// older ones are being overwritten
+    #[serde(skip)]
+    oldest: usize,
+    /// Measurements overwritten so far
+    #[serde(skip)]
+    dropped: u64,
 }
 
 impl Default for MeasurementSet {
+    /// An empty set of 1 KiB chunks, as sent by default
     fn default() -> Self {
         let print_live = false;
-        Self::new(print_live)
+        Self::new(1024, print_live)
     }
 }
 
 impl MeasurementSet {
-    pub fn new(print_live: bool) -> Self {
+    pub fn new(chunk_size: usize, print_live: bool) -> Self {
         Self {
+            id: Uuid::new_v4(),
             start: Instant::now(),
             start_time: SystemTime::now(),
+            chunk_size,
+            peer: None,
+            metadata: BTreeMap::new(),
             measurements: Vec::new(),
             print_live,
+            max_samples: None,
+            oldest: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Keep only the latest `max_samples` measurements, overwriting the
+    /// oldest ones in place, so that long runs use bounded memory
+    pub fn with_max_samples(mut self, max_samples: usize) -> Self {
+        self.max_samples = Some(max_samples.max(1));
+        self
+    }
+
+    /// Write the measurements to a JSON file
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Read measurements previously written by [`MeasurementSet::save`]
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut mset: Self = serde_json::from_reader(reader)?;
+        // Older files only counted whole chunks
+        let chunk_size = mset.chunk_size as u64;
+        for m in &mut mset.measurements {
+            if m.sent_bytes == 0 && m.received_bytes == 0 {
+                m.sent_bytes = m.sent * chunk_size;
+                m.received_bytes = m.received * chunk_size;
+            }
         }
+        Ok(mset)
     }
 
+    /// Record whole-chunk counters
     pub fn record(&mut self, sent: u64, received: u64) {
-        let measurement = Measurement::new(self.start, sent, received);
+        self.record_with_stats(sent, received, None);
+    }
+
+    /// Record whole-chunk counters along with transport statistics
+    pub fn record_with_stats(
+        &mut self,
+        sent: u64,
+        received: u64,
+        transport: Option<TransportStats>,
+    ) {
+        let chunk_size = self.chunk_size as u64;
+        self.record_bytes(sent * chunk_size, received * chunk_size, transport);
+    }
+
+    /// Record exact byte counters, which may include partial chunks,
+    /// returning the new measurement
+    pub fn record_bytes(
+        &mut self,
+        sent_bytes: u64,
+        received_bytes: u64,
+        transport: Option<TransportStats>,
+    ) -> &Measurement {
+        let mut measurement =
+            Measurement::new(self.start, self.chunk_size, sent_bytes, received_bytes);
+        measurement.transport = transport;
         if self.print_live {
             measurement.print();
         }
-        self.measurements.push(measurement);
+        match self.max_samples {
+            Some(max) if self.measurements.len() >= max => {
+                // Overwrite the oldest measurement; `finish` puts
+                // them back in order
+                let index = self.oldest;
+                self.measurements[index] = measurement;
+                self.oldest = (index + 1) % self.measurements.len();
+                self.dropped += 1;
+                &self.measurements[index]
+            }
+            _ => {
+                self.measurements.push(measurement);
+                self.measurements.last().unwrap()
+            }
+        }
+    }
+
+    /// Put the measurements back in order after recording,
+    /// noting how many were dropped to stay within `max_samples`
+    pub fn finish(&mut self) {
+        self.measurements.rotate_left(self.oldest);
+        self.oldest = 0;
+        if self.dropped > 0 {
+            self.metadata
+                .insert("samples_dropped".into(), self.dropped.to_string());
+        }
+        if let Some(slow_start) = self.slow_start() {
+            self.metadata.insert(
+                "steady_state_secs".into(),
+                format!("{:.6}", slow_start.duration.as_secs_f64()),
+            );
+        }
+    }
+
+    /// Leave the start of the run out of its summary statistics
+    /// (mean throughput, RTT and stalls). An automatic warm-up
+    /// lasts until steady state, or is off if that isn't detected.
+    pub fn set_warmup(&mut self, warmup: Warmup) {
+        let warmup = match warmup {
+            Warmup::Off => Duration::ZERO,
+            Warmup::Fixed(warmup) => warmup,
+            Warmup::Auto => self.slow_start().map_or(Duration::ZERO, |s| s.duration),
+        };
+        if warmup.is_zero() {
+            self.metadata.remove("warmup_secs");
+        } else {
+            self.metadata
+                .insert("warmup_secs".into(), format!("{:.6}", warmup.as_secs_f64()));
+        }
+    }
+
+    /// The start of the run left out of its summary statistics
+    pub fn warmup(&self) -> Duration {
+        self.metadata
+            .get("warmup_secs")
+            .and_then(|secs| secs.parse().ok())
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .unwrap_or_default()
+    }
+
+    /// Index of the measurement the summary statistics start from:
+    /// the last one within the warm-up, if there is one
+    fn summary_start(&self) -> Option<usize> {
+        let warmup = self.warmup();
+        if warmup.is_zero() {
+            return None;
+        }
+        self.measurements.iter().rposition(|m| m.dt <= warmup)
+    }
+
+    /// The slow start, until the received throughput first stays
+    /// within 10% of the run's typical (median, over its second half)
+    /// throughput for a few intervals. `None` if the run is too short
+    /// to tell, or never gets there.
+    pub fn slow_start(&self) -> Option<SlowStart> {
+        let throughput = self.throughput();
+        if throughput.len() < 2 * STEADY_INTERVALS {
+            return None;
+        }
+        let typical = stats::percentile(&throughput[throughput.len() / 2..], 50.0)?;
+        if typical <= 0.0 {
+            return None;
+        }
+        let steady = throughput
+            .windows(STEADY_INTERVALS)
+            .position(|window| window.iter().all(|&t| t >= STEADY_FRACTION * typical))?;
+        let end = &self.measurements[steady];
+        let secs = end.dt.as_secs_f64();
+        Some(SlowStart {
+            duration: end.dt,
+            received_bytes: end.received_bytes,
+            throughput: if secs > 0.0 {
+                end.received_bytes as f64 / secs
+            } else {
+                0.0
+            },
+        })
     }
 
     pub fn print(&self) {
         // TODO: Format SystemTime
         println!("Measurements @ {:?}", self.start_time);
+        if self.is_encrypted() {
+            println!("(TLS: throughput includes encryption overhead)");
+        }
+        if self.is_host_internal() {
+            println!(
+                "(Host-internal baseline over {}: no network involved)",
+                self.metadata.get("transport").map_or("-", String::as_str)
+            );
+        }
         for measurement in &self.measurements {
             measurement.print();
         }
-        println!("");
+        if self.partial_chunk() > 0 {
+            println!("(Trailing partial chunk: {} bytes)", self.partial_chunk());
+        }
+        if self.is_truncated() {
+            println!("(Truncated: the stream ended before the end-of-test marker)");
+        }
+        if self.is_incomplete() {
+            println!(
+                "(Incomplete: less than the expected {} bytes arrived)",
+                self.metadata
+                    .get("expected_bytes")
+                    .map_or("-", String::as_str)
+            );
+        }
+        if let Some(slow_start) = self.slow_start() {
+            println!(
+                "Slow start: {:.2}s, {} bytes at {:.2} MB/s, then steady",
+                slow_start.duration.as_secs_f64(),
+                slow_start.received_bytes,
+                slow_start.throughput / 1e6
+            );
+        }
+        if !self.warmup().is_zero() {
+            println!(
+                "(Summary statistics leave out the first {:.2}s as warm-up)",
+                self.warmup().as_secs_f64()
+            );
+        }
+        if let (Some(time), Some(throughput)) = (self.transfer_time(), self.effective_throughput())
+        {
+            println!(
+                "Transferred {} bytes in {:.3}s: {:.2} MB/s",
+                self.metadata
+                    .get("transfer_bytes")
+                    .map_or("-", String::as_str),
+                time.as_secs_f64(),
+                throughput / 1e6
+            );
+        }
+        println!();
+    }
+
+    /// Whether the data was sent over TLS
+    pub fn is_encrypted(&self) -> bool {
+        self.metadata.get("encryption").map(String::as_str) == Some("tls")
+    }
+
+    /// Whether the data never left the host, e.g. over a Unix socket
+    pub fn is_host_internal(&self) -> bool {
+        self.metadata.get("scope").map(String::as_str) == Some("host-internal")
+    }
+
+    /// Whether the test ended without a clean end-of-test marker
+    pub fn is_truncated(&self) -> bool {
+        self.metadata.get("completion").map(String::as_str) == Some("truncated")
+    }
+
+    /// Whether less than a fixed amount of data arrived, although
+    /// the stream ended cleanly (e.g. when the test was cancelled)
+    pub fn is_incomplete(&self) -> bool {
+        self.metadata.get("completion").map(String::as_str) == Some("incomplete")
+    }
+
+    /// Record how long it took to transfer a fixed amount of data
+    pub fn record_transfer(&mut self, bytes: u64, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        self.metadata
+            .insert("transfer_bytes".into(), bytes.to_string());
+        self.metadata
+            .insert("transfer_secs".into(), format!("{:.6}", secs));
+        if secs > 0.0 {
+            self.metadata.insert(
+                "effective_throughput".into(),
+                format!("{:.0}", bytes as f64 / secs),
+            );
+        }
+    }
+
+    /// How long the fixed amount of data took to transfer,
+    /// for tests which sent one
+    pub fn transfer_time(&self) -> Option<Duration> {
+        let secs = self.metadata.get("transfer_secs")?.parse().ok()?;
+        Duration::try_from_secs_f64(secs).ok()
+    }
+
+    /// Throughput (bytes / second) over the whole transfer
+    /// of a fixed amount of data, for tests which sent one
+    pub fn effective_throughput(&self) -> Option<f64> {
+        self.metadata.get("effective_throughput")?.parse().ok()
+    }
+
+    /// Record the bytes the readers received past their last
+    /// whole chunk, summed over the streams
+    pub fn record_partial_chunk(&mut self, bytes: u64) {
+        self.metadata
+            .insert("partial_chunk_bytes".into(), bytes.to_string());
+    }
+
+    /// Bytes received past the last whole chunk, as recorded by
+    /// the readers, or worked out from the counters for older sets
+    pub fn partial_chunk(&self) -> u64 {
+        if let Some(bytes) = self.metadata.get("partial_chunk_bytes") {
+            return bytes.parse().unwrap_or(0);
+        }
+        match self.measurements.last() {
+            Some(last) if self.chunk_size > 0 => last.received_bytes % self.chunk_size as u64,
+            _ => 0,
+        }
     }
 
     pub fn time(&self) -> Vec<f64> {
@@ -92,6 +491,131 @@ impl MeasurementSet {
         self.measurements.iter().map(|m| m.received).collect()
     }
 
+    /// Received throughput (bytes / second)
+    /// over each interval between measurements.
+    pub fn throughput(&self) -> Vec<f64> {
+        self.measurements
+            .windows(2)
+            .map(|w| {
+                let dt = (w[1].dt - w[0].dt).as_secs_f64();
+                let bytes = (w[1].received_bytes - w[0].received_bytes) as f64;
+                if dt > 0.0 {
+                    bytes / dt
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /// Round-trip time estimates (seconds), one per measurement
+    /// after the warm-up which has received at least one chunk.
+    ///
+    /// This is only meaningful when the peer echoes data back.
+    /// For each measurement, we find the earliest measurement
+    /// by which the received chunks had already been sent,
+    /// so the resolution is limited by the measurement frequency.
+    pub fn rtt(&self) -> Vec<f64> {
+        let warmup = self.warmup();
+        let mut rtts = Vec::new();
+        let mut j = 0;
+        for m in &self.measurements {
+            if m.received == 0 || m.dt < warmup {
+                continue;
+            }
+            while j < self.measurements.len() && self.measurements[j].sent < m.received {
+                j += 1;
+            }
+            match self.measurements.get(j) {
+                Some(sent_by) if sent_by.dt <= m.dt => {
+                    rtts.push((m.dt - sent_by.dt).as_secs_f64());
+                }
+                _ => break,
+            }
+        }
+        rtts
+    }
+
+    /// Fraction of sent bytes which were never received
+    /// by the end of the measurement set.
+    pub fn loss(&self) -> f64 {
+        match self.measurements.last() {
+            Some(last) if last.sent_bytes > 0 => {
+                last.sent_bytes.saturating_sub(last.received_bytes) as f64 / last.sent_bytes as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Mean received throughput (bytes / second)
+    /// over the measurement set, after the warm-up.
+    pub fn mean_throughput(&self) -> f64 {
+        let (start, received) = match self.summary_start() {
+            Some(i) => (self.measurements[i].dt, self.measurements[i].received_bytes),
+            None => (Duration::ZERO, 0),
+        };
+        match self.measurements.last() {
+            Some(last) if last.dt > start => {
+                (last.received_bytes - received) as f64 / (last.dt - start).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Longest period after the warm-up
+    /// during which no new bytes were received.
+    pub fn longest_stall(&self) -> Duration {
+        let mut longest = Duration::ZERO;
+        let start = self.summary_start().unwrap_or(0);
+        let mut last_progress = match self.measurements.get(start) {
+            Some(first) => first,
+            None => return longest,
+        };
+        for m in &self.measurements[start + 1..] {
+            if m.received_bytes > last_progress.received_bytes {
+                last_progress = m;
+            } else {
+                longest = longest.max(m.dt - last_progress.dt);
+            }
+        }
+        longest
+    }
+
+    /// Linearly interpolate the (sent, received) chunk counters
+    /// at time offset `t` (seconds), or `None` if `t`
+    /// lies outside of the measured range.
+    pub fn interpolate(&self, t: f64) -> Option<(f64, f64)> {
+        self.interpolate_with(t, |m| (m.sent, m.received))
+    }
+
+    /// Like [`interpolate`](Self::interpolate), but for the exact
+    /// (sent, received) byte counters.
+    pub fn interpolate_bytes(&self, t: f64) -> Option<(f64, f64)> {
+        self.interpolate_with(t, |m| (m.sent_bytes, m.received_bytes))
+    }
+
+    fn interpolate_with(
+        &self,
+        t: f64,
+        counters: impl Fn(&Measurement) -> (u64, u64),
+    ) -> Option<(f64, f64)> {
+        let i = self
+            .measurements
+            .iter()
+            .position(|m| m.dt.as_secs_f64() >= t)?;
+        let hi = &self.measurements[i];
+        if i == 0 {
+            let (sent, received) = counters(hi);
+            return (hi.dt.as_secs_f64() == t).then_some((sent as f64, received as f64));
+        }
+        let lo = &self.measurements[i - 1];
+        let (t0, t1) = (lo.dt.as_secs_f64(), hi.dt.as_secs_f64());
+        let frac = (t - t0) / (t1 - t0);
+        let lerp = |a: u64, b: u64| a as f64 + frac * (b as f64 - a as f64);
+        let ((sent0, received0), (sent1, received1)) = (counters(lo), counters(hi));
+        Some((lerp(sent0, sent1), lerp(received0, received1)))
+    }
+
     pub fn plot(&self) {
         let t: Vec<f32> = self.time().into_iter().map(|x| x as f32).collect();
         let s: Vec<f32> = self.sent().into_iter().map(|x| x as f32).collect();
@@ -112,8 +636,8 @@ impl MeasurementSet {
         let green: RGB8 = [0, 255, 0].into();
 
         Chart::new(width, height, xmin, xmax)
-            .linecolorplot(&sent_shape, red.clone())
-            .linecolorplot(&received_shape, green.clone())
+            .linecolorplot(&sent_shape, red)
+            .linecolorplot(&received_shape, green)
             .nice();
 
         println!("{} {}", "sent".fg(red), "received".fg(green));
@@ -4,28 +4,128 @@ use std::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use tokio::sync::oneshot;
-use tracing::{info, instrument};
+use tokio::{
+    runtime::Handle,
+    sync::oneshot::{self, error::TryRecvError},
+};
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    measurement::{MeasurementSet, TransportStats},
+    metrics::MetricsPusher,
+    sink::{MeasurementSink, PrintSink},
+};
+
+/// Finest interval tokio's timers can keep; shorter ones
+/// are sampled on a thread of their own
+const TIMER_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Longest sleep between checks for the stop signal
+/// while sampling on a thread of its own
+const STOP_CHECK: Duration = Duration::from_millis(1);
+
+/// Relative change in throughput, between one interval and the next,
+/// below which adaptive sampling considers it steady
+const STEADY_TOLERANCE: f64 = 0.05;
+
+/// Parse a sampling interval: a number of milliseconds (e.g. `200`),
+/// or a duration with a unit (e.g. `250us`, `1.5ms`, `2s`)
+pub fn parse_interval(s: &str) -> anyhow::Result<Duration> {
+    let interval = match s.parse::<f64>() {
+        Ok(ms) if ms.is_finite() && ms >= 0.0 => Duration::from_secs_f64(ms / 1000.0),
+        Ok(_) => anyhow::bail!("invalid interval: {}", s),
+        Err(_) => humantime::parse_duration(s)?,
+    };
+    anyhow::ensure!(!interval.is_zero(), "interval must be positive");
+    Ok(interval)
+}
+
+/// How to sample, beyond the base frequency
+#[derive(Debug, Clone, Default)]
+pub struct SamplingConfig {
+    /// Back off up to this interval while throughput is steady
+    pub adaptive_max: Option<Duration>,
+    /// Keep only this many of the latest measurements
+    pub max_samples: Option<usize>,
+}
 
-use crate::measurement::MeasurementSet;
+/// Command-line sampling options
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct SamplingArgs {
+    /// Sample adaptively: every -f while throughput changes, backing
+    /// off up to this interval while it's steady (e.g. 1s)
+    #[clap(long, parse(try_from_str = parse_interval), env = "SEISMIC_ADAPTIVE_MAX")]
+    pub adaptive_max: Option<Duration>,
+    /// Keep only this many of the latest measurements,
+    /// dropping the oldest
+    #[clap(long, env = "SEISMIC_MAX_SAMPLES")]
+    pub max_samples: Option<usize>,
+}
+
+impl From<SamplingArgs> for SamplingConfig {
+    fn from(args: SamplingArgs) -> Self {
+        Self {
+            adaptive_max: args.adaptive_max,
+            max_samples: args.max_samples,
+        }
+    }
+}
+
+/// When to take the next measurement
+struct Schedule {
+    next: Instant,
+    interval: Duration,
+}
+
+impl Schedule {
+    /// Move on to the next tick after `now`, skipping any that were
+    /// missed (e.g. under load) rather than bunching them up
+    fn advance(&mut self, now: Instant) {
+        self.next += self.interval;
+        if self.next <= now {
+            let behind = (now - self.next).as_nanos();
+            let missed = behind / self.interval.as_nanos() + 1;
+            // Stay in phase, unless that many intervals don't fit in
+            // a Duration, in which case just wait one from now
+            self.next = self
+                .interval
+                .as_nanos()
+                .checked_mul(missed)
+                .and_then(|skip| u64::try_from(skip).ok())
+                .and_then(|skip| self.next.checked_add(Duration::from_nanos(skip)))
+                .unwrap_or(now + self.interval);
+        }
+    }
+}
 
 /// Measures a counter periodically,
 /// stopping when a signal is given.
 pub struct Measurer {
     /// Measurement frequency
     freq: Duration,
-    /// Counter for chunks sent
+    /// Back off from `freq` up to this interval
+    /// while throughput is steady
+    adaptive_max: Option<Duration>,
+    /// Bytes received and time of the previous measurement,
+    /// and the throughput since the one before
+    last: Option<(u64, Duration, Option<f64>)>,
+    /// Counter for bytes sent
     sent: Arc<AtomicU64>,
-    /// Counter for chunks received
+    /// Counter for bytes received
     received: Arc<AtomicU64>,
     /// One-shot channel indicating
     /// measurement should end.
     stop: Pin<Box<oneshot::Receiver<()>>>,
     /// The measurements themselves
     mset: MeasurementSet,
+    /// Where each measurement goes as it's taken
+    sinks: Vec<Box<dyn MeasurementSink>>,
+    /// Optionally record statistics from the transport
+    transport_stats: Option<Box<dyn Fn() -> Option<TransportStats> + Send>>,
 }
 
 pub struct MeasurerStopper(oneshot::Sender<()>);
@@ -44,6 +144,7 @@ impl MeasurerStopper {
 impl Measurer {
     pub fn new(
         freq: Duration,
+        chunk_size: usize,
         print_live: bool,
         sent: Arc<AtomicU64>,
         received: Arc<AtomicU64>,
@@ -51,36 +152,214 @@ impl Measurer {
         let (stop_send, stop_recv) = oneshot::channel();
         let stopper = MeasurerStopper(stop_send);
         let stop = Box::pin(stop_recv);
-        let mset = MeasurementSet::new(print_live);
+        let mset = MeasurementSet::new(chunk_size, false);
+        let mut sinks: Vec<Box<dyn MeasurementSink>> = Vec::new();
+        if print_live {
+            sinks.push(Box::new(PrintSink));
+        }
 
         let measurer = Self {
             freq,
+            adaptive_max: None,
+            last: None,
             sent,
             received,
             stop,
             mset,
+            sinks,
+            transport_stats: None,
         };
 
         (measurer, stopper)
     }
 
-    #[instrument(name = "Measurer::run", skip(self))]
-    pub async fn run(mut self) -> MeasurementSet {
-        // Ticks once for each measurement
-        let mut interval = tokio::time::interval(self.freq);
+    /// Record the address of the other end of the connection
+    pub fn with_peer(mut self, peer: impl Into<String>) -> Self {
+        self.mset.peer = Some(peer.into());
+        self
+    }
 
-        loop {
-            tokio::select! {
-                _ = &mut self.stop => { break; }
-                _ = interval.tick() => {
-                    let sent = self.sent.load(Ordering::SeqCst);
-                    let received = self.received.load(Ordering::SeqCst);
-                    self.mset.record(sent, received);
+    /// Attach a metadata entry to the measurement set
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.mset.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Record whether the measured stream is encrypted
+    pub fn with_encryption(self, encrypted: bool) -> Self {
+        let encryption = if encrypted { "tls" } else { "none" };
+        self.with_metadata("encryption", encryption)
+    }
+
+    /// Record whether the data stays on this host (e.g. a Unix socket),
+    /// making the run a baseline of the host's own overhead
+    pub fn with_host_internal(self, host_internal: bool) -> Self {
+        let scope = if host_internal {
+            "host-internal"
+        } else {
+            "network"
+        };
+        self.with_metadata("scope", scope)
+    }
+
+    /// Record the transport's own statistics with each measurement
+    pub fn with_transport_stats(
+        mut self,
+        stats: impl Fn() -> Option<TransportStats> + Send + 'static,
+    ) -> Self {
+        self.transport_stats = Some(Box::new(stats));
+        self
+    }
+
+    /// Id of the measurement set being recorded
+    pub fn run_id(&self) -> Uuid {
+        self.mset.id
+    }
+
+    pub fn with_metrics(self, metrics: MetricsPusher) -> Self {
+        self.with_sink(metrics)
+    }
+
+    /// Also hand each measurement to `sink` as it's taken
+    pub fn with_sink(mut self, sink: impl MeasurementSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Hand each measurement to these sinks too
+    pub fn with_sinks(mut self, sinks: Vec<Box<dyn MeasurementSink>>) -> Self {
+        self.sinks.extend(sinks);
+        self
+    }
+
+    /// Sample every `freq` while throughput is changing (e.g. ramping
+    /// up), doubling the interval up to `max` while it's steady
+    pub fn with_adaptive(mut self, max: Duration) -> Self {
+        self.adaptive_max = Some(max.max(self.freq));
+        self
+    }
+
+    /// Keep only the latest `max_samples` measurements
+    pub fn with_max_samples(mut self, max_samples: usize) -> Self {
+        self.mset = self.mset.with_max_samples(max_samples);
+        self
+    }
+
+    /// Apply adaptive sampling and a sample limit, if configured
+    pub fn with_sampling(mut self, sampling: &SamplingConfig) -> Self {
+        if let Some(max) = sampling.adaptive_max {
+            self = self.with_adaptive(max);
+        }
+        if let Some(max_samples) = sampling.max_samples {
+            self = self.with_max_samples(max_samples);
+        }
+        self
+    }
+
+    /// Take a measurement, returning the interval until the next one
+    fn record(&mut self) -> Duration {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let received = self.received.load(Ordering::SeqCst);
+        let transport = self.transport_stats.as_ref().and_then(|stats| stats());
+        let m = self.mset.record_bytes(sent, received, transport).clone();
+        for sink in &mut self.sinks {
+            sink.record(&self.mset, &m);
+        }
+        self.next_interval(received, m.dt)
+    }
+
+    /// With adaptive sampling, back off while throughput is
+    /// steady and return to `freq` as soon as it changes
+    fn next_interval(&mut self, received: u64, dt: Duration) -> Duration {
+        let max = match self.adaptive_max {
+            Some(max) => max,
+            None => return self.freq,
+        };
+        let (rate, interval) = match self.last {
+            Some((last_received, last_dt, last_rate)) if dt > last_dt => {
+                let rate = (received - last_received) as f64 / (dt - last_dt).as_secs_f64();
+                let steady = last_rate.is_some_and(|last_rate| {
+                    // An idle stream is steady too
+                    (last_rate == 0.0 && rate == 0.0)
+                        || (last_rate > 0.0
+                            && ((rate - last_rate) / last_rate).abs() < STEADY_TOLERANCE)
+                });
+                let interval = if steady {
+                    (dt - last_dt).saturating_mul(2).min(max)
+                } else {
+                    self.freq
+                };
+                (Some(rate), interval)
+            }
+            _ => (None, self.freq),
+        };
+        self.last = Some((received, dt, rate));
+        interval
+    }
+
+    #[instrument(name = "Measurer::run", skip(self), fields(run_id = %self.mset.id))]
+    pub async fn run(mut self) -> MeasurementSet {
+        if self.freq < TIMER_RESOLUTION {
+            // Too fine for tokio's timers: sample on a blocking thread,
+            // within the runtime so that metrics can still be pushed
+            let runtime = Handle::current();
+            self = tokio::task::spawn_blocking(move || {
+                let _runtime = runtime.enter();
+                self.run_precise();
+                self
+            })
+            .await
+            .expect("measurer thread panicked");
+        } else {
+            let mut schedule = Schedule {
+                next: Instant::now(),
+                interval: self.freq,
+            };
+            loop {
+                tokio::select! {
+                    _ = &mut self.stop => { break; }
+                    _ = tokio::time::sleep_until(schedule.next.into()) => {
+                        schedule.interval = self.record();
+                        schedule.advance(Instant::now());
+                    }
                 }
             }
         }
 
+        // Count everything transferred since the last tick
+        self.record();
+        self.mset.finish();
+        if let Some(dropped) = self.mset.metadata.get("samples_dropped") {
+            warn!("dropped the oldest {} measurements", dropped);
+        }
+
+        for sink in self.sinks.drain(..) {
+            sink.finish().await;
+        }
+
         info!("End Measurer::run");
         self.mset
     }
+
+    /// Sample with sub-millisecond precision, sleeping on this thread
+    fn run_precise(&mut self) {
+        let mut schedule = Schedule {
+            next: Instant::now(),
+            interval: self.freq,
+        };
+        loop {
+            let now = Instant::now();
+            if now >= schedule.next {
+                schedule.interval = self.record();
+                schedule.advance(Instant::now());
+                continue;
+            }
+            match self.stop.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                // Stopped, or the stopper was dropped
+                _ => break,
+            }
+            std::thread::sleep((schedule.next - now).min(STOP_CHECK));
+        }
+    }
 }
@@ -0,0 +1,436 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{Body, Client, Request};
+use opentelemetry::{
+    metrics::{Counter, Meter, MeterProvider},
+    sdk::metrics::PushController,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    measurement::{Measurement, MeasurementSet},
+    parse_key_value,
+    sink::MeasurementSink,
+};
+
+/// Measurement name used by every backend
+const METRIC_PREFIX: &str = "seismic";
+
+/// Run metadata that tags samples unless configured otherwise;
+/// each has few values, so runs don't each start new series
+pub const DEFAULT_TAG_KEYS: [&str; 4] = ["role", "transport", "encryption", "scope"];
+
+/// Where to push measurements
+#[derive(Debug, Clone)]
+pub enum MetricsBackend {
+    /// InfluxDB line protocol over UDP (`host:port`)
+    InfluxUdp(String),
+    /// InfluxDB line protocol over HTTP, POSTed to a write URL
+    /// such as `http://localhost:8086/api/v2/write?org=o&bucket=b`
+    InfluxHttp { url: String, token: Option<String> },
+    /// StatsD gauges over UDP (`host:port`), with DogStatsD-style tags
+    Statsd(String),
+    /// OpenTelemetry metrics over OTLP/gRPC (e.g. `http://localhost:4317`)
+    Otlp(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub backends: Vec<MetricsBackend>,
+    /// Extra tags attached to every sample,
+    /// in addition to those taken from the run metadata
+    pub tags: BTreeMap<String, String>,
+    /// Run metadata keys (or `peer` and `run_id`) to tag samples with
+    pub tag_keys: Vec<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            backends: Vec::new(),
+            tags: BTreeMap::new(),
+            tag_keys: DEFAULT_TAG_KEYS.map(String::from).into(),
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.backends.is_empty()
+    }
+}
+
+/// Command-line options for selecting metrics backends
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct MetricsArgs {
+    /// Push measurements as InfluxDB line protocol over UDP (host:port)
+    #[clap(long, env = "SEISMIC_INFLUX_UDP")]
+    pub influx_udp: Option<String>,
+    /// Push measurements as InfluxDB line protocol to this HTTP write URL
+    #[clap(long, env = "SEISMIC_INFLUX_HTTP")]
+    pub influx_http: Option<String>,
+    /// Token for the InfluxDB HTTP API
+    #[clap(
+        long,
+        requires = "influx-http",
+        env = "SEISMIC_INFLUX_TOKEN",
+        hide_env_values = true
+    )]
+    pub influx_token: Option<String>,
+    /// Push measurements as StatsD gauges over UDP (host:port)
+    #[clap(long, env = "SEISMIC_STATSD")]
+    pub statsd: Option<String>,
+    /// Push measurements as OpenTelemetry metrics to this OTLP/gRPC endpoint
+    #[clap(long, env = "SEISMIC_OTLP_METRICS")]
+    pub otlp_metrics: Option<String>,
+    /// Extra metric tag (key=value), may be repeated
+    #[clap(long = "tag", parse(try_from_str = parse_key_value))]
+    pub tags: Vec<(String, String)>,
+    /// Tag samples with this run metadata key (or peer or run_id),
+    /// may be repeated; every value of a tag is a new series
+    #[clap(long = "tag-key", default_values = &DEFAULT_TAG_KEYS)]
+    pub tag_keys: Vec<String>,
+}
+
+impl From<MetricsArgs> for MetricsConfig {
+    fn from(args: MetricsArgs) -> Self {
+        let mut backends = Vec::new();
+        if let Some(addr) = args.influx_udp {
+            backends.push(MetricsBackend::InfluxUdp(addr));
+        }
+        if let Some(url) = args.influx_http {
+            backends.push(MetricsBackend::InfluxHttp {
+                url,
+                token: args.influx_token,
+            });
+        }
+        if let Some(addr) = args.statsd {
+            backends.push(MetricsBackend::Statsd(addr));
+        }
+        if let Some(endpoint) = args.otlp_metrics {
+            backends.push(MetricsBackend::Otlp(endpoint));
+        }
+
+        Self {
+            backends,
+            tags: args.tags.into_iter().collect(),
+            tag_keys: args.tag_keys,
+        }
+    }
+}
+
+/// A single measurement, tagged and timestamped for export
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The run the sample belongs to, whether or not it's a tag
+    pub run_id: Uuid,
+    pub time: SystemTime,
+    pub sent: u64,
+    pub received: u64,
+    pub sent_bytes: u64,
+    pub received_bytes: u64,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Sample {
+    /// Tag a measurement with the `tag_keys` of its set's metadata
+    /// (or its `peer` and `run_id`), and the extra tags
+    pub fn new(
+        mset: &MeasurementSet,
+        measurement: &Measurement,
+        extra_tags: &BTreeMap<String, String>,
+        tag_keys: &[String],
+    ) -> Self {
+        let mut tags = BTreeMap::new();
+        for key in tag_keys {
+            let value = match key.as_str() {
+                "run_id" => Some(mset.id.to_string()),
+                "peer" => mset.peer.clone(),
+                key => mset.metadata.get(key).cloned(),
+            };
+            if let Some(value) = value {
+                tags.insert(key.clone(), value);
+            }
+        }
+        tags.extend(extra_tags.clone());
+
+        Self {
+            run_id: mset.id,
+            time: mset.start_time + measurement.dt,
+            sent: measurement.sent,
+            received: measurement.received,
+            sent_bytes: measurement.sent_bytes,
+            received_bytes: measurement.received_bytes,
+            tags,
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, u64); 4] {
+        [
+            ("sent", self.sent),
+            ("received", self.received),
+            ("sent_bytes", self.sent_bytes),
+            ("received_bytes", self.received_bytes),
+        ]
+    }
+
+    /// Format as a single line of InfluxDB line protocol
+    pub fn to_line_protocol(&self) -> String {
+        let mut line = METRIC_PREFIX.to_string();
+        for (key, value) in &self.tags {
+            line.push_str(&format!(",{}={}", escape_influx(key), escape_influx(value)));
+        }
+        let fields: Vec<String> = self
+            .fields()
+            .iter()
+            .map(|(key, value)| format!("{}={}i", key, value))
+            .collect();
+        let nanos = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_nanos();
+        line.push_str(&format!(" {} {}", fields.join(","), nanos));
+        line
+    }
+
+    /// Format as StatsD gauges, one per line
+    pub fn to_statsd(&self) -> String {
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}:{}", escape_statsd(key), escape_statsd(value)))
+            .collect();
+        self.fields()
+            .iter()
+            .map(|(key, value)| {
+                format!("{}.{}:{}|g|#{}", METRIC_PREFIX, key, value, tags.join(","))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn escape_influx(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn escape_statsd(s: &str) -> String {
+    s.replace(['|', ',', '#', ':'], "_")
+}
+
+/// Pushes measurements to the configured backends
+/// from a background task, so that slow backends
+/// don't delay measurement.
+pub struct MetricsPusher {
+    tags: BTreeMap<String, String>,
+    tag_keys: Vec<String>,
+    tx: UnboundedSender<Sample>,
+    task: JoinHandle<()>,
+}
+
+impl MetricsPusher {
+    pub async fn connect(config: MetricsConfig) -> anyhow::Result<Self> {
+        let mut exporters = Vec::new();
+        for backend in config.backends {
+            exporters.push(Exporter::connect(backend).await?);
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(export_samples(rx, exporters));
+
+        Ok(Self {
+            tags: config.tags,
+            tag_keys: config.tag_keys,
+            tx,
+            task,
+        })
+    }
+
+    pub fn push(&self, mset: &MeasurementSet, measurement: &Measurement) {
+        let sample = Sample::new(mset, measurement, &self.tags, &self.tag_keys);
+        // Failure means the export task has already exited
+        self.tx.send(sample).ok();
+    }
+
+    /// Wait for all pushed samples to be exported
+    pub async fn finish(self) {
+        drop(self.tx);
+        self.task.await.ok();
+        info!("MetricsPusher::finish");
+    }
+}
+
+impl MeasurementSink for MetricsPusher {
+    fn record(&mut self, mset: &MeasurementSet, measurement: &Measurement) {
+        self.push(mset, measurement);
+    }
+
+    fn finish(self: Box<Self>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(MetricsPusher::finish(*self))
+    }
+}
+
+async fn export_samples(mut rx: UnboundedReceiver<Sample>, mut exporters: Vec<Exporter>) {
+    while let Some(sample) = rx.recv().await {
+        // Batch up whatever else is waiting
+        let mut batch = vec![sample];
+        while let Ok(sample) = rx.try_recv() {
+            batch.push(sample);
+        }
+
+        for exporter in &mut exporters {
+            if let Err(err) = exporter.export(&batch).await {
+                warn!("failed to export metrics: {}", err);
+            }
+        }
+    }
+}
+
+enum Exporter {
+    InfluxUdp(UdpSocket),
+    InfluxHttp {
+        client: Client<hyper::client::HttpConnector>,
+        url: String,
+        token: Option<String>,
+    },
+    Statsd(UdpSocket),
+    Otlp(OtlpExporter),
+}
+
+impl Exporter {
+    async fn connect(backend: MetricsBackend) -> anyhow::Result<Self> {
+        let exporter = match backend {
+            MetricsBackend::InfluxUdp(addr) => Self::InfluxUdp(connect_udp(&addr).await?),
+            MetricsBackend::InfluxHttp { url, token } => Self::InfluxHttp {
+                client: Client::new(),
+                url,
+                token,
+            },
+            MetricsBackend::Statsd(addr) => Self::Statsd(connect_udp(&addr).await?),
+            MetricsBackend::Otlp(endpoint) => Self::Otlp(OtlpExporter::new(&endpoint)?),
+        };
+        Ok(exporter)
+    }
+
+    async fn export(&mut self, batch: &[Sample]) -> anyhow::Result<()> {
+        match self {
+            Self::InfluxUdp(socket) => {
+                // One datagram per sample keeps packets small
+                for sample in batch {
+                    socket.send(sample.to_line_protocol().as_bytes()).await?;
+                }
+            }
+            Self::InfluxHttp { client, url, token } => {
+                let lines: Vec<String> = batch.iter().map(Sample::to_line_protocol).collect();
+                let mut request = Request::post(url.as_str());
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                let request = request.body(Body::from(lines.join("\n")))?;
+                let response = client.request(request).await?;
+                if !response.status().is_success() {
+                    anyhow::bail!("InfluxDB responded with {}", response.status());
+                }
+            }
+            Self::Statsd(socket) => {
+                for sample in batch {
+                    socket.send(sample.to_statsd().as_bytes()).await?;
+                }
+            }
+            Self::Otlp(exporter) => exporter.record(batch),
+        }
+        Ok(())
+    }
+}
+
+/// Bind a socket of the same family as `addr` and connect it there
+async fn connect_udp(addr: &str) -> anyhow::Result<UdpSocket> {
+    let target = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{} did not resolve to an address", addr))?;
+    let local: SocketAddr = if target.is_ipv6() {
+        "[::]:0".parse()?
+    } else {
+        "0.0.0.0:0".parse()?
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// Records samples as OpenTelemetry counters,
+/// which the push controller exports periodically.
+struct OtlpExporter {
+    /// Exports on a final tick when dropped
+    _controller: PushController,
+    counters: Vec<Counter<u64>>,
+    /// Last exported value of each counter, by run
+    last: BTreeMap<Uuid, [u64; 4]>,
+}
+
+impl OtlpExporter {
+    fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let controller = opentelemetry_otlp::new_pipeline()
+            .metrics(tokio::spawn, |period| {
+                tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(period))
+            })
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .build()?;
+
+        let meter: Meter = controller.provider().meter(METRIC_PREFIX, None);
+        let counters = ["sent", "received", "sent_bytes", "received_bytes"]
+            .iter()
+            .map(|name| {
+                meter
+                    .u64_counter(format!("{}.{}", METRIC_PREFIX, name))
+                    .init()
+            })
+            .collect();
+
+        Ok(Self {
+            _controller: controller,
+            counters,
+            last: BTreeMap::new(),
+        })
+    }
+
+    fn record(&mut self, batch: &[Sample]) {
+        for sample in batch {
+            let attributes: Vec<KeyValue> = sample
+                .tags
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+                .collect();
+            let last = self.last.entry(sample.run_id).or_default();
+
+            // Counters take increments rather than totals
+            for (i, (_name, value)) in sample.fields().iter().enumerate() {
+                self.counters[i].add(value.saturating_sub(last[i]), &attributes);
+                last[i] = *value;
+            }
+        }
+    }
+}
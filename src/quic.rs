@@ -0,0 +1,155 @@
+//! QUIC transport for the data connection.
+//!
+//! The control channel stays on TCP. The client opens a single QUIC
+//! connection to the server's data port (over UDP), with one
+//! bidirectional stream per test stream. Like a TCP data connection,
+//! each stream starts with the session id.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    Connection, Endpoint, RecvStream, SendStream,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    measurement::TransportStats,
+    stream::BoxedStream,
+    tls::{TlsClientConfig, TlsServerConfig},
+    transport::{BoxFuture, Connector},
+};
+
+/// ALPN protocol identifier for seismic's data connections
+pub const ALPN: &[u8] = b"seismic";
+
+/// A bidirectional QUIC stream
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// Listen for QUIC connections on `addr`
+pub fn server_endpoint(addr: SocketAddr, tls: &TlsServerConfig) -> anyhow::Result<Endpoint> {
+    let mut crypto = tls.rustls_config()?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let config = quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+    Ok(Endpoint::server(config, addr)?)
+}
+
+/// Open a QUIC connection to `addr`
+pub async fn connect(addr: &str, tls: &TlsClientConfig) -> anyhow::Result<Connection> {
+    let remote = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("could not resolve {}", addr))?;
+    let local: SocketAddr = if remote.is_ipv6() {
+        "[::]:0".parse()?
+    } else {
+        "0.0.0.0:0".parse()?
+    };
+
+    let mut crypto = tls.rustls_config()?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let mut endpoint = Endpoint::client(local)?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(crypto)?,
+    )));
+
+    Ok(endpoint.connect(remote, &tls.server_name)?.await?)
+}
+
+/// Opens streams on a single QUIC connection
+pub struct QuicConnector {
+    addr: String,
+    conn: Connection,
+}
+
+impl QuicConnector {
+    pub async fn connect(addr: impl Into<String>, tls: &TlsClientConfig) -> anyhow::Result<Self> {
+        let addr = addr.into();
+        let conn = connect(&addr, tls).await?;
+        Ok(Self { addr, conn })
+    }
+}
+
+impl Connector for QuicConnector {
+    fn name(&self) -> &'static str {
+        "quic"
+    }
+
+    fn peer(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn connect(&self) -> BoxFuture<'_, io::Result<BoxedStream>> {
+        Box::pin(async move { Ok(Box::new(open_stream(&self.conn).await?) as BoxedStream) })
+    }
+
+    fn is_encrypted(&self) -> bool {
+        true
+    }
+
+    fn stats(&self) -> Option<TransportStats> {
+        Some(stats(&self.conn))
+    }
+
+    fn close(&self) {
+        self.conn.close(0u32.into(), b"done");
+    }
+}
+
+pub async fn open_stream(conn: &Connection) -> io::Result<QuicStream> {
+    let (send, recv) = conn.open_bi().await?;
+    Ok(QuicStream { send, recv })
+}
+
+pub async fn accept_stream(conn: &Connection) -> io::Result<QuicStream> {
+    let (send, recv) = conn.accept_bi().await?;
+    Ok(QuicStream { send, recv })
+}
+
+/// Current statistics of the connection's path
+pub fn stats(conn: &Connection) -> TransportStats {
+    let path = conn.stats().path;
+    TransportStats {
+        rtt: path.rtt,
+        congestion_window: path.cwnd,
+        sent_packets: path.sent_packets,
+        lost_packets: path.lost_packets,
+    }
+}
@@ -6,23 +6,26 @@ use std::{
     },
 };
 
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
-    },
-};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::{debug, info, instrument, warn};
 
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring;
+use crate::{
+    limits::RateLimiter,
+    stream::{self, BoxedStream, StreamReadHalf, StreamWriteHalf},
+};
+
 pub enum Reader {
     Simple(SimpleReader),
     Echoing(EchoingReader),
 }
 
 impl Reader {
+    /// Read until the stream ends, returning the size of any
+    /// trailing partial chunk
     #[instrument(name = "Reader::run", skip(self))]
-    pub async fn run(&mut self) -> anyhow::Result<()> {
+    pub async fn run(&mut self) -> anyhow::Result<u64> {
         info!("Reader::run");
         match self {
             Reader::Simple(inner) => inner.run().await,
@@ -33,19 +36,19 @@ impl Reader {
 
 pub struct EchoingReader {
     reader: SimpleReader,
-    write_half: OwnedWriteHalf,
-    /// Counter for chunks sent
+    write_half: StreamWriteHalf,
+    /// Counter for bytes sent
     sent: Arc<AtomicU64>,
 }
 
 impl EchoingReader {
     pub fn new(
-        stream: TcpStream,
+        stream: BoxedStream,
         chunk_size: usize,
         sent: Arc<AtomicU64>,
         received: Arc<AtomicU64>,
     ) -> Self {
-        let (read_half, write_half) = stream.into_split();
+        let (read_half, write_half) = stream::split(stream);
 
         let reader = SimpleReader::new(read_half, chunk_size, received);
 
@@ -58,26 +61,34 @@ impl EchoingReader {
         }
     }
 
-    pub async fn read_chunk(&mut self) -> std::io::Result<()> {
+    pub fn with_rate_limit(mut self, rate_limit: Option<Arc<RateLimiter>>) -> Self {
+        self.reader = self.reader.with_rate_limit(rate_limit);
+        self
+    }
+
+    pub async fn read_chunk(&mut self) -> std::io::Result<usize> {
         // Read
-        self.reader.read_chunk().await?;
+        let nbytes = self.reader.read_chunk().await?;
 
-        // Echo response
-        self.write_half.write_all(&self.reader.buf).await?;
-        self.sent.fetch_add(1, Ordering::SeqCst);
+        // Echo response, including any partial chunk
+        self.write_half
+            .write_all(&self.reader.buf[..nbytes])
+            .await?;
+        self.sent.fetch_add(nbytes as u64, Ordering::SeqCst);
 
-        Ok(())
+        Ok(nbytes)
     }
 
     #[instrument(name = "EchoingReader::run", skip(self))]
-    pub async fn run(&mut self) -> anyhow::Result<()> {
+    pub async fn run(&mut self) -> anyhow::Result<u64> {
         info!("start EchoingReader::run");
 
+        let chunk_size = self.reader.buf.len();
         loop {
             let res = self.read_chunk().await;
-            if let ReadChunkAction::Exit(res) = handle_read_chunk_result(res) {
+            if let ReadChunkAction::Exit(res) = handle_read_chunk_result(res, chunk_size) {
                 info!("end EchoingReader::run ({:?})", res);
-                return res;
+                return res.map(|()| self.reader.partial_chunk());
             }
         }
     }
@@ -85,16 +96,22 @@ impl EchoingReader {
 
 /// Simple (non-echoing) reader
 pub struct SimpleReader {
-    read_half: OwnedReadHalf,
+    read_half: StreamReadHalf,
     pub buf: Vec<u8>,
-    /// Counter for chunks received
+    /// Counter for bytes received
     received: Arc<AtomicU64>,
+    /// Bytes read from this stream
+    total: u64,
+    /// Optionally limit the rate of reading
+    rate_limit: Option<Arc<RateLimiter>>,
+    /// Receive with io_uring from this socket instead
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    io_uring: Option<std::os::fd::OwnedFd>,
 }
 
 impl SimpleReader {
-    pub fn new(read_half: OwnedReadHalf, chunk_size: usize, received: Arc<AtomicU64>) -> Self {
-        let mut buf = Vec::new();
-        buf.resize(chunk_size, 0);
+    pub fn new(read_half: StreamReadHalf, chunk_size: usize, received: Arc<AtomicU64>) -> Self {
+        let buf = vec![0; chunk_size];
 
         info!("SimpleReader::new");
 
@@ -102,31 +119,72 @@ impl SimpleReader {
             read_half,
             buf,
             received,
+            total: 0,
+            rate_limit: None,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            io_uring: None,
         }
     }
 
-    pub async fn read_chunk(&mut self) -> std::io::Result<()> {
-        debug!("read_chunk");
-        match self.read_half.read_exact(&mut self.buf).await {
-            Ok(_nbytes) => {
-                // Increment received chunks counter
-                self.received.fetch_add(1, Ordering::SeqCst);
+    /// Receive with io_uring from `socket`, the one underneath the
+    /// read half
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn with_io_uring(mut self, socket: std::os::fd::OwnedFd) -> Self {
+        self.io_uring = Some(socket);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
 
-                Ok(())
+    /// Read up to one chunk, counting bytes as they arrive.
+    /// Returns the number of bytes read, which is
+    /// less than a whole chunk only at the end of the stream.
+    pub async fn read_chunk(&mut self) -> std::io::Result<usize> {
+        debug!("read_chunk");
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.acquire(self.buf.len()).await;
+        }
+        let mut filled = 0;
+        while filled < self.buf.len() {
+            let nbytes = self.read_half.read(&mut self.buf[filled..]).await?;
+            if nbytes == 0 {
+                break;
             }
-            Err(err) => Err(err),
+            filled += nbytes;
+            self.total += nbytes as u64;
+            self.received.fetch_add(nbytes as u64, Ordering::SeqCst);
         }
+        Ok(filled)
+    }
+
+    /// Bytes read past the last whole chunk
+    pub fn partial_chunk(&self) -> u64 {
+        self.total % self.buf.len() as u64
     }
 
     #[instrument(name = "SimpleReader::Run", skip(self))]
-    pub async fn run(&mut self) -> anyhow::Result<()> {
+    pub async fn run(&mut self) -> anyhow::Result<u64> {
         info!("start SimpleReader::run");
 
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(socket) = self.io_uring.take() {
+            let (received, rate_limit) = (self.received.clone(), self.rate_limit.clone());
+            let res =
+                uring::run_blocking(move || uring::receive(socket, received, rate_limit)).await;
+            info!("end SimpleReader::run ({:?})", res);
+            self.total += res?;
+            return Ok(self.partial_chunk());
+        }
+
+        let chunk_size = self.buf.len();
         loop {
             let res = self.read_chunk().await;
-            if let ReadChunkAction::Exit(res) = handle_read_chunk_result(res) {
+            if let ReadChunkAction::Exit(res) = handle_read_chunk_result(res, chunk_size) {
                 info!("end SimpleReader::run ({:?})", res);
-                return res;
+                return res.map(|()| self.partial_chunk());
             } else {
                 debug!("Continue");
             }
@@ -139,18 +197,23 @@ enum ReadChunkAction {
     Exit(anyhow::Result<()>),
 }
 
-fn handle_read_chunk_result(res: std::io::Result<()>) -> ReadChunkAction {
+fn handle_read_chunk_result(res: std::io::Result<usize>, chunk_size: usize) -> ReadChunkAction {
     use ErrorKind::{ConnectionReset, UnexpectedEof};
-    if let Err(err) = res {
-        return match err.kind() {
+    match res {
+        Ok(nbytes) if nbytes == chunk_size => ReadChunkAction::Continue,
+        // A short read means the stream ended; its bytes are already counted
+        Ok(0) => ReadChunkAction::Exit(Ok(())),
+        Ok(nbytes) => {
+            info!("stream ended with a partial chunk of {} bytes", nbytes);
+            ReadChunkAction::Exit(Ok(()))
+        }
+        Err(err) => match err.kind() {
             // EOF _is_ expected here.
             UnexpectedEof | ConnectionReset => ReadChunkAction::Exit(Ok(())),
             other => {
                 warn!("UNEXPECTED ERROR KIND: {:?} ({})", other, other);
                 ReadChunkAction::Exit(Err(err.into()))
             }
-        };
+        },
     }
-
-    ReadChunkAction::Continue
 }
@@ -3,19 +3,26 @@ use std::{
     time::Duration,
 };
 
-use tokio::net::TcpStream;
-use tracing::{info, instrument};
+use tokio::{sync::broadcast, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, info, instrument, Span};
 
 use crate::{
-    measurement::MeasurementSet,
-    measurer::{Measurer, MeasurerStopper},
+    limits::RateLimiter,
+    measurement::{Measurement, MeasurementSet, TransportStats},
+    measurer::{Measurer, MeasurerStopper, SamplingConfig},
+    metrics::{MetricsConfig, MetricsPusher},
     reader::{EchoingReader, Reader, SimpleReader},
+    sink::{BroadcastSink, MeasurementSink},
+    stream::{self, BoxedStream},
 };
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct ReceiverConfig {
     /// Measurement frequency
     pub freq: Duration,
+    /// Adaptive sampling and sample limit
+    pub sampling: SamplingConfig,
     /// Bytes per chunk
     pub chunk_size: usize,
     /// Whether to echo data back to stream
@@ -23,70 +30,239 @@ pub struct ReceiverConfig {
     /// Whether to print new measurements
     /// as they're recorded
     pub print_live: bool,
+    /// Where to push measurements as metrics
+    pub metrics: MetricsConfig,
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        Self {
+            freq: Duration::from_millis(200),
+            sampling: SamplingConfig::default(),
+            chunk_size: 1024,
+            echo: true,
+            print_live: false,
+            metrics: MetricsConfig::default(),
+        }
+    }
 }
 
 pub struct Receiver {
-    /// TCP Stream to read from
-    stream: TcpStream,
+    /// Streams to read from
+    streams: Vec<BoxedStream>,
+    /// Address of the sender
+    peer: Option<String>,
+    /// Name of the transport carrying the streams
+    transport: &'static str,
+    /// Whether the streams are encrypted
+    encrypted: bool,
+    /// Whether the streams stay on this host
+    host_internal: bool,
+    /// Optionally limit the rate of reading
+    rate_limit: Option<Arc<RateLimiter>>,
+    /// Optionally record statistics from the transport
+    transport_stats: Option<Box<dyn Fn() -> Option<TransportStats> + Send>>,
     /// Configuration values
     config: ReceiverConfig,
-    /// Counter for chunks sent
+    /// Counter for bytes sent
     sent: Arc<AtomicU64>,
-    /// Counter for chunks received
+    /// Counter for bytes received
     received: Arc<AtomicU64>,
+    /// Publishes measurements to subscribers as they're taken
+    live: BroadcastSink,
+    /// Other consumers of live measurements
+    sinks: Vec<Box<dyn MeasurementSink>>,
+    /// Ends the run early when cancelled
+    cancel: CancellationToken,
 }
 
 impl Receiver {
-    pub fn new(stream: TcpStream, config: ReceiverConfig) -> Self {
+    pub fn new(stream: BoxedStream, config: ReceiverConfig) -> Self {
+        Self::multi(vec![stream], config)
+    }
+
+    /// Receive concurrently on several streams of the same test
+    pub fn multi(streams: Vec<BoxedStream>, config: ReceiverConfig) -> Self {
         let sent = Arc::new(AtomicU64::new(0));
         let received = Arc::new(AtomicU64::new(0));
         Self {
-            stream,
+            streams,
+            peer: None,
+            transport: "tcp",
+            encrypted: false,
+            host_internal: false,
+            rate_limit: None,
+            transport_stats: None,
             config,
             sent,
             received,
+            live: BroadcastSink::new(),
+            sinks: Vec::new(),
+            cancel: CancellationToken::new(),
         }
     }
 
-    /// TODO: Get rid of this method, probably.
-    fn split(self) -> (Reader, Measurer, MeasurerStopper) {
-        let freq = self.config.freq;
-
-        let reader = if self.config.echo {
-            let inner = EchoingReader::new(
-                self.stream,
-                self.config.chunk_size,
-                self.sent.clone(),
-                self.received.clone(),
-            );
-            Reader::Echoing(inner)
-        } else {
-            let (read_half, _write_half) = self.stream.into_split();
-            let inner = SimpleReader::new(read_half, self.config.chunk_size, self.received.clone());
-            Reader::Simple(inner)
-        };
+    /// Receive each measurement as it's taken, once the run starts
+    pub fn subscribe(&self) -> broadcast::Receiver<Measurement> {
+        self.live.subscribe()
+    }
+
+    /// Also hand each measurement to `sink` as it's taken
+    pub fn with_sink(mut self, sink: impl MeasurementSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// End the run early once `cancel` is cancelled. Reading and
+    /// echoing stop and the streams are closed, and the measurements
+    /// so far are returned with `cancelled=true` in their metadata.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Record the address of the sender
+    pub fn with_peer(mut self, peer: impl Into<String>) -> Self {
+        self.peer = Some(peer.into());
+        self
+    }
+
+    /// Share a limit on the rate of reading with other receivers
+    pub fn with_rate_limit(mut self, rate_limit: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
 
-        let (measurer, stopper) =
-            Measurer::new(freq, self.config.print_live, self.sent, self.received);
+    /// Note that the stream is encrypted, so throughput
+    /// includes the encryption overhead
+    pub fn with_encryption(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+        self
+    }
+
+    /// Note that the streams stay on this host, so the
+    /// run measures the host's own overhead
+    pub fn with_host_internal(mut self, host_internal: bool) -> Self {
+        self.host_internal = host_internal;
+        self
+    }
+
+    /// Record which transport carries the streams
+    pub fn with_transport(mut self, transport: &'static str) -> Self {
+        self.transport = transport;
+        self
+    }
 
-        (reader, measurer, stopper)
+    /// Record the transport's own statistics with each measurement
+    pub fn with_transport_stats(
+        mut self,
+        stats: impl Fn() -> Option<TransportStats> + Send + 'static,
+    ) -> Self {
+        self.transport_stats = Some(Box::new(stats));
+        self
     }
 
-    #[instrument(name = "Receiver::run", skip(self))]
-    pub async fn run(self) -> anyhow::Result<MeasurementSet> {
-        let (mut reader, measurer, stopper) = self.split();
+    /// One reader per stream, echoing if configured
+    fn readers(&mut self) -> Vec<Reader> {
+        std::mem::take(&mut self.streams)
+            .into_iter()
+            .map(|stream| {
+                if self.config.echo {
+                    let inner = EchoingReader::new(
+                        stream,
+                        self.config.chunk_size,
+                        self.sent.clone(),
+                        self.received.clone(),
+                    )
+                    .with_rate_limit(self.rate_limit.clone());
+                    Reader::Echoing(inner)
+                } else {
+                    let (read_half, _write_half) = stream::split(stream);
+                    let inner =
+                        SimpleReader::new(read_half, self.config.chunk_size, self.received.clone())
+                            .with_rate_limit(self.rate_limit.clone());
+                    Reader::Simple(inner)
+                }
+            })
+            .collect()
+    }
+
+    fn measurer(&mut self) -> (Measurer, MeasurerStopper) {
+        let (measurer, stopper) = Measurer::new(
+            self.config.freq,
+            self.config.chunk_size,
+            self.config.print_live,
+            self.sent.clone(),
+            self.received.clone(),
+        );
+        let mut measurer = measurer
+            .with_sampling(&self.config.sampling)
+            .with_sink(self.live.clone())
+            .with_sinks(std::mem::take(&mut self.sinks))
+            .with_metadata("role", "receiver")
+            .with_metadata("transport", self.transport)
+            .with_metadata("streams", self.streams.len().to_string())
+            .with_encryption(self.encrypted)
+            .with_host_internal(self.host_internal);
+        if let Some(peer) = self.peer.take() {
+            measurer = measurer.with_peer(peer);
+        }
+        if let Some(stats) = self.transport_stats.take() {
+            measurer = measurer.with_transport_stats(stats);
+        }
+
+        (measurer, stopper)
+    }
+
+    #[instrument(name = "Receiver::run", skip(self), fields(run_id))]
+    pub async fn run(mut self) -> anyhow::Result<MeasurementSet> {
+        let (mut measurer, stopper) = self.measurer();
+        let readers = self.readers();
+        Span::current().record("run_id", display(measurer.run_id()));
+        let metrics = self.config.metrics.clone();
+        if !metrics.is_empty() {
+            measurer = measurer.with_metrics(MetricsPusher::connect(metrics).await?);
+        }
 
         // Start measuring
         let mfut = tokio::spawn(async move { measurer.run().await });
 
-        // Start reading
-        let read_res = reader.run().await;
+        // Read from every stream
+        let read_futs: Vec<_> = readers
+            .into_iter()
+            .map(|mut reader| tokio::spawn(async move { reader.run().await }))
+            .collect();
+        let aborts: Vec<_> = read_futs.iter().map(JoinHandle::abort_handle).collect();
+        let reading = async move {
+            let mut read_res = Ok(0);
+            for read_fut in read_futs {
+                let res = read_fut.await?;
+                read_res = read_res.and_then(|partial| Ok(partial + res?));
+            }
+            anyhow::Ok(read_res.map(Some))
+        };
+        let read_res = tokio::select! {
+            read_res = reading => read_res?,
+            _ = self.cancel.cancelled() => {
+                // Dropping the readers closes the streams
+                for abort in aborts {
+                    abort.abort();
+                }
+                Ok(None)
+            }
+        };
         // Stop measuring once reading is complete
         stopper.stop();
 
         // Get the measurements and return them
         // if reading was successful
-        let mset = mfut.await?;
+        let mut mset = mfut.await?;
+        if let Ok(Some(partial)) = read_res {
+            mset.record_partial_chunk(partial);
+        }
+        if self.cancel.is_cancelled() {
+            mset.metadata.insert("cancelled".into(), "true".into());
+        }
         info!("End Receiver::run");
         read_res.and(Ok(mset))
     }
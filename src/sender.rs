@@ -7,100 +7,438 @@ use std::{
     time::{Duration, Instant},
 };
 
-use rand::{thread_rng, RngCore};
-use tokio::{
-    io::AsyncWriteExt,
-    net::{tcp::OwnedWriteHalf, TcpStream},
-};
-use tracing::{info, instrument};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, info, instrument, warn, Span};
+use uuid::Uuid;
 
-use crate::{measurement::MeasurementSet, measurer::MeasurerStopper};
+use crate::{
+    access,
+    control::{self, ClientMessage, ControlChannel, ServerMessage},
+    generator::Generator,
+    measurement::{Measurement, MeasurementSet, Warmup},
+    measurer::{MeasurerStopper, SamplingConfig},
+    metrics::{MetricsConfig, MetricsPusher},
+    quic::QuicConnector,
+    sink::{BroadcastSink, MeasurementSink},
+    stream::{self, BoxedStream},
+    tls::TlsClientConfig,
+    transport::{Connector, TcpConnector, UnixConnector},
+};
 use crate::{measurer::Measurer, reader::SimpleReader};
 
-#[derive(Debug)]
+/// How test data is carried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    /// One TCP connection (optionally with TLS)
+    Tcp,
+    /// One QUIC connection, with one or more streams
+    Quic,
+    /// Unix domain sockets; the addresses are socket paths
+    Unix,
+}
+
+impl Transport {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Quic => "quic",
+            Transport::Unix => "unix",
+        }
+    }
+}
+
+/// How the send and receive paths do their I/O
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// tokio's readiness-based I/O
+    Tokio,
+    /// io_uring, for plain TCP and Unix sockets
+    /// (Linux, with the `io-uring` cargo feature)
+    IoUring,
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Tokio => "tokio",
+            Backend::IoUring => "io-uring",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct SenderConfig {
     /// Destination address of receiver
     pub addr: String,
+    /// Address of the receiver's control port
+    pub control_addr: String,
     /// Measurement frequency
     pub freq: Duration,
+    /// Adaptive sampling and sample limit
+    pub sampling: SamplingConfig,
     /// Length of transmission
     pub length: Duration,
+    /// How much of the start of the run to leave out of its summary
+    pub warmup: Warmup,
+    /// Send this many bytes (rounded up to whole chunks) over
+    /// all streams, however long it takes, instead of for `length`
+    pub total_bytes: Option<u64>,
     /// Bytes per chunk
     pub chunk_size: usize,
     /// Whether to print new measurements
     /// as they're recorded
     pub print_live: bool,
+    /// Where to push measurements as metrics
+    pub metrics: MetricsConfig,
+    /// Encrypt the control and data connections
+    pub tls: Option<TlsClientConfig>,
+    /// Pre-shared token, for servers that require authentication
+    pub auth_token: Option<String>,
+    /// How to carry the test data
+    pub transport: Transport,
+    /// Number of concurrent streams (QUIC only)
+    pub streams: usize,
+    /// Send each stream from a thread of its own, pinned to
+    /// these CPU cores in turn; empty to share the runtime's threads
+    pub pin_cores: Vec<usize>,
+    /// Send with `sendfile` over plain TCP and Unix sockets (Linux only)
+    pub zero_copy: bool,
+    /// How to send and receive the test data
+    pub backend: Backend,
+}
+
+impl Default for SenderConfig {
+    /// A five-second test over TCP in 1 KiB chunks, sampled every
+    /// 200ms; the addresses must be filled in
+    fn default() -> Self {
+        Self {
+            addr: String::new(),
+            control_addr: String::new(),
+            freq: Duration::from_millis(200),
+            sampling: SamplingConfig::default(),
+            length: Duration::from_secs(5),
+            warmup: Warmup::Off,
+            total_bytes: None,
+            chunk_size: 1024,
+            print_live: false,
+            metrics: MetricsConfig::default(),
+            tls: None,
+            auth_token: None,
+            transport: Transport::Tcp,
+            streams: 1,
+            pin_cores: Vec::new(),
+            zero_copy: false,
+            backend: Backend::Tokio,
+        }
+    }
+}
+
+impl SenderConfig {
+    /// The fixed amount of data to send, rounded up to whole chunks
+    pub fn whole_total_bytes(&self) -> Option<u64> {
+        let chunk_size = self.chunk_size as u64;
+        self.total_bytes
+            .map(|bytes| bytes.div_ceil(chunk_size) * chunk_size)
+    }
+
+    /// Stream `i`'s share of the fixed amount of data:
+    /// the chunks are split as evenly as possible
+    fn stream_bytes(&self, i: usize) -> Option<u64> {
+        let chunk_size = self.chunk_size as u64;
+        let chunks = self.whole_total_bytes()? / chunk_size;
+        let (streams, i) = (self.streams as u64, i as u64);
+        let extra = u64::from(i < chunks % streams);
+        Some((chunks / streams + extra) * chunk_size)
+    }
 }
 
 pub struct Sender {
-    /// TCP Stream to read from
-    stream: TcpStream,
+    /// Streams to write to and read from
+    streams: Vec<BoxedStream>,
+    /// Transport the streams were opened with
+    data: Arc<dyn Connector>,
+    /// Control connection, held open for the session's lifetime
+    control: ControlChannel<BoxedStream>,
+    /// Session assigned by the server
+    session_id: Uuid,
+    /// Whether the server echoes our data back
+    echo: bool,
     /// Configuration values
     config: SenderConfig,
-    /// Counter for chunks sent
+    /// Counter for bytes sent
     sent: Arc<AtomicU64>,
-    /// Counter for chunks received
+    /// Counter for bytes received
     received: Arc<AtomicU64>,
+    /// Publishes measurements to subscribers as they're taken
+    live: BroadcastSink,
+    /// Other consumers of live measurements
+    sinks: Vec<Box<dyn MeasurementSink>>,
+    /// Ends the test early
+    cancel: CancellationToken,
 }
 
 impl Sender {
+    /// Connect to the server over the transport given in the config
     pub async fn new(config: SenderConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            config.streams == 1 || config.transport == Transport::Quic,
+            "multiple streams require the QUIC transport"
+        );
+
+        let tls = config.tls.as_ref().map(|tls| tls.connector()).transpose()?;
+        let control: Box<dyn Connector> = match config.transport {
+            Transport::Unix => Box::new(UnixConnector::new(&config.control_addr, tls.clone())),
+            _ => Box::new(TcpConnector::new(config.control_addr.clone(), tls.clone())),
+        };
+        let data: Arc<dyn Connector> = match config.transport {
+            Transport::Tcp => Arc::new(TcpConnector::new(config.addr.clone(), tls)),
+            Transport::Unix => Arc::new(UnixConnector::new(&config.addr, tls)),
+            Transport::Quic => {
+                // QUIC is always encrypted
+                let quic_tls = config
+                    .tls
+                    .clone()
+                    .unwrap_or_else(|| TlsClientConfig::new(host(&config.addr)));
+                Arc::new(QuicConnector::connect(config.addr.clone(), &quic_tls).await?)
+            }
+        };
+
+        Self::connect(config, control.as_ref(), data).await
+    }
+
+    /// Set up a session over `control`, then open the
+    /// configured number of data streams with `data`
+    pub async fn connect(
+        config: SenderConfig,
+        control: &dyn Connector,
+        data: Arc<dyn Connector>,
+    ) -> anyhow::Result<Self> {
         let sent = Arc::new(AtomicU64::new(0));
         let received = Arc::new(AtomicU64::new(0));
-        let stream = TcpStream::connect(&config.addr).await?;
-        let sender = Self {
-            stream,
+
+        anyhow::ensure!(config.streams > 0, "at least one stream is required");
+        anyhow::ensure!(
+            config.total_bytes != Some(0),
+            "the amount of data to send must be positive"
+        );
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        anyhow::ensure!(
+            config.backend == Backend::Tokio,
+            "the io_uring backend needs Linux and the io-uring feature"
+        );
+        anyhow::ensure!(
+            !(config.zero_copy && config.backend == Backend::IoUring),
+            "zero-copy can't be combined with the io_uring backend"
+        );
+        #[cfg(not(target_os = "linux"))]
+        if config.zero_copy {
+            warn!("zero-copy is only supported on Linux; copying instead");
+        }
+
+        // Set up a session, passing our trace context
+        // so the receiver's spans join the same trace
+        let mut control = ControlChannel::new(control.connect().await?);
+        let trace_context = control::current_trace_context();
+        let hello = ClientMessage::Hello {
+            trace_context,
+            chunk_size: config.chunk_size,
+            duration_ms: match config.total_bytes {
+                Some(_) => 0,
+                None => config.length.as_millis() as u64,
+            },
+            streams: config.streams,
+            total_bytes: config.whole_total_bytes(),
+        };
+        control.send(&hello).await?;
+        let (session_id, echo) = await_session(&mut control, config.auth_token.as_deref()).await?;
+        info!("Session {}", session_id);
+
+        let mut streams = Vec::with_capacity(config.streams);
+        for _ in 0..config.streams {
+            let mut stream = data.connect().await?;
+            control::write_session_id(&mut stream, session_id).await?;
+            streams.push(stream);
+        }
+
+        Ok(Self {
+            streams,
+            data,
+            control,
+            session_id,
+            echo,
             config,
             sent,
             received,
-        };
+            live: BroadcastSink::new(),
+            sinks: Vec::new(),
+            cancel: CancellationToken::new(),
+        })
+    }
 
-        Ok(sender)
+    /// End the test early once `cancel` is cancelled. Sending stops after
+    /// the chunk in progress, and the run returns what was measured, with
+    /// `cancelled=true` in its metadata.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
     }
 
-    /// TODO: Get rid of this method, probably.
-    fn split(self) -> (Generator, SimpleReader, Measurer, MeasurerStopper) {
-        let freq = self.config.freq;
-        let (read_half, write_half) = self.stream.into_split();
+    /// Receive each measurement as it's taken, once the run starts
+    pub fn subscribe(&self) -> broadcast::Receiver<Measurement> {
+        self.live.subscribe()
+    }
+
+    /// Also hand each measurement to `sink` as it's taken
+    pub fn with_sink(mut self, sink: impl MeasurementSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
 
-        let generator = Generator::new(
-            self.config.length,
-            write_half,
+    fn measurer(&mut self) -> (Measurer, MeasurerStopper) {
+        let (measurer, stopper) = Measurer::new(
+            self.config.freq,
             self.config.chunk_size,
+            self.config.print_live,
             self.sent.clone(),
+            self.received.clone(),
         );
+        let mut measurer = measurer
+            .with_sampling(&self.config.sampling)
+            .with_sink(self.live.clone())
+            .with_sinks(std::mem::take(&mut self.sinks))
+            .with_peer(self.data.peer())
+            .with_metadata("role", "sender")
+            .with_metadata("transport", self.data.name())
+            .with_metadata("streams", self.config.streams.to_string())
+            .with_metadata("backend", self.config.backend.name())
+            .with_encryption(self.data.is_encrypted())
+            .with_host_internal(self.data.is_host_internal());
+        if self.data.stats().is_some() {
+            let data = self.data.clone();
+            measurer = measurer.with_transport_stats(move || data.stats());
+        }
 
-        let reader = SimpleReader::new(read_half, self.config.chunk_size, self.received.clone());
+        (measurer, stopper)
+    }
 
-        let (measurer, stopper) =
-            Measurer::new(freq, self.config.print_live, self.sent, self.received);
+    /// The socket to send from with `sendfile`, if zero-copy
+    /// is enabled and possible for `stream`
+    #[cfg(target_os = "linux")]
+    fn zero_copy_socket(
+        &self,
+        stream: &BoxedStream,
+    ) -> anyhow::Result<Option<crate::zero_copy::Socket>> {
+        if !self.config.zero_copy {
+            return Ok(None);
+        }
+        let socket = crate::zero_copy::Socket::of(stream)?;
+        if socket.is_none() {
+            warn!("zero-copy needs plain TCP or a Unix socket; copying instead");
+        }
+        Ok(socket)
+    }
 
-        (generator, reader, measurer, stopper)
+    /// The socket to use with io_uring, if that's the backend
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn io_uring_socket(
+        &self,
+        stream: &BoxedStream,
+    ) -> anyhow::Result<Option<std::os::fd::OwnedFd>> {
+        if self.config.backend != Backend::IoUring {
+            return Ok(None);
+        }
+        let fd = stream::socket_fd(stream).ok_or_else(|| {
+            anyhow::anyhow!("the io_uring backend needs plain TCP or a Unix socket")
+        })?;
+        Ok(Some(fd.try_clone_to_owned()?))
     }
 
-    #[instrument(name = "Sender::run", skip(self))]
-    pub async fn run(self) -> anyhow::Result<MeasurementSet> {
-        let (generator, mut reader, measurer, stopper) = self.split();
+    #[instrument(name = "Sender::run", skip(self), fields(session_id = %self.session_id, run_id))]
+    pub async fn run(mut self) -> anyhow::Result<MeasurementSet> {
+        let (mut measurer, stopper) = self.measurer();
+        Span::current().record("run_id", display(measurer.run_id()));
+        let metrics = self.config.metrics.clone();
+        if !metrics.is_empty() {
+            measurer = measurer.with_metrics(MetricsPusher::connect(metrics).await?);
+        }
 
         // Start measuring
         info!("Start measuring");
         let mfut = tokio::spawn(async move { measurer.run().await });
 
-        // Start reading
-        info!("Start reading");
-        let read_fut = tokio::spawn(async move { reader.run().await });
-
-        // Start writing
-        info!("Start writing");
-        let write_fut = tokio::spawn(async move { generator.run().await });
+        // Start reading and writing on each stream
+        info!("Start reading and writing");
+        let mut read_futs = Vec::new();
+        let mut write_futs = Vec::new();
+        let started = Instant::now();
+        let streams = std::mem::take(&mut self.streams);
+        for (i, stream) in streams.into_iter().enumerate() {
+            #[cfg(target_os = "linux")]
+            let socket = self.zero_copy_socket(&stream)?;
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            let uring_socket = self.io_uring_socket(&stream)?;
+            let (read_half, write_half) = stream::split(stream);
+            let reader =
+                SimpleReader::new(read_half, self.config.chunk_size, self.received.clone());
+            let mut generator = Generator::new(
+                self.config.length,
+                write_half,
+                self.config.chunk_size,
+                self.sent.clone(),
+            )
+            .with_cancel(self.cancel.clone());
+            if let Some(bytes) = self.config.stream_bytes(i) {
+                generator = generator.with_total_bytes(bytes);
+            }
+            #[cfg(target_os = "linux")]
+            let generator = match socket {
+                Some(socket) => generator.with_zero_copy(socket)?,
+                None => generator,
+            };
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            let (reader, generator) = match uring_socket {
+                Some(socket) => (
+                    reader.with_io_uring(socket.try_clone()?),
+                    generator.with_io_uring(socket),
+                ),
+                None => (reader, generator),
+            };
+            let mut reader = reader;
+            read_futs.push(tokio::spawn(async move { reader.run().await }));
+            write_futs.push(match self.config.pin_cores.as_slice() {
+                [] => tokio::spawn(async move { generator.run().await }),
+                cores => generator.spawn_pinned(cores[i % cores.len()])?,
+            });
+        }
 
         // Wait for writing to complete
         info!("Wait for writing to complete");
-        let write_res = write_fut.await?;
+        let mut write_res = Ok(());
+        for write_fut in write_futs {
+            write_res = write_res.and(write_fut.await?);
+        }
+        let sent_bytes = self.sent.load(Ordering::SeqCst);
+        if write_res.is_ok() {
+            // Mark the end of the test, so the receiver can
+            // tell a clean finish from a truncated stream
+            self.control
+                .send(&ClientMessage::Done { sent_bytes })
+                .await?;
+        }
 
         // Wait for reading to complete
         info!("Wait for reading to complete");
-        let read_res = read_fut.await?;
+        let mut read_res = Ok(0);
+        for read_fut in read_futs {
+            let res = read_fut.await?;
+            read_res = read_res.and_then(|partial| Ok(partial + res?));
+        }
+        // Everything has arrived, and come back
+        let elapsed = started.elapsed();
 
         // Stop measuring once reading is complete
         info!("Stop measuring");
@@ -108,65 +446,70 @@ impl Sender {
 
         // Get the measurements and return them
         // if reading and writing were successful
-        let mset = mfut.await?;
+        let mut mset = mfut.await?;
+        // With an echoing receiver, everything sent comes back;
+        // otherwise there's nothing to check on our side
+        let echoed = !self.echo || self.received.load(Ordering::SeqCst) == sent_bytes;
+        let total_bytes = self.config.whole_total_bytes();
+        let completion = if !(write_res.is_ok() && echoed) {
+            "truncated"
+        } else if total_bytes.is_some_and(|total| sent_bytes != total) {
+            "incomplete"
+        } else {
+            "clean"
+        };
+        mset.metadata.insert("completion".into(), completion.into());
+        mset.set_warmup(self.config.warmup);
+        if let Some(total) = total_bytes {
+            mset.metadata
+                .insert("expected_bytes".into(), total.to_string());
+            if completion == "clean" {
+                mset.record_transfer(total, elapsed);
+            }
+        }
+        if let Ok(partial) = read_res {
+            mset.record_partial_chunk(partial);
+        }
+        if !self.echo {
+            mset.metadata.insert("echo".into(), "false".into());
+        }
+        if self.cancel.is_cancelled() {
+            mset.metadata.insert("cancelled".into(), "true".into());
+        }
+        self.data.close();
+        // Closing the control connection ends the session
+        drop(self.control);
         info!("End Sender::run");
         write_res.and(read_res).and(Ok(mset))
     }
 }
 
-/// Generate data and send it over the wire
-pub struct Generator {
-    length: Duration,
-    write_half: OwnedWriteHalf,
-    buf: Vec<u8>,
-    /// Counter for chunks sent
-    sent: Arc<AtomicU64>,
+/// Host part of a `host:port` address
+fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _port)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
 }
 
-impl Generator {
-    pub fn new(
-        length: Duration,
-        write_half: OwnedWriteHalf,
-        chunk_size: usize,
-        sent: Arc<AtomicU64>,
-    ) -> Self {
-        let mut buf = Vec::new();
-        buf.resize(chunk_size, 0);
-
-        Self {
-            length,
-            write_half,
-            buf,
-            sent,
-        }
-    }
-
-    #[instrument(name = "Generator::run", skip(self))]
-    pub async fn run(mut self) -> anyhow::Result<()> {
-        let start_time = Instant::now();
-        loop {
-            let now = Instant::now();
-            let elapsed = now - start_time;
-
-            if elapsed >= self.length {
-                break;
+/// Wait for the server to open a session, answering
+/// its challenge if it requires authentication
+async fn await_session(
+    control: &mut ControlChannel<BoxedStream>,
+    auth_token: Option<&str>,
+) -> anyhow::Result<(Uuid, bool)> {
+    loop {
+        match control.expect().await? {
+            ServerMessage::Session { session_id, echo } => return Ok((session_id, echo)),
+            ServerMessage::Challenge { nonce } => {
+                let token = auth_token.ok_or_else(|| {
+                    anyhow::anyhow!("server requires authentication, but no auth token was given")
+                })?;
+                let mac = access::respond(token, &nonce);
+                control.send(&ClientMessage::Auth { mac }).await?;
             }
-
-            // Generate random chunk of data
-            {
-                let mut rng = thread_rng();
-                rng.fill_bytes(&mut self.buf);
+            ServerMessage::Rejected { reason } => {
+                anyhow::bail!("server rejected session: {}", reason)
             }
-
-            // Send it over the wire
-            self.write_half.write_all(&self.buf).await?;
-            self.write_half.flush().await?;
-
-            // Increment counter
-            self.sent.fetch_add(1, Ordering::SeqCst);
+            ServerMessage::Busy { reason } => anyhow::bail!("server busy: {}", reason),
         }
-
-        info!("End Generator::run");
-        Ok(())
     }
 }
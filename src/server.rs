@@ -0,0 +1,610 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, info_span, instrument, warn, Instrument};
+use uuid::Uuid;
+
+use crate::{
+    access::{self, AccessConfig},
+    control::{self, ClientMessage, ControlChannel, ServerMessage},
+    limits::{Limits, RateLimiter},
+    measurement::MeasurementSet,
+    quic,
+    receiver::{Receiver, ReceiverConfig},
+    store::Store,
+    stream::BoxedStream,
+    tls,
+    transport::{Listener, Peer},
+};
+
+/// How long a new control connection has to complete the
+/// TLS handshake (if any) and authenticate
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a new data connection has to complete the
+/// TLS handshake (if any) and identify its session
+const SESSION_ID_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the client's end-of-test marker
+/// after its data streams have ended
+const END_OF_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Leeway for connection setup and the end
+/// of the test when enforcing the maximum duration
+const DURATION_GRACE: Duration = Duration::from_secs(5);
+
+pub type SharedStore = Arc<Mutex<Store>>;
+
+/// A test session, set up over the control channel
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: Uuid,
+    /// Address of the client's control connection
+    pub peer: Peer,
+    /// W3C trace context of the client
+    pub trace_context: HashMap<String, String>,
+    /// Bytes per chunk requested by the client
+    pub chunk_size: usize,
+    /// Planned length of the test; zero for a fixed amount of data
+    pub duration: Duration,
+    /// Fixed amount of data the client will send, if any
+    pub total_bytes: Option<u64>,
+    /// Number of concurrent data streams (only QUIC supports several)
+    pub streams: usize,
+    /// Whether a data connection has been opened for this session
+    pub claimed: bool,
+    /// Bytes the client sent, once it has marked the end of the test
+    pub done: watch::Receiver<Option<u64>>,
+}
+
+/// Sessions with an open control connection
+#[derive(Debug, Clone, Default)]
+pub struct Sessions(Arc<Mutex<HashMap<Uuid, Session>>>);
+
+impl Sessions {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, Session>> {
+        self.0.lock().expect("sessions mutex poisoned")
+    }
+
+    pub fn insert(&self, session: Session) {
+        self.lock().insert(session.id, session);
+    }
+
+    /// Insert a session unless that would exceed the session limits,
+    /// returning the reason if it's refused
+    pub fn admit(&self, session: Session, limits: &Limits) -> Result<(), String> {
+        let mut sessions = self.lock();
+        if let Some(max) = limits.max_sessions {
+            if sessions.len() >= max {
+                return Err(format!("{} sessions already running", sessions.len()));
+            }
+        }
+        if let Some(max) = limits.max_sessions_per_client {
+            let host = session.peer.host();
+            let count = sessions.values().filter(|s| s.peer.host() == host).count();
+            if count >= max {
+                return Err(format!("{} sessions already running from {}", count, host));
+            }
+        }
+        sessions.insert(session.id, session);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Session> {
+        self.lock().get(id).cloned()
+    }
+
+    /// Get a session for a new data connection; each
+    /// session may only be used for one data connection.
+    /// (With QUIC, all of a session's streams share one connection.)
+    pub fn claim(&self, id: &Uuid) -> Option<Session> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(id).filter(|session| !session.claimed)?;
+        session.claimed = true;
+        Some(session.clone())
+    }
+
+    pub fn remove(&self, id: &Uuid) -> Option<Session> {
+        self.lock().remove(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// State shared by all of the server's connections
+#[derive(Clone)]
+pub struct ServerState {
+    pub config: ReceiverConfig,
+    pub sessions: Sessions,
+    /// Optionally record each run
+    pub store: Option<SharedStore>,
+    /// Encrypt control and data connections
+    pub tls: Option<TlsAcceptor>,
+    /// Who may connect, and whether they must authenticate
+    pub access: AccessConfig,
+    /// Caps on sessions and the resources they use
+    pub limits: Limits,
+    /// Print and plot each finished run
+    pub report: bool,
+    /// Shared by all receivers, if bandwidth is limited
+    rate_limit: Option<Arc<RateLimiter>>,
+}
+
+impl ServerState {
+    pub fn new(config: ReceiverConfig, store: Option<SharedStore>) -> Self {
+        Self {
+            config,
+            sessions: Sessions::default(),
+            store,
+            tls: None,
+            access: AccessConfig::default(),
+            limits: Limits::default(),
+            report: true,
+            rate_limit: None,
+        }
+    }
+
+    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    pub fn with_access(mut self, access: AccessConfig) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Don't print or plot finished runs, e.g. when the
+    /// client in the same process reports them instead
+    pub fn without_report(mut self) -> Self {
+        self.report = false;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.rate_limit = limits.rate_limiter().map(Arc::new);
+        self.limits = limits;
+        self
+    }
+}
+
+/// Check a peer against the access lists; local
+/// peers (e.g. over a Unix socket) are always allowed
+fn permits(access: &AccessConfig, peer: &Peer) -> bool {
+    peer.ip().is_none_or(|ip| access.permits(ip))
+}
+
+#[instrument(skip(listener, state))]
+pub async fn listen_control<L: Listener>(
+    mut listener: L,
+    state: ServerState,
+) -> anyhow::Result<()> {
+    info!(
+        "Listening on {} control port {}",
+        listener.name(),
+        listener.local_addr()?
+    );
+
+    while let Ok((stream, peer)) = listener.accept().await {
+        tokio::spawn(handle_control(stream, peer, state.clone()));
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(stream, state))]
+async fn handle_control(stream: BoxedStream, addr: Peer, state: ServerState) {
+    info!("Handling control connection from {}", addr);
+
+    if !permits(&state.access, &addr) {
+        warn!("rejecting control connection from {}: not allowed", addr);
+        return;
+    }
+
+    let (mut channel, session_id, done) = match tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        open_session(stream, addr.clone(), &state),
+    )
+    .await
+    {
+        Ok(Ok(opened)) => opened,
+        Ok(Err(err)) => {
+            warn!("control error: {}", err);
+            return;
+        }
+        Err(_elapsed) => {
+            warn!("timed out setting up a session with {}", addr);
+            return;
+        }
+    };
+
+    // The session lasts as long as the control connection
+    loop {
+        match channel.recv::<ClientMessage>().await {
+            Ok(Some(ClientMessage::Done { sent_bytes })) => {
+                info!("Session {} sent {} bytes", session_id, sent_bytes);
+                done.send_replace(Some(sent_bytes));
+            }
+            Ok(Some(msg)) => warn!("unexpected control message: {:?}", msg),
+            Ok(None) => break,
+            Err(err) => {
+                warn!("control error: {}", err);
+                break;
+            }
+        }
+    }
+
+    state.sessions.remove(&session_id);
+    info!("Session {} closed", session_id);
+}
+
+/// Complete the TLS handshake (if enabled), authenticate
+/// the client (if required) and set up a session, returning
+/// the sender for the session's end-of-test marker
+async fn open_session(
+    stream: BoxedStream,
+    addr: Peer,
+    state: &ServerState,
+) -> anyhow::Result<(
+    ControlChannel<BoxedStream>,
+    Uuid,
+    watch::Sender<Option<u64>>,
+)> {
+    let stream = tls::accept(state.tls.as_ref(), stream).await?;
+    let mut channel = ControlChannel::new(stream);
+
+    let (trace_context, chunk_size, duration_ms, streams, total_bytes) =
+        match channel.expect().await? {
+            ClientMessage::Hello {
+                trace_context,
+                chunk_size,
+                duration_ms,
+                streams,
+                total_bytes,
+            } => (trace_context, chunk_size, duration_ms, streams, total_bytes),
+            msg => anyhow::bail!("expected hello, got {:?}", msg),
+        };
+
+    if let Some(token) = &state.access.token {
+        if let Err(err) = authenticate(&mut channel, token).await {
+            let reason = "authentication failed".to_string();
+            channel.send(&ServerMessage::Rejected { reason }).await.ok();
+            return Err(err.context(format!("authentication of {} failed", addr)));
+        }
+    }
+
+    let duration = Duration::from_millis(duration_ms);
+    if let Err(reason) = state
+        .limits
+        .check_request(chunk_size, duration, streams, total_bytes)
+    {
+        channel
+            .send(&ServerMessage::Rejected {
+                reason: reason.clone(),
+            })
+            .await
+            .ok();
+        anyhow::bail!("rejected session from {}: {}", addr, reason);
+    }
+
+    let (done_send, done) = watch::channel(None);
+    let session = Session {
+        id: Uuid::new_v4(),
+        peer: addr.clone(),
+        trace_context,
+        chunk_size,
+        duration,
+        total_bytes,
+        streams,
+        claimed: false,
+        done,
+    };
+    let session_id = session.id;
+    if let Err(reason) = state.sessions.admit(session, &state.limits) {
+        channel
+            .send(&ServerMessage::Busy {
+                reason: reason.clone(),
+            })
+            .await
+            .ok();
+        anyhow::bail!("server busy, refused session from {}: {}", addr, reason);
+    }
+
+    let echo = state.config.echo;
+    channel
+        .send(&ServerMessage::Session { session_id, echo })
+        .await?;
+    info!("Session {} opened", session_id);
+
+    Ok((channel, session_id, done_send))
+}
+
+/// Challenge the client to prove it knows the pre-shared token
+async fn authenticate(
+    channel: &mut ControlChannel<BoxedStream>,
+    token: &str,
+) -> anyhow::Result<()> {
+    let nonce = access::nonce();
+    channel
+        .send(&ServerMessage::Challenge {
+            nonce: nonce.clone(),
+        })
+        .await?;
+
+    match channel.expect().await? {
+        ClientMessage::Auth { mac } if access::verify(token, &nonce, &mac) => Ok(()),
+        ClientMessage::Auth { .. } => anyhow::bail!("wrong token"),
+        msg => anyhow::bail!("expected auth, got {:?}", msg),
+    }
+}
+
+#[instrument(skip(listener, state))]
+pub async fn listen_data<L: Listener>(mut listener: L, state: ServerState) -> anyhow::Result<()> {
+    info!(
+        "Listening on {} data port {}",
+        listener.name(),
+        listener.local_addr()?
+    );
+
+    let transport = listener.name();
+    while let Ok((stream, peer)) = listener.accept().await {
+        tokio::spawn(handle_data(stream, peer, transport, state.clone()));
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(stream, state))]
+async fn handle_data(stream: BoxedStream, addr: Peer, transport: &'static str, state: ServerState) {
+    info!("Handling data connection from {}", addr);
+
+    if !permits(&state.access, &addr) {
+        warn!("rejecting data connection from {}: not allowed", addr);
+        return;
+    }
+
+    let (stream, session_id) =
+        match tokio::time::timeout(SESSION_ID_TIMEOUT, identify_data(stream, &state)).await {
+            Ok(Ok(identified)) => identified,
+            Ok(Err(err)) => {
+                warn!("failed to read session id from {}: {}", addr, err);
+                return;
+            }
+            Err(_elapsed) => {
+                warn!("timed out waiting for session id from {}", addr);
+                return;
+            }
+        };
+
+    let session = match state.sessions.claim(&session_id) {
+        Some(session) => session,
+        None => {
+            warn!(
+                "rejecting data connection from {}: unknown or already used session {}",
+                addr, session_id
+            );
+            return;
+        }
+    };
+
+    if session.streams != 1 {
+        warn!(
+            "rejecting data connection from {}: session {} wants {} streams over TCP",
+            addr, session.id, session.streams
+        );
+        return;
+    }
+
+    let receiver = Receiver::new(stream, session_config(&state, &session))
+        .with_peer(addr.to_string())
+        .with_transport(transport)
+        .with_encryption(state.tls.is_some())
+        .with_host_internal(matches!(addr, Peer::Local(_)))
+        .with_rate_limit(state.rate_limit.clone());
+    run_session(receiver, &session, &state).await;
+}
+
+/// Receiver configuration for a session
+fn session_config(state: &ServerState, session: &Session) -> ReceiverConfig {
+    ReceiverConfig {
+        chunk_size: session.chunk_size,
+        ..state.config.clone()
+    }
+}
+
+/// Run a session's test, then report and store the results
+async fn run_session(receiver: Receiver, session: &Session, state: &ServerState) {
+    // Continue the client's trace
+    let span = info_span!("session", session_id = %session.id);
+    control::set_remote_parent(&span, &session.trace_context);
+
+    let cancel = CancellationToken::new();
+    let run = receiver.with_cancel(cancel.clone()).run().instrument(span);
+    tokio::pin!(run);
+
+    // Cut off clients that run past the maximum duration
+    let max_duration = state.limits.max_duration.map(|max| max + DURATION_GRACE);
+    let result = match max_duration {
+        Some(max) => tokio::select! {
+            result = &mut run => result,
+            _ = tokio::time::sleep(max) => {
+                warn!("session {} exceeded the maximum duration", session.id);
+                cancel.cancel();
+                run.await
+            }
+        },
+        None => run.await,
+    };
+
+    match result {
+        Ok(mut mset) => {
+            let completion = if cancel.is_cancelled() {
+                "truncated"
+            } else {
+                completion(&mset, session).await
+            };
+            mset.metadata.insert("completion".into(), completion.into());
+            if let Some(total) = session.total_bytes {
+                mset.metadata
+                    .insert("expected_bytes".into(), total.to_string());
+            }
+            if state.report {
+                mset.print();
+                mset.plot();
+            }
+            if let Some(store) = state.store.clone() {
+                if let Err(err) = save_run(store, mset).await {
+                    error!("failed to store run: {}", err);
+                }
+            }
+        }
+        Err(err) => {
+            error!("data error: {}", err);
+        }
+    }
+}
+
+/// Whether the session ended cleanly: the client marked the end
+/// of the test, and everything it sent has been received, which
+/// for a fixed amount of data must be all that was announced
+async fn completion(mset: &MeasurementSet, session: &Session) -> &'static str {
+    let mut done = session.done.clone();
+    tokio::time::timeout(END_OF_TEST_TIMEOUT, done.wait_for(Option::is_some))
+        .await
+        .ok();
+    let sent_bytes = *done.borrow();
+
+    let received_bytes = mset.measurements.last().map_or(0, |m| m.received_bytes);
+    match sent_bytes {
+        Some(sent_bytes) if sent_bytes == received_bytes => match session.total_bytes {
+            Some(total) if received_bytes != total => {
+                warn!(
+                    "session {} incomplete: received {} of the expected {} bytes",
+                    session.id, received_bytes, total
+                );
+                "incomplete"
+            }
+            _ => "clean",
+        },
+        Some(sent_bytes) => {
+            warn!(
+                "session {} truncated: received {} of {} bytes",
+                session.id, received_bytes, sent_bytes
+            );
+            "truncated"
+        }
+        None => {
+            warn!(
+                "session {} truncated: no end-of-test marker after {} bytes",
+                session.id, received_bytes
+            );
+            "truncated"
+        }
+    }
+}
+
+/// Complete the TLS handshake (if enabled) and read the session id
+async fn identify_data(
+    stream: BoxedStream,
+    state: &ServerState,
+) -> std::io::Result<(BoxedStream, Uuid)> {
+    let mut stream = tls::accept(state.tls.as_ref(), stream).await?;
+    let session_id = control::read_session_id(&mut stream).await?;
+    Ok((stream, session_id))
+}
+
+#[instrument(skip(endpoint, state))]
+pub async fn listen_quic(endpoint: quinn::Endpoint, state: ServerState) -> anyhow::Result<()> {
+    info!("Listening for QUIC on data port {}", endpoint.local_addr()?);
+
+    while let Some(incoming) = endpoint.accept().await {
+        let addr = incoming.remote_address();
+        if !state.access.permits(addr.ip()) {
+            warn!("rejecting QUIC connection from {}: not allowed", addr);
+            incoming.refuse();
+            continue;
+        }
+        tokio::spawn(handle_quic(incoming, addr, state.clone()));
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(incoming, state))]
+async fn handle_quic(incoming: quinn::Incoming, addr: SocketAddr, state: ServerState) {
+    info!("Handling QUIC connection from {}", addr);
+
+    let identified = tokio::time::timeout(SESSION_ID_TIMEOUT, identify_quic(incoming, &state));
+    let (conn, session, streams) = match identified.await {
+        Ok(Ok(identified)) => identified,
+        Ok(Err(err)) => {
+            warn!("failed to set up QUIC session with {}: {}", addr, err);
+            return;
+        }
+        Err(_elapsed) => {
+            warn!("timed out waiting for QUIC streams from {}", addr);
+            return;
+        }
+    };
+
+    let stats_conn = conn.clone();
+    let receiver = Receiver::multi(streams, session_config(&state, &session))
+        .with_peer(addr.to_string())
+        .with_transport("quic")
+        .with_encryption(true)
+        .with_rate_limit(state.rate_limit.clone())
+        .with_transport_stats(move || Some(quic::stats(&stats_conn)));
+    run_session(receiver, &session, &state).await;
+
+    // Let the client read the last echoed data before the connection goes
+    tokio::time::timeout(DURATION_GRACE, conn.closed())
+        .await
+        .ok();
+}
+
+/// Complete the QUIC handshake and accept all of the session's streams,
+/// each of which must start with the same session id
+async fn identify_quic(
+    incoming: quinn::Incoming,
+    state: &ServerState,
+) -> anyhow::Result<(quinn::Connection, Session, Vec<BoxedStream>)> {
+    let conn = incoming.await?;
+
+    let mut first = quic::accept_stream(&conn).await?;
+    let session_id = control::read_session_id(&mut first).await?;
+    let session = state
+        .sessions
+        .claim(&session_id)
+        .ok_or_else(|| anyhow::anyhow!("unknown or already used session {}", session_id))?;
+
+    let mut streams: Vec<BoxedStream> = vec![Box::new(first)];
+    while streams.len() < session.streams {
+        let mut stream = quic::accept_stream(&conn).await?;
+        let id = control::read_session_id(&mut stream).await?;
+        anyhow::ensure!(id == session_id, "stream for a different session {}", id);
+        streams.push(Box::new(stream));
+    }
+
+    Ok((conn, session, streams))
+}
+
+async fn save_run(store: SharedStore, mset: MeasurementSet) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut store = store.lock().expect("store mutex poisoned");
+        store.insert(&mset)
+    })
+    .await?
+}
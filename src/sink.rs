@@ -0,0 +1,75 @@
+//! Consumers of live measurements.
+//!
+//! The measurer hands each measurement to its sinks as soon as it's
+//! taken: printing it, pushing it as metrics, or broadcasting it to
+//! subscribers such as a UI or a live threshold check.
+
+use std::{future::Future, pin::Pin};
+
+use tokio::sync::broadcast;
+
+use crate::measurement::{Measurement, MeasurementSet};
+
+/// Measurements a subscriber may fall behind by before missing some
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// Receives each measurement as it's taken
+pub trait MeasurementSink: Send {
+    /// Handle a new measurement, the latest of `mset`; this runs on
+    /// the measurer's task, so it should return quickly
+    fn record(&mut self, mset: &MeasurementSet, measurement: &Measurement);
+
+    /// Wait for any work still in flight, once measuring has stopped
+    fn finish(self: Box<Self>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async {})
+    }
+}
+
+impl<S: MeasurementSink + ?Sized> MeasurementSink for Box<S> {
+    fn record(&mut self, mset: &MeasurementSet, measurement: &Measurement) {
+        (**self).record(mset, measurement);
+    }
+
+    fn finish(self: Box<Self>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        (*self).finish()
+    }
+}
+
+/// Prints each measurement to stdout
+pub struct PrintSink;
+
+impl MeasurementSink for PrintSink {
+    fn record(&mut self, _mset: &MeasurementSet, measurement: &Measurement) {
+        measurement.print();
+    }
+}
+
+/// Broadcasts each measurement to any number of subscribers
+#[derive(Clone)]
+pub struct BroadcastSink(broadcast::Sender<Measurement>);
+
+impl BroadcastSink {
+    pub fn new() -> Self {
+        Self(broadcast::channel(BROADCAST_CAPACITY).0)
+    }
+
+    /// Receive measurements taken from now on. Subscribers that fall
+    /// too far behind miss the oldest ones (`RecvError::Lagged`), and
+    /// see the channel close once the run is over.
+    pub fn subscribe(&self) -> broadcast::Receiver<Measurement> {
+        self.0.subscribe()
+    }
+}
+
+impl Default for BroadcastSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeasurementSink for BroadcastSink {
+    fn record(&mut self, _mset: &MeasurementSet, measurement: &Measurement) {
+        // Failure only means that nobody is subscribed
+        self.0.send(measurement.clone()).ok();
+    }
+}
@@ -0,0 +1,190 @@
+use std::{fs, path::Path, time::Duration};
+
+use crate::{measurement::MeasurementSet, stats};
+
+/// Service level objectives checked against a finished measurement set.
+/// Unset thresholds are not checked.
+#[derive(Debug, Default, Clone)]
+pub struct Thresholds {
+    /// Minimum mean received throughput (MB/s)
+    pub min_throughput: Option<f64>,
+    /// Maximum 99th percentile round-trip time
+    pub max_rtt_p99: Option<Duration>,
+    /// Maximum fraction of bytes lost (percent)
+    pub max_loss_pct: Option<f64>,
+    /// Maximum period without receiving any data
+    pub max_stall: Option<Duration>,
+}
+
+/// Outcome of checking a single threshold
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub unit: &'static str,
+    /// Human-readable comparison, e.g. ">= 10"
+    pub expected: String,
+    pub actual: f64,
+    pub passed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SloReport {
+    pub checks: Vec<Check>,
+}
+
+impl Thresholds {
+    pub fn is_empty(&self) -> bool {
+        self.min_throughput.is_none()
+            && self.max_rtt_p99.is_none()
+            && self.max_loss_pct.is_none()
+            && self.max_stall.is_none()
+    }
+
+    pub fn check(&self, mset: &MeasurementSet) -> SloReport {
+        let mut checks = Vec::new();
+
+        if let Some(min) = self.min_throughput {
+            let actual = mset.mean_throughput() / 1e6;
+            checks.push(Check {
+                name: "throughput",
+                unit: "MB/s",
+                expected: format!(">= {}", min),
+                actual,
+                passed: actual >= min,
+            });
+        }
+
+        if let Some(max) = self.max_rtt_p99 {
+            let max_ms = max.as_secs_f64() * 1e3;
+            // No RTT samples means nothing was ever echoed back
+            let actual = stats::percentile(&mset.rtt(), 99.0)
+                .map(|s| s * 1e3)
+                .unwrap_or(f64::INFINITY);
+            checks.push(Check {
+                name: "rtt_p99",
+                unit: "ms",
+                expected: format!("<= {}", max_ms),
+                actual,
+                passed: actual <= max_ms,
+            });
+        }
+
+        if let Some(max) = self.max_loss_pct {
+            let actual = 100.0 * mset.loss();
+            checks.push(Check {
+                name: "loss",
+                unit: "%",
+                expected: format!("<= {}", max),
+                actual,
+                passed: actual <= max,
+            });
+        }
+
+        if let Some(max) = self.max_stall {
+            let actual = mset.longest_stall().as_secs_f64() * 1e3;
+            let max_ms = max.as_secs_f64() * 1e3;
+            checks.push(Check {
+                name: "stall",
+                unit: "ms",
+                expected: format!("<= {}", max_ms),
+                actual,
+                passed: actual <= max_ms,
+            });
+        }
+
+        SloReport { checks }
+    }
+}
+
+impl SloReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| !c.passed).count()
+    }
+
+    pub fn print(&self) {
+        println!("SLO checks");
+        for check in &self.checks {
+            let status = if check.passed { "PASS" } else { "FAIL" };
+            println!(
+                "  {} {:<12} {:>12.3} {} (expected {})",
+                status, check.name, check.actual, check.unit, check.expected
+            );
+        }
+        println!();
+    }
+
+    /// Render the checks as a JUnit XML test suite,
+    /// with one test case per threshold.
+    pub fn to_junit(&self, suite: &str) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+            escape_xml(suite),
+            self.checks.len(),
+            self.failures()
+        ));
+        for check in &self.checks {
+            xml.push_str(&format!(
+                "  <testcase classname=\"{}\" name=\"{}\"",
+                escape_xml(suite),
+                check.name
+            ));
+            if check.passed {
+                xml.push_str("/>\n");
+            } else {
+                let message = format!(
+                    "{} was {:.3} {}, expected {} {}",
+                    check.name, check.actual, check.unit, check.expected, check.unit
+                );
+                xml.push_str(">\n");
+                xml.push_str(&format!(
+                    "    <failure message=\"{}\"/>\n",
+                    escape_xml(&message)
+                ));
+                xml.push_str("  </testcase>\n");
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+
+    pub fn write_junit(&self, suite: &str, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, self.to_junit(suite))?;
+        Ok(())
+    }
+}
+
+/// Write a JUnit XML report for a run which failed
+/// before any thresholds could be checked.
+pub fn write_junit_error(suite: &str, message: &str, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let suite = escape_xml(suite);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"1\" errors=\"1\">\n",
+        suite
+    ));
+    xml.push_str(&format!(
+        "  <testcase classname=\"{}\" name=\"run\">\n",
+        suite
+    ));
+    xml.push_str(&format!(
+        "    <error message=\"{}\"/>\n",
+        escape_xml(message)
+    ));
+    xml.push_str("  </testcase>\n");
+    xml.push_str("</testsuite>\n");
+    fs::write(path, xml)?;
+    Ok(())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,102 @@
+use statrs::distribution::{ContinuousCDF, Normal, StudentsT};
+
+/// Arithmetic mean (`NaN` for an empty sample)
+pub fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// Unbiased sample variance (`NaN` for fewer than two values)
+pub fn variance(xs: &[f64]) -> f64 {
+    let m = mean(xs);
+    xs.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (xs.len() as f64 - 1.0)
+}
+
+/// Nearest-rank percentile, with `p` in `[0, 100]`
+/// (`None` for an empty sample)
+pub fn percentile(xs: &[f64], p: f64) -> Option<f64> {
+    if xs.is_empty() {
+        return None;
+    }
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    let idx = rank.clamp(1, sorted.len()) - 1;
+    Some(sorted[idx])
+}
+
+/// Result of Welch's unequal-variances t-test
+#[derive(Debug, Clone, Copy)]
+pub struct WelchTest {
+    /// t statistic (positive when the second sample has the larger mean)
+    pub t: f64,
+    /// Welch–Satterthwaite degrees of freedom
+    pub df: f64,
+    /// Two-sided p-value
+    pub p_value: f64,
+}
+
+impl WelchTest {
+    /// Compare the means of two samples.
+    /// Returns `None` if either sample has fewer than two values
+    /// or both samples have zero variance.
+    pub fn new(a: &[f64], b: &[f64]) -> Option<Self> {
+        if a.len() < 2 || b.len() < 2 {
+            return None;
+        }
+
+        let (na, nb) = (a.len() as f64, b.len() as f64);
+        let (va, vb) = (variance(a) / na, variance(b) / nb);
+        let se2 = va + vb;
+        if se2 <= 0.0 {
+            return None;
+        }
+
+        let t = (mean(b) - mean(a)) / se2.sqrt();
+        let df = se2.powi(2) / (va.powi(2) / (na - 1.0) + vb.powi(2) / (nb - 1.0));
+        let dist = StudentsT::new(0.0, 1.0, df).ok()?;
+        let p_value = 2.0 * (1.0 - dist.cdf(t.abs()));
+
+        Some(Self { t, df, p_value })
+    }
+
+    pub fn is_significant(&self, alpha: f64) -> bool {
+        self.p_value < alpha
+    }
+}
+
+/// Result of a two-sided two-proportion z-test
+#[derive(Debug, Clone, Copy)]
+pub struct ProportionTest {
+    /// z statistic (positive when the second proportion is larger)
+    pub z: f64,
+    /// Two-sided p-value
+    pub p_value: f64,
+}
+
+impl ProportionTest {
+    /// Compare `xa / na` against `xb / nb`.
+    /// Returns `None` if either sample is empty
+    /// or the pooled proportion is 0 or 1.
+    pub fn new(xa: u64, na: u64, xb: u64, nb: u64) -> Option<Self> {
+        if na == 0 || nb == 0 {
+            return None;
+        }
+
+        let (xa, na, xb, nb) = (xa as f64, na as f64, xb as f64, nb as f64);
+        let pooled = (xa + xb) / (na + nb);
+        let se = (pooled * (1.0 - pooled) * (1.0 / na + 1.0 / nb)).sqrt();
+        if se <= 0.0 {
+            return None;
+        }
+
+        let z = (xb / nb - xa / na) / se;
+        let dist = Normal::new(0.0, 1.0).ok()?;
+        let p_value = 2.0 * (1.0 - dist.cdf(z.abs()));
+
+        Some(Self { z, p_value })
+    }
+
+    pub fn is_significant(&self, alpha: f64) -> bool {
+        self.p_value < alpha
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::measurement::{Measurement, MeasurementSet, TransportStats};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id TEXT PRIMARY KEY,
+    start_time_ms INTEGER NOT NULL,
+    peer TEXT,
+    chunk_size INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_start_time ON runs (start_time_ms);
+CREATE INDEX IF NOT EXISTS runs_peer ON runs (peer);
+
+CREATE TABLE IF NOT EXISTS samples (
+    run_id TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    dt_ns INTEGER NOT NULL,
+    sent INTEGER NOT NULL,
+    received INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS samples_run_id ON samples (run_id);
+
+-- Transport-level statistics of a sample, if any (e.g. QUIC)
+CREATE TABLE IF NOT EXISTS transport_stats (
+    run_id TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    dt_ns INTEGER NOT NULL,
+    rtt_ns INTEGER NOT NULL,
+    congestion_window INTEGER NOT NULL,
+    sent_packets INTEGER NOT NULL,
+    lost_packets INTEGER NOT NULL,
+    PRIMARY KEY (run_id, dt_ns)
+);
+
+-- Exact byte counts of a sample, including partial chunks
+CREATE TABLE IF NOT EXISTS sample_bytes (
+    run_id TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    dt_ns INTEGER NOT NULL,
+    sent_bytes INTEGER NOT NULL,
+    received_bytes INTEGER NOT NULL,
+    PRIMARY KEY (run_id, dt_ns)
+);
+
+CREATE TABLE IF NOT EXISTS metadata (
+    run_id TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (run_id, key)
+);
+";
+
+/// Criteria for selecting stored runs.
+/// Unset fields match every run.
+#[derive(Debug, Default, Clone)]
+pub struct RunFilter {
+    /// Peer address, either exactly or as a host with any port
+    pub peer: Option<String>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+/// Overview of a stored run, without its samples
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub id: Uuid,
+    pub start_time: SystemTime,
+    pub peer: Option<String>,
+    pub chunk_size: usize,
+    pub samples: u64,
+    /// Final sent counter
+    pub sent: u64,
+    /// Final received counter
+    pub received: u64,
+}
+
+impl RunSummary {
+    pub fn print(&self) {
+        println!(
+            "{}  {}  {:<24} {:>8} samples {:>10} sent / {:>10} received ({} B chunks)",
+            self.id,
+            humantime::format_rfc3339_seconds(self.start_time),
+            self.peer.as_deref().unwrap_or("-"),
+            self.samples,
+            self.sent,
+            self.received,
+            self.chunk_size,
+        );
+    }
+}
+
+/// Run history persisted in a local SQLite database
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Open (or create) the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    pub fn insert(&mut self, mset: &MeasurementSet) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO runs (id, start_time_ms, peer, chunk_size) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                mset.id.to_string(),
+                to_millis(mset.start_time),
+                mset.peer,
+                mset.chunk_size as i64
+            ],
+        )?;
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO samples (run_id, dt_ns, sent, received) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for m in &mset.measurements {
+                stmt.execute(params![
+                    mset.id.to_string(),
+                    m.dt.as_nanos() as i64,
+                    m.sent as i64,
+                    m.received as i64
+                ])?;
+            }
+
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO sample_bytes (run_id, dt_ns, sent_bytes, received_bytes)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for m in &mset.measurements {
+                stmt.execute(params![
+                    mset.id.to_string(),
+                    m.dt.as_nanos() as i64,
+                    m.sent_bytes as i64,
+                    m.received_bytes as i64
+                ])?;
+            }
+
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO transport_stats
+                    (run_id, dt_ns, rtt_ns, congestion_window, sent_packets, lost_packets)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for m in &mset.measurements {
+                if let Some(stats) = &m.transport {
+                    stmt.execute(params![
+                        mset.id.to_string(),
+                        m.dt.as_nanos() as i64,
+                        stats.rtt.as_nanos() as i64,
+                        stats.congestion_window as i64,
+                        stats.sent_packets as i64,
+                        stats.lost_packets as i64
+                    ])?;
+                }
+            }
+
+            let mut stmt =
+                tx.prepare("INSERT INTO metadata (run_id, key, value) VALUES (?1, ?2, ?3)")?;
+            for (key, value) in &mset.metadata {
+                stmt.execute(params![mset.id.to_string(), key, value])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// List runs matching `filter`, oldest first
+    pub fn list(&self, filter: &RunFilter) -> anyhow::Result<Vec<RunSummary>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT r.id, r.start_time_ms, r.peer, r.chunk_size,
+                    COUNT(s.run_id), COALESCE(MAX(s.sent), 0), COALESCE(MAX(s.received), 0)
+             FROM runs r LEFT JOIN samples s ON s.run_id = r.id
+             WHERE (?1 IS NULL OR r.peer = ?1 OR substr(r.peer, 1, length(?1) + 1) = ?1 || ':')
+               AND (?2 IS NULL OR r.start_time_ms >= ?2)
+               AND (?3 IS NULL OR r.start_time_ms <= ?3)
+             GROUP BY r.id
+             ORDER BY r.start_time_ms",
+        )?;
+
+        let rows = stmt.query_map(
+            params![
+                filter.peer,
+                filter.since.map(to_millis),
+                filter.until.map(to_millis)
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, i64>(6)?,
+                ))
+            },
+        )?;
+
+        let mut summaries = Vec::new();
+        for row in rows {
+            let (id, start_ms, peer, chunk_size, samples, sent, received) = row?;
+            summaries.push(RunSummary {
+                id: id.parse()?,
+                start_time: from_millis(start_ms),
+                peer,
+                chunk_size: chunk_size as usize,
+                samples: samples as u64,
+                sent: sent as u64,
+                received: received as u64,
+            });
+        }
+
+        Ok(summaries)
+    }
+
+    /// Load a complete run, or `None` if no run has this id
+    pub fn load(&self, id: Uuid) -> anyhow::Result<Option<MeasurementSet>> {
+        let run = self
+            .conn
+            .query_row(
+                "SELECT start_time_ms, peer, chunk_size FROM runs WHERE id = ?1",
+                params![id.to_string()],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let (start_ms, peer, chunk_size) = match run {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+
+        let mut mset = MeasurementSet::new(chunk_size as usize, false);
+        mset.id = id;
+        mset.start_time = from_millis(start_ms);
+        mset.peer = peer;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT s.dt_ns, s.sent, s.received,
+                    t.rtt_ns, t.congestion_window, t.sent_packets, t.lost_packets,
+                    b.sent_bytes, b.received_bytes
+             FROM samples s
+             LEFT JOIN transport_stats t ON t.run_id = s.run_id AND t.dt_ns = s.dt_ns
+             LEFT JOIN sample_bytes b ON b.run_id = s.run_id AND b.dt_ns = s.dt_ns
+             WHERE s.run_id = ?1
+             ORDER BY s.dt_ns",
+        )?;
+        let samples = stmt.query_map(params![id.to_string()], |row| {
+            let transport = match row.get::<_, Option<i64>>(3)? {
+                Some(rtt_ns) => Some(TransportStats {
+                    rtt: Duration::from_nanos(rtt_ns as u64),
+                    congestion_window: row.get::<_, i64>(4)? as u64,
+                    sent_packets: row.get::<_, i64>(5)? as u64,
+                    lost_packets: row.get::<_, i64>(6)? as u64,
+                }),
+                None => None,
+            };
+            let sent = row.get::<_, i64>(1)? as u64;
+            let received = row.get::<_, i64>(2)? as u64;
+            // Runs stored before byte counting only have whole chunks
+            let sent_bytes = row.get::<_, Option<i64>>(7)?;
+            let received_bytes = row.get::<_, Option<i64>>(8)?;
+            Ok(Measurement {
+                dt: Duration::from_nanos(row.get::<_, i64>(0)? as u64),
+                sent,
+                received,
+                sent_bytes: sent_bytes.map_or(sent * chunk_size as u64, |b| b as u64),
+                received_bytes: received_bytes.map_or(received * chunk_size as u64, |b| b as u64),
+                transport,
+            })
+        })?;
+        for sample in samples {
+            mset.measurements.push(sample?);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key, value FROM metadata WHERE run_id = ?1")?;
+        let entries = stmt.query_map(params![id.to_string()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for entry in entries {
+            let (key, value) = entry?;
+            mset.metadata.insert(key, value);
+        }
+
+        Ok(Some(mset))
+    }
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn from_millis(ms: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms.max(0) as u64)
+}
@@ -0,0 +1,38 @@
+use std::any::Any;
+#[cfg(unix)]
+use std::os::fd::{AsFd, BorrowedFd};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+#[cfg(unix)]
+use tokio::net::{TcpStream, UnixStream};
+
+/// A bidirectional byte stream, e.g. plain TCP or TLS over TCP
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {
+    /// The concrete stream, e.g. to reach the socket of plain TCP
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static> AsyncStream for S {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
+pub type StreamReadHalf = ReadHalf<BoxedStream>;
+pub type StreamWriteHalf = WriteHalf<BoxedStream>;
+
+pub fn split(stream: BoxedStream) -> (StreamReadHalf, StreamWriteHalf) {
+    tokio::io::split(stream)
+}
+
+/// The socket underneath a plain TCP or Unix stream, or
+/// `None` for other streams (e.g. TLS)
+#[cfg(unix)]
+pub fn socket_fd(stream: &BoxedStream) -> Option<BorrowedFd<'_>> {
+    let stream = stream.as_ref().as_any();
+    match stream.downcast_ref::<TcpStream>() {
+        Some(tcp) => Some(tcp.as_fd()),
+        None => stream.downcast_ref::<UnixStream>().map(AsFd::as_fd),
+    }
+}
@@ -0,0 +1,218 @@
+//! Optional TLS for the control and data connections.
+//!
+//! The server presents a certificate on both ports, and may require
+//! client certificates signed by a given CA (mutual TLS). The client
+//! either trusts the usual web PKI roots or only a pinned CA.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+use crate::stream::{AsyncStream, BoxedStream};
+
+/// Command-line TLS options for the server
+#[derive(clap::Args, Debug, Clone)]
+pub struct TlsServerArgs {
+    /// Certificate chain (PEM); enables TLS on the control and data ports
+    #[clap(long, requires = "tls-key", env = "SEISMIC_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// Private key (PEM) for --tls-cert
+    #[clap(long, requires = "tls-cert", env = "SEISMIC_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Require client certificates signed by this CA (PEM)
+    #[clap(long, requires = "tls-cert", env = "SEISMIC_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+impl From<TlsServerArgs> for Option<TlsServerConfig> {
+    fn from(args: TlsServerArgs) -> Self {
+        Some(TlsServerConfig {
+            cert: args.tls_cert?,
+            key: args.tls_key?,
+            client_ca: args.tls_client_ca,
+        })
+    }
+}
+
+/// Command-line TLS options for the client
+#[derive(clap::Args, Debug, Clone)]
+pub struct TlsClientArgs {
+    /// Encrypt the control and data connections with TLS
+    #[clap(long, env = "SEISMIC_TLS")]
+    pub tls: bool,
+    /// Only trust server certificates signed by this CA (PEM); implies --tls
+    #[clap(long, env = "SEISMIC_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+    /// Client certificate chain (PEM) for mutual TLS; implies --tls
+    #[clap(long, requires = "tls-key", env = "SEISMIC_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// Private key (PEM) for --tls-cert
+    #[clap(long, requires = "tls-cert", env = "SEISMIC_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Name to verify the server's certificate against (default: the target)
+    #[clap(long, env = "SEISMIC_TLS_SERVER_NAME")]
+    pub tls_server_name: Option<String>,
+}
+
+impl TlsClientArgs {
+    pub fn config(&self, target: &str) -> Option<TlsClientConfig> {
+        let enabled = self.tls || self.tls_ca.is_some() || self.tls_cert.is_some();
+        enabled.then(|| TlsClientConfig {
+            server_name: self
+                .tls_server_name
+                .clone()
+                .unwrap_or_else(|| target.to_string()),
+            ca: self.tls_ca.clone(),
+            identity: self.tls_cert.clone().zip(self.tls_key.clone()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsServerConfig {
+    /// Certificate chain (PEM)
+    pub cert: PathBuf,
+    /// Private key (PEM)
+    pub key: PathBuf,
+    /// Require client certificates signed by this CA
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsServerConfig {
+    pub fn rustls_config(&self) -> anyhow::Result<ServerConfig> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_key(&self.key)?;
+
+        let builder = ServerConfig::builder();
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(ca)?)).build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(builder.with_single_cert(certs, key)?)
+    }
+
+    pub fn acceptor(&self) -> anyhow::Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(self.rustls_config()?)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsClientConfig {
+    /// Name the server's certificate must be valid for
+    pub server_name: String,
+    /// Pinned CA; the web PKI roots are trusted otherwise
+    pub ca: Option<PathBuf>,
+    /// Certificate chain and private key for mutual TLS
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsClientConfig {
+    /// Trust the web PKI roots, without a client certificate
+    pub fn new(server_name: impl Into<String>) -> Self {
+        Self {
+            server_name: server_name.into(),
+            ca: None,
+            identity: None,
+        }
+    }
+
+    pub fn rustls_config(&self) -> anyhow::Result<ClientConfig> {
+        let roots = match &self.ca {
+            Some(ca) => load_roots(ca)?,
+            None => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        Ok(match &self.identity {
+            Some((cert, key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            None => builder.with_no_client_auth(),
+        })
+    }
+
+    pub fn connector(&self) -> anyhow::Result<TlsClient> {
+        let server_name = ServerName::try_from(self.server_name.clone())
+            .with_context(|| format!("invalid TLS server name {:?}", self.server_name))?;
+
+        Ok(TlsClient {
+            connector: TlsConnector::from(Arc::new(self.rustls_config()?)),
+            server_name,
+        })
+    }
+}
+
+/// Wraps outgoing connections in TLS
+#[derive(Clone)]
+pub struct TlsClient {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl TlsClient {
+    pub async fn connect<S: AsyncStream + 'static>(
+        &self,
+        stream: S,
+    ) -> std::io::Result<BoxedStream> {
+        let stream = self
+            .connector
+            .connect(self.server_name.clone(), stream)
+            .await?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Wrap an accepted connection in TLS, if enabled
+pub async fn accept(
+    acceptor: Option<&TlsAcceptor>,
+    stream: BoxedStream,
+) -> std::io::Result<BoxedStream> {
+    match acceptor {
+        Some(acceptor) => Ok(Box::new(acceptor.accept(stream).await?)),
+        None => Ok(stream),
+    }
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("failed to open certificate {:?}", path))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates found in {:?}", path);
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("failed to open private key {:?}", path))?,
+    );
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow::anyhow!("no private key found in {:?}", path))
+}
+
+fn load_roots(path: &Path) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
@@ -1,27 +1,346 @@
-use tracing::Level;
-use tracing_subscriber::prelude::*;
-
-pub fn init_tracing(service_name: &str, level: Level, jaeger: bool) -> anyhow::Result<()> {
-    let logging = tracing_subscriber::fmt::layer().pretty();
-
-    let filter = tracing_subscriber::filter::Targets::new()
-        .with_target("seismic", level)
-        .with_target("server", level)
-        .with_target("client", level);
-
-    let subscriber_base = tracing_subscriber::registry().with(logging).with(filter);
-
-    // Optionally send telemetry data to Jaeger
-    if jaeger {
-        let tracer = opentelemetry_jaeger::new_pipeline()
-            .with_service_name(service_name)
-            .install_simple()?;
-        let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-        let subscriber = subscriber_base.with(telemetry);
-        subscriber.try_init()?;
-    } else {
-        subscriber_base.try_init()?;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use opentelemetry::{
+    global,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self as sdktrace, BatchSpanProcessor, Sampler},
+        Resource,
+    },
+    trace::TracerProvider,
+    KeyValue,
+};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use tracing::{Level, Subscriber};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{fmt::MakeWriter, prelude::*, registry::LookupSpan, EnvFilter, Layer};
+
+use crate::parse_key_value;
+
+/// Where to send trace spans
+#[derive(Debug, Clone)]
+pub enum TraceExporter {
+    /// Jaeger agent on localhost (simple, unbatched pipeline)
+    Jaeger,
+    /// OTLP/gRPC collector
+    Otlp(OtlpConfig),
+}
+
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// Collector endpoint, e.g. `http://localhost:4317`
+    pub endpoint: String,
+    /// Fraction of root traces to sample (remote parents are respected)
+    pub sample_ratio: f64,
+    /// Extra resource attributes, in addition to `service.name`
+    pub resource: Vec<(String, String)>,
+    /// Maximum delay before exporting a batch of spans
+    pub batch_delay: Duration,
+    /// Maximum number of spans per exported batch
+    pub max_batch_size: usize,
+    /// Maximum number of spans buffered before new ones are dropped
+    pub max_queue_size: usize,
+}
+
+/// Format of log lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum LogFormat {
+    /// Multi-line, human-readable
+    Pretty,
+    /// Single-line, human-readable
+    Compact,
+    /// One JSON object per line, including span fields
+    Json,
+}
+
+/// How often to start a new log file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TracingConfig {
+    /// Default level for seismic's own targets
+    pub level: Level,
+    /// `RUST_LOG`-style filter directives, overriding `level`
+    pub filter: Option<String>,
+    /// Format of log lines on stderr
+    pub format: LogFormat,
+    /// Also write logs (as JSON) to rotating files
+    pub file: Option<LogFileConfig>,
+    pub exporter: Option<TraceExporter>,
+    /// Serve task instrumentation to tokio-console
+    pub console: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogFileConfig {
+    /// Log file path; rotated files get a date suffix
+    pub path: PathBuf,
+    pub rotation: LogRotation,
+}
+
+/// Command-line options for logging and tracing, accepted by every subcommand
+#[derive(clap::Args, Debug, Clone)]
+pub struct TracingArgs {
+    /// Print INFO statements (default is WARN+)
+    #[clap(short, global = true)]
+    pub verbose: bool,
+    /// Log filter directives, e.g. "seismic=debug,tokio=trace"
+    /// (defaults to $RUST_LOG, then to -v)
+    #[clap(long, global = true)]
+    pub log_filter: Option<String>,
+    /// Format of log lines on stderr
+    #[clap(long, arg_enum, default_value = "pretty", global = true)]
+    pub log_format: LogFormat,
+    /// Also write JSON logs to this file
+    #[clap(long, global = true)]
+    pub log_file: Option<PathBuf>,
+    /// How often to rotate the log file
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "daily",
+        requires = "log-file",
+        global = true
+    )]
+    pub log_rotation: LogRotation,
+    //// Enable tracing to Jaeger
+    #[clap(short, global = true)]
+    pub jaeger: bool,
+    /// Export traces to this OTLP/gRPC collector endpoint
+    #[clap(long, conflicts_with = "jaeger", global = true)]
+    pub otlp_traces: Option<String>,
+    /// Fraction of traces to sample with --otlp-traces
+    #[clap(long, default_value = "1.0", global = true)]
+    pub trace_sample_ratio: f64,
+    /// Extra trace resource attribute (key=value), may be repeated
+    #[clap(long, parse(try_from_str = parse_key_value), global = true)]
+    pub trace_resource: Vec<(String, String)>,
+    /// Maximum delay (ms) before exporting a batch of spans
+    #[clap(long, default_value = "5000", global = true)]
+    pub trace_batch_delay_ms: u64,
+    /// Maximum number of spans per exported batch
+    #[clap(long, default_value = "512", global = true)]
+    pub trace_batch_size: usize,
+    /// Maximum number of spans buffered for export
+    #[clap(long, default_value = "2048", global = true)]
+    pub trace_queue_size: usize,
+    /// Serve task instrumentation to tokio-console (on 127.0.0.1:6669)
+    #[clap(long, global = true)]
+    pub console: bool,
+}
+
+impl TracingArgs {
+    pub fn level(&self) -> Level {
+        if self.verbose {
+            Level::INFO
+        } else {
+            Level::WARN
+        }
+    }
+
+    pub fn exporter(&self) -> Option<TraceExporter> {
+        if self.jaeger {
+            return Some(TraceExporter::Jaeger);
+        }
+
+        self.otlp_traces.as_ref().map(|endpoint| {
+            TraceExporter::Otlp(OtlpConfig {
+                endpoint: endpoint.clone(),
+                sample_ratio: self.trace_sample_ratio,
+                resource: self.trace_resource.clone(),
+                batch_delay: Duration::from_millis(self.trace_batch_delay_ms),
+                max_batch_size: self.trace_batch_size,
+                max_queue_size: self.trace_queue_size,
+            })
+        })
+    }
+
+    pub fn config(&self) -> TracingConfig {
+        TracingConfig {
+            level: self.level(),
+            filter: self.log_filter.clone(),
+            format: self.log_format,
+            file: self.log_file.clone().map(|path| LogFileConfig {
+                path,
+                rotation: self.log_rotation,
+            }),
+            exporter: self.exporter(),
+            console: self.console,
+        }
+    }
+}
+
+/// Keeps background log and trace exporters running.
+/// Dropping it flushes buffered log lines and spans.
+pub struct TracingGuard {
+    _file_guard: Option<WorkerGuard>,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        global::shutdown_tracer_provider();
     }
+}
+
+pub fn init_tracing(service_name: &str, config: TracingConfig) -> anyhow::Result<TracingGuard> {
+    let filter = match &config.filter {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            // The library and the binary share the `seismic` target
+            EnvFilter::new(format!("seismic={}", config.level))
+        }),
+    };
+
+    let logging = fmt_layer(config.format, std::io::stderr, true);
+
+    let (file_logging, file_guard) = match &config.file {
+        Some(file) => {
+            let directory = match file.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let prefix = file
+                .path
+                .file_name()
+                .ok_or_else(|| anyhow::anyhow!("invalid log file path {:?}", file.path))?;
+            let appender = RollingFileAppender::new(file.rotation.into(), directory, prefix);
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (Some(fmt_layer(LogFormat::Json, writer, false)), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    // Propagate W3C trace context across the control channel
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // Optionally send telemetry data to Jaeger or an OTLP collector
+    let tracer = match config.exporter {
+        Some(TraceExporter::Jaeger) => Some(
+            opentelemetry_jaeger::new_pipeline()
+                .with_service_name(service_name)
+                .install_simple()?,
+        ),
+        Some(TraceExporter::Otlp(otlp)) => Some(otlp_tracer(service_name, otlp)?),
+        None => None,
+    };
+    let telemetry = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+
+    // The filter only applies to our own layers, so that the
+    // console layer still sees tokio's runtime instrumentation
+    let layers = logging
+        .and_then(file_logging)
+        .and_then(telemetry)
+        .with_filter(filter);
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(console_layer(config.console)?)
+        .try_init()?;
+
+    Ok(TracingGuard {
+        _file_guard: file_guard,
+    })
+}
+
+type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+#[cfg(feature = "console")]
+fn console_layer<S>(enabled: bool) -> anyhow::Result<Option<BoxedLayer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Ok(enabled.then(|| console_subscriber::ConsoleLayer::builder().spawn().boxed()))
+}
+
+#[cfg(not(feature = "console"))]
+fn console_layer<S>(enabled: bool) -> anyhow::Result<Option<BoxedLayer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    anyhow::ensure!(
+        !enabled,
+        "--console requires seismic to be built with the \"console\" feature"
+    );
+    Ok(None)
+}
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+fn otlp_tracer(service_name: &str, config: OtlpConfig) -> anyhow::Result<sdktrace::Tracer> {
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(config.endpoint),
+    )
+    .build_span_exporter()?;
+
+    let processor = BatchSpanProcessor::builder(exporter, opentelemetry::runtime::Tokio)
+        .with_scheduled_delay(config.batch_delay)
+        .with_max_export_batch_size(config.max_batch_size)
+        .with_max_queue_size(config.max_queue_size)
+        .build();
+
+    let mut attributes = vec![KeyValue::new("service.name", service_name.to_string())];
+    attributes.extend(
+        config
+            .resource
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value)),
+    );
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+
+    let provider = sdktrace::TracerProvider::builder()
+        .with_span_processor(processor)
+        .with_config(
+            sdktrace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(attributes)),
+        )
+        .build();
+
+    let tracer = provider.versioned_tracer("seismic", Some(env!("CARGO_PKG_VERSION")), None);
+    global::set_tracer_provider(provider);
 
-    Ok(())
+    Ok(tracer)
 }
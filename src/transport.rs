@@ -0,0 +1,316 @@
+//! Transports carrying the control and data connections.
+//!
+//! A `Connector` opens connections on the client side, and a `Listener`
+//! accepts them on the server side. Either way the result is a
+//! `BoxedStream`, so the sender, receiver and readers don't care whether
+//! the bytes go over TCP, a Unix domain socket or an in-memory pipe.
+
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+
+use tokio::{
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    sync::mpsc,
+};
+
+use crate::{measurement::TransportStats, stream::BoxedStream, tls::TlsClient};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// File names of the control and data sockets in a Unix socket directory
+pub const UNIX_CONTROL_SOCKET: &str = "control.sock";
+pub const UNIX_DATA_SOCKET: &str = "data.sock";
+
+/// Where a connection came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Peer {
+    /// A remote address, e.g. over TCP or QUIC
+    Inet(SocketAddr),
+    /// A process on the same host, e.g. over a Unix domain socket
+    Local(String),
+}
+
+impl Peer {
+    /// IP address of a remote peer; `None` for local ones
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Inet(addr) => Some(addr.ip()),
+            Peer::Local(_) => None,
+        }
+    }
+
+    /// The client's host, without the port; all local peers
+    /// of the same listener share one
+    pub fn host(&self) -> String {
+        match self {
+            Peer::Inet(addr) => addr.ip().to_string(),
+            Peer::Local(name) => name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Inet(addr) => write!(f, "{}", addr),
+            Peer::Local(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl From<SocketAddr> for Peer {
+    fn from(addr: SocketAddr) -> Self {
+        Peer::Inet(addr)
+    }
+}
+
+/// Opens connections to a server
+pub trait Connector: Send + Sync {
+    /// Name recorded in the run's metadata, e.g. "tcp"
+    fn name(&self) -> &'static str;
+
+    /// Address of the server, for the run's metadata
+    fn peer(&self) -> String;
+
+    fn connect(&self) -> BoxFuture<'_, io::Result<BoxedStream>>;
+
+    /// Whether connections are encrypted
+    fn is_encrypted(&self) -> bool {
+        false
+    }
+
+    /// Whether connections stay on this host
+    fn is_host_internal(&self) -> bool {
+        false
+    }
+
+    /// Current statistics of the underlying connection, if it keeps any
+    fn stats(&self) -> Option<TransportStats> {
+        None
+    }
+
+    /// Signal the end of the test, once all streams are done
+    fn close(&self) {}
+}
+
+/// Accepts connections from clients
+pub trait Listener: Send {
+    /// Name recorded in the run's metadata, e.g. "tcp"
+    fn name(&self) -> &'static str;
+
+    fn local_addr(&self) -> io::Result<String>;
+
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, Peer)>>;
+}
+
+/// TCP, optionally wrapped in TLS
+pub struct TcpConnector {
+    addr: String,
+    tls: Option<TlsClient>,
+}
+
+impl TcpConnector {
+    pub fn new(addr: impl Into<String>, tls: Option<TlsClient>) -> Self {
+        Self {
+            addr: addr.into(),
+            tls,
+        }
+    }
+}
+
+impl Connector for TcpConnector {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn peer(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn connect(&self) -> BoxFuture<'_, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let stream = TcpStream::connect(&self.addr).await?;
+            match &self.tls {
+                Some(tls) => tls.connect(stream).await,
+                None => Ok(Box::new(stream) as BoxedStream),
+            }
+        })
+    }
+
+    fn is_encrypted(&self) -> bool {
+        self.tls.is_some()
+    }
+}
+
+impl Listener for TcpListener {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        TcpListener::local_addr(self).map(|addr| addr.to_string())
+    }
+
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, Peer)>> {
+        Box::pin(async move {
+            let (stream, addr) = TcpListener::accept(self).await?;
+            Ok((Box::new(stream) as BoxedStream, Peer::Inet(addr)))
+        })
+    }
+}
+
+/// Unix domain socket, optionally wrapped in TLS
+pub struct UnixConnector {
+    path: PathBuf,
+    tls: Option<TlsClient>,
+}
+
+impl UnixConnector {
+    pub fn new(path: impl Into<PathBuf>, tls: Option<TlsClient>) -> Self {
+        Self {
+            path: path.into(),
+            tls,
+        }
+    }
+}
+
+impl Connector for UnixConnector {
+    fn name(&self) -> &'static str {
+        "unix"
+    }
+
+    fn is_host_internal(&self) -> bool {
+        true
+    }
+
+    fn peer(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn connect(&self) -> BoxFuture<'_, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let stream = UnixStream::connect(&self.path).await?;
+            match &self.tls {
+                Some(tls) => tls.connect(stream).await,
+                None => Ok(Box::new(stream) as BoxedStream),
+            }
+        })
+    }
+
+    fn is_encrypted(&self) -> bool {
+        self.tls.is_some()
+    }
+}
+
+impl Listener for UnixListener {
+    fn name(&self) -> &'static str {
+        "unix"
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        let addr = UnixListener::local_addr(self)?;
+        Ok(addr
+            .as_pathname()
+            .map_or_else(|| "(unnamed)".into(), |path| path.display().to_string()))
+    }
+
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, Peer)>> {
+        Box::pin(async move {
+            let (stream, _addr) = UnixListener::accept(self).await?;
+            Ok((Box::new(stream) as BoxedStream, Peer::Local("unix".into())))
+        })
+    }
+}
+
+/// Bind the control and data sockets in `dir`, replacing stale ones
+pub fn bind_unix(dir: &Path) -> io::Result<(UnixListener, UnixListener)> {
+    std::fs::create_dir_all(dir)?;
+    let bind = |name: &str| {
+        let path = dir.join(name);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        UnixListener::bind(path)
+    };
+    Ok((bind(UNIX_CONTROL_SOCKET)?, bind(UNIX_DATA_SOCKET)?))
+}
+
+/// An in-memory pipe between a connector and a listener in the same process
+pub fn duplex(name: &str, buffer_size: usize) -> (DuplexConnector, DuplexListener) {
+    let (tx, rx) = mpsc::channel(16);
+    let connector = DuplexConnector {
+        name: name.to_string(),
+        buffer_size,
+        tx,
+    };
+    let listener = DuplexListener {
+        name: name.to_string(),
+        rx,
+    };
+    (connector, listener)
+}
+
+pub struct DuplexConnector {
+    name: String,
+    buffer_size: usize,
+    tx: mpsc::Sender<tokio::io::DuplexStream>,
+}
+
+impl Connector for DuplexConnector {
+    fn name(&self) -> &'static str {
+        "duplex"
+    }
+
+    fn is_host_internal(&self) -> bool {
+        true
+    }
+
+    fn peer(&self) -> String {
+        self.name.clone()
+    }
+
+    fn connect(&self) -> BoxFuture<'_, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let (client, server) = tokio::io::duplex(self.buffer_size);
+            self.tx
+                .send(server)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "listener gone"))?;
+            Ok(Box::new(client) as BoxedStream)
+        })
+    }
+}
+
+pub struct DuplexListener {
+    name: String,
+    rx: mpsc::Receiver<tokio::io::DuplexStream>,
+}
+
+impl Listener for DuplexListener {
+    fn name(&self) -> &'static str {
+        "duplex"
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        Ok(self.name.clone())
+    }
+
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, Peer)>> {
+        Box::pin(async move {
+            let stream = self.rx.recv().await.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotConnected, "all connectors gone")
+            })?;
+            Ok((
+                Box::new(stream) as BoxedStream,
+                Peer::Local(self.name.clone()),
+            ))
+        })
+    }
+}
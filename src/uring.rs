@@ -0,0 +1,267 @@
+//! io_uring backend for the send and receive paths (Linux only,
+//! behind the `io-uring` cargo feature).
+//!
+//! Each stream's generator and reader drive a ring of their own on a
+//! dedicated thread. The generator's payload is a registered buffer,
+//! written with `WRITE_FIXED`, several writes at a time. The reader
+//! uses multishot receive into buffers provided to the kernel, so that
+//! one submission keeps receiving until the stream ends.
+
+use std::{
+    io,
+    os::fd::{AsRawFd, OwnedFd},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::{generator::batch_end, limits::RateLimiter};
+
+/// Submission queue entries per ring
+const QUEUE_DEPTH: u32 = 64;
+
+/// Writes of the payload kept in flight at once
+const WRITES_IN_FLIGHT: usize = 4;
+
+/// Size of each buffer provided for receiving
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of buffers provided for receiving
+const RECV_BUFFERS: u16 = 16;
+
+/// Group of the provided buffers
+const BUFFER_GROUP: u16 = 0;
+
+/// Tags telling completions apart; writes are tagged with their length
+const RECV: u64 = 1;
+const PROVIDE: u64 = 2;
+const POLL: u64 = u64::MAX;
+
+/// Run blocking I/O on a new thread, which inherits the
+/// caller's CPU affinity (e.g. of a pinned sender)
+pub async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    let (result_send, result) = oneshot::channel();
+    std::thread::Builder::new()
+        .name("seismic-uring".into())
+        .spawn(move || {
+            result_send.send(f()).ok();
+        })?;
+    Ok(result.await??)
+}
+
+/// Send `payload` over and over until `deadline` or until `total_bytes`
+/// have been sent (or until cancelled), then finish the chunk in
+/// progress, like `Generator::run`. Writes already in flight when the
+/// test ends are finished too.
+pub fn send(
+    socket: OwnedFd,
+    payload: Vec<u8>,
+    chunk_size: usize,
+    deadline: Option<Instant>,
+    total_bytes: Option<u64>,
+    cancel: &CancellationToken,
+    sent: Arc<AtomicU64>,
+) -> io::Result<()> {
+    let is_over =
+        || deadline.is_some_and(|deadline| Instant::now() >= deadline) || cancel.is_cancelled();
+    let mut ring = IoUring::new(QUEUE_DEPTH)?;
+    let iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut _,
+        iov_len: payload.len(),
+    };
+    // SAFETY: `payload` outlives the ring, and isn't modified
+    unsafe { ring.submitter().register_buffers(&[iov])? };
+    let fd = types::Fd(socket.as_raw_fd());
+
+    // Bytes to send in all, once known
+    let mut limit = total_bytes;
+    // Bytes written or being written
+    let mut requested: u64 = 0;
+    let mut in_flight = 0;
+    let mut stopping = false;
+    // Waiting for the socket to become writable
+    let mut blocked = false;
+    let mut polling = false;
+    loop {
+        if !stopping && is_over() {
+            stopping = true;
+            let end = requested.div_ceil(chunk_size as u64) * chunk_size as u64;
+            limit = Some(limit.map_or(end, |limit| limit.min(end)));
+        }
+
+        if blocked {
+            if !polling {
+                let poll = opcode::PollAdd::new(fd, libc::POLLOUT as u32)
+                    .build()
+                    .user_data(POLL);
+                // SAFETY: polling involves no buffers
+                unsafe { push(&mut ring, &poll)? };
+                polling = true;
+            }
+        } else {
+            while in_flight < WRITES_IN_FLIGHT {
+                let len = batch_end(payload.len(), limit.map(|limit| limit - requested));
+                if len == 0 {
+                    break;
+                }
+                // The payload is the same throughout, so each write
+                // can start at its beginning
+                let write = opcode::WriteFixed::new(fd, payload.as_ptr(), len as u32, 0)
+                    .build()
+                    .user_data(len as u64);
+                // SAFETY: the write only reads `payload`, which outlives the ring
+                unsafe { push(&mut ring, &write)? };
+                requested += len as u64;
+                in_flight += 1;
+            }
+        }
+        if in_flight == 0 && !polling {
+            return Ok(());
+        }
+
+        wait(&ring)?;
+        let completions: Vec<_> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (tag, result) in completions {
+            if tag == POLL {
+                if result < 0 {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                polling = false;
+                blocked = false;
+                continue;
+            }
+
+            in_flight -= 1;
+            let nbytes = match result {
+                err if err == -libc::EAGAIN => {
+                    blocked = true;
+                    0
+                }
+                err if err < 0 => return Err(io::Error::from_raw_os_error(-err)),
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                nbytes => nbytes as u64,
+            };
+            sent.fetch_add(nbytes, Ordering::Relaxed);
+            // What wasn't written is asked for again
+            requested -= tag - nbytes;
+        }
+    }
+}
+
+/// Receive until the stream ends, counting the bytes, and taking
+/// them from `rate_limit` (if any) as they arrive. Returns the total.
+pub fn receive(
+    socket: OwnedFd,
+    received: Arc<AtomicU64>,
+    rate_limit: Option<Arc<RateLimiter>>,
+) -> io::Result<u64> {
+    let mut total = 0;
+    let mut ring = IoUring::new(QUEUE_DEPTH)?;
+    let mut buffers = vec![0; RECV_BUFFER_SIZE * RECV_BUFFERS as usize];
+    let fd = types::Fd(socket.as_raw_fd());
+
+    let provide_all = opcode::ProvideBuffers::new(
+        buffers.as_mut_ptr(),
+        RECV_BUFFER_SIZE as i32,
+        RECV_BUFFERS,
+        BUFFER_GROUP,
+        0,
+    )
+    .build()
+    .user_data(PROVIDE);
+    let recv = opcode::RecvMulti::new(fd, BUFFER_GROUP)
+        .build()
+        .user_data(RECV);
+    // SAFETY: `buffers` outlives the ring
+    unsafe {
+        push(&mut ring, &provide_all)?;
+        push(&mut ring, &recv)?;
+    }
+
+    loop {
+        wait(&ring)?;
+        let completions: Vec<_> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result(), cqe.flags()))
+            .collect();
+        for (tag, result, flags) in completions {
+            if tag == PROVIDE {
+                if result < 0 {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                continue;
+            }
+
+            if result > 0 {
+                total += result as u64;
+                received.fetch_add(result as u64, Ordering::Relaxed);
+                // Holding on to the buffer holds up the next receives
+                if let Some(rate_limit) = &rate_limit {
+                    rate_limit.acquire_blocking(result as usize);
+                }
+            }
+            // Give the buffer back for the next receive
+            if let Some(id) = cqueue::buffer_select(flags) {
+                let offset = id as usize * RECV_BUFFER_SIZE;
+                let provide = opcode::ProvideBuffers::new(
+                    buffers[offset..].as_mut_ptr(),
+                    RECV_BUFFER_SIZE as i32,
+                    1,
+                    BUFFER_GROUP,
+                    id,
+                )
+                .build()
+                .user_data(PROVIDE);
+                // SAFETY: as above
+                unsafe { push(&mut ring, &provide)? };
+            }
+
+            match result {
+                // The stream ended
+                0 => return Ok(total),
+                // Like `SimpleReader`, a reset ends the stream cleanly
+                err if err == -libc::ECONNRESET => return Ok(total),
+                // Out of buffers: some were just given back
+                err if err == -libc::ENOBUFS => {}
+                err if err < 0 => return Err(io::Error::from_raw_os_error(-err)),
+                _ => {}
+            }
+            if !cqueue::more(flags) {
+                // SAFETY: as above
+                unsafe { push(&mut ring, &recv)? };
+            }
+        }
+    }
+}
+
+/// Queue an entry for submission
+///
+/// # Safety
+///
+/// Any buffers `entry` refers to must stay valid until it completes.
+unsafe fn push(ring: &mut IoUring, entry: &squeue::Entry) -> io::Result<()> {
+    ring.submission()
+        .push(entry)
+        .map_err(|_| io::Error::other("io_uring submission queue is full"))
+}
+
+/// Submit queued entries and wait for at least one completion
+fn wait(ring: &IoUring) -> io::Result<()> {
+    loop {
+        match ring.submit_and_wait(1) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result.map(drop),
+        }
+    }
+}
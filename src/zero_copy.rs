@@ -0,0 +1,92 @@
+//! Zero-copy sending with `sendfile(2)` (Linux only).
+//!
+//! The payload is written once to an in-memory file, from which the
+//! kernel copies it straight into the socket, without passing each
+//! batch through userspace. Only plain TCP and Unix sockets qualify;
+//! TLS has to encrypt in userspace anyway.
+
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use tokio::io::unix::AsyncFd;
+
+use crate::stream::{self, BoxedStream};
+
+/// The socket underneath a plain stream
+pub struct Socket(AsyncFd<OwnedFd>);
+
+impl Socket {
+    /// The socket of `stream`, or `None` if it isn't a plain socket
+    pub fn of(stream: &BoxedStream) -> io::Result<Option<Self>> {
+        let fd = match stream::socket_fd(stream) {
+            Some(fd) => fd,
+            None => return Ok(None),
+        };
+        // A duplicate of the socket can be registered with the
+        // runtime separately from the stream itself
+        let fd = AsyncFd::new(fd.try_clone_to_owned()?)?;
+        Ok(Some(Self(fd)))
+    }
+}
+
+/// Sends bytes of a fixed payload with `sendfile`
+pub struct ZeroCopySender {
+    socket: Socket,
+    payload: File,
+}
+
+impl ZeroCopySender {
+    pub fn new(socket: Socket, payload: &[u8]) -> io::Result<Self> {
+        let mut file = memfd("seismic-payload")?;
+        file.write_all(payload)?;
+        Ok(Self {
+            socket,
+            payload: file,
+        })
+    }
+
+    /// Send up to `len` bytes of the payload from `offset`,
+    /// returning how many were sent
+    pub async fn send(&self, offset: usize, len: usize) -> io::Result<usize> {
+        loop {
+            let mut guard = self.socket.0.writable().await?;
+            let result = guard.try_io(|socket| {
+                let mut offset = offset as libc::off_t;
+                // SAFETY: both descriptors stay open while `self` is borrowed
+                let sent = unsafe {
+                    libc::sendfile(
+                        socket.as_raw_fd(),
+                        self.payload.as_raw_fd(),
+                        &mut offset,
+                        len,
+                    )
+                };
+                if sent < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(sent as usize)
+                }
+            });
+            match result {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Create an anonymous in-memory file
+fn memfd(name: &str) -> io::Result<File> {
+    let name = CString::new(name)?;
+    // SAFETY: `name` is a valid C string
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just created and nothing else owns it
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
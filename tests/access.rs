@@ -0,0 +1,103 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use uuid::Uuid;
+
+use seismic::{
+    access::{self, AccessConfig},
+    sender::{Sender, SenderConfig},
+};
+
+use common::{sender_config, server_state, start_server};
+
+const TOKEN: &str = "correct horse battery staple";
+
+fn with_token(addrs: (SocketAddr, SocketAddr), token: &str) -> SenderConfig {
+    SenderConfig {
+        auth_token: Some(token.into()),
+        ..sender_config(addrs)
+    }
+}
+
+fn token_required() -> AccessConfig {
+    AccessConfig {
+        token: Some(TOKEN.into()),
+        ..AccessConfig::default()
+    }
+}
+
+#[test]
+fn challenge_response() {
+    let nonce = access::nonce();
+    let mac = access::respond(TOKEN, &nonce);
+    assert!(access::verify(TOKEN, &nonce, &mac));
+    assert!(!access::verify("wrong", &nonce, &mac));
+    assert!(!access::verify(TOKEN, &access::nonce(), &mac));
+    assert!(!access::verify(TOKEN, &nonce, "not hex"));
+}
+
+#[test]
+fn cidr_lists() {
+    let access = AccessConfig {
+        token: None,
+        allow: vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+        deny: vec!["10.1.0.0/16".parse().unwrap()],
+    };
+    assert!(access.permits("10.2.3.4".parse().unwrap()));
+    assert!(access.permits("::1".parse().unwrap()));
+    assert!(!access.permits("10.1.2.3".parse().unwrap()));
+    assert!(!access.permits("192.168.1.1".parse().unwrap()));
+    // IPv4-mapped IPv6 addresses match IPv4 networks
+    assert!(access.permits("::ffff:10.2.3.4".parse().unwrap()));
+    assert!(AccessConfig::default().permits("192.168.1.1".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn authenticated_session() {
+    let addrs = start_server(server_state().with_access(token_required())).await;
+    let sender = Sender::new(with_token(addrs, TOKEN)).await.unwrap();
+    let mset = sender.run().await.unwrap();
+    assert!(mset.measurements.last().unwrap().received > 0);
+}
+
+#[tokio::test]
+async fn wrong_or_missing_token_is_rejected() {
+    let addrs = start_server(server_state().with_access(token_required())).await;
+
+    let err = Sender::new(with_token(addrs, "wrong")).await.err().unwrap();
+    assert!(err.to_string().contains("rejected"), "{}", err);
+
+    assert!(Sender::new(sender_config(addrs)).await.is_err());
+}
+
+#[tokio::test]
+async fn denied_network_is_rejected() {
+    let access = AccessConfig {
+        deny: vec!["127.0.0.0/8".parse().unwrap()],
+        ..AccessConfig::default()
+    };
+    let addrs = start_server(server_state().with_access(access)).await;
+    assert!(Sender::new(sender_config(addrs)).await.is_err());
+}
+
+#[tokio::test]
+async fn data_without_session_is_rejected() {
+    let (_control, data) = start_server(server_state().with_access(token_required())).await;
+
+    let mut stream = TcpStream::connect(data).await.unwrap();
+    stream.write_all(Uuid::new_v4().as_bytes()).await.unwrap();
+    stream.write_all(&[0; 1024]).await.unwrap();
+
+    // The server hangs up instead of echoing
+    let mut buf = [0; 1024];
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use std::{net::Ipv6Addr, time::Duration};
+
+use tokio::net::TcpStream;
+
+use seismic::{
+    api::{CancellationToken, ClientBuilder, ServerHandle},
+    receiver::ReceiverConfig,
+    Seismic,
+};
+
+use common::receiver_config;
+
+async fn server() -> ServerHandle {
+    Seismic::server([127, 0, 0, 1])
+        .control_port(0)
+        .data_port(0)
+        .spawn()
+        .await
+        .unwrap()
+}
+
+fn client(server: &ServerHandle) -> ClientBuilder {
+    Seismic::client("127.0.0.1")
+        .control_port(server.control_addr().port())
+        .data_port(server.data_addr().port())
+        .freq(Duration::from_millis(50))
+}
+
+#[tokio::test]
+async fn client_and_server_in_process() {
+    let server = server().await;
+    let client = client(&server).duration(Duration::from_millis(300));
+    let mut live = client.subscribe();
+    let mset = client.run().await.unwrap();
+    server.shutdown().await.unwrap();
+
+    assert!(mset.measurements.last().unwrap().received > 0);
+    assert_eq!(
+        mset.metadata.get("completion").map(String::as_str),
+        Some("clean")
+    );
+    assert!(!mset.metadata.contains_key("cancelled"));
+    assert!(live.recv().await.is_ok());
+}
+
+#[tokio::test]
+async fn server_without_echo() {
+    let server = Seismic::server([127, 0, 0, 1])
+        .control_port(0)
+        .data_port(0)
+        .config(ReceiverConfig {
+            echo: false,
+            ..receiver_config()
+        })
+        .spawn()
+        .await
+        .unwrap();
+    let mset = client(&server)
+        .duration(Duration::from_millis(300))
+        .run()
+        .await
+        .unwrap();
+    server.shutdown().await.unwrap();
+
+    let last = mset.measurements.last().unwrap();
+    assert!(last.sent_bytes > 0);
+    assert_eq!(last.received_bytes, 0);
+    // Nothing coming back is expected, not a truncated run
+    assert_eq!(mset.metadata["completion"], "clean");
+    assert_eq!(mset.metadata["echo"], "false");
+}
+
+#[tokio::test]
+async fn cancel_running_test() {
+    let server = server().await;
+    let client = client(&server).duration(Duration::from_secs(60)).spawn();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!client.is_finished());
+    client.cancel();
+
+    let mset = tokio::time::timeout(Duration::from_secs(10), client.join())
+        .await
+        .expect("cancelled test didn't finish")
+        .unwrap();
+    server.shutdown().await.unwrap();
+
+    assert_eq!(
+        mset.metadata.get("cancelled").map(String::as_str),
+        Some("true")
+    );
+    let last = mset.measurements.last().unwrap();
+    assert!(last.dt < Duration::from_secs(10));
+    assert_eq!(last.sent_bytes % 1024, 0);
+}
+
+#[tokio::test]
+async fn cancel_before_start() {
+    let server = server().await;
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    let result = client(&server).cancel_token(cancel).run().await;
+    server.shutdown().await.unwrap();
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn server_shutdown_stops_listening() {
+    let server = server().await;
+    let control_addr = server.control_addr();
+    assert!(TcpStream::connect(control_addr).await.is_ok());
+
+    server.shutdown().await.unwrap();
+    assert!(TcpStream::connect(control_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn ipv6_targets() {
+    let config = Seismic::client("::1")
+        .control_port(7224)
+        .data_port(7225)
+        .config();
+    assert_eq!(config.control_addr, "[::1]:7224");
+    assert_eq!(config.addr, "[::1]:7225");
+    assert_eq!(Seismic::client("host").config().addr, "host:7225");
+
+    let server = match Seismic::server(Ipv6Addr::LOCALHOST)
+        .control_port(0)
+        .data_port(0)
+        .spawn()
+        .await
+    {
+        Ok(server) => server,
+        // No IPv6 loopback here
+        Err(_) => return,
+    };
+    let mset = Seismic::client("::1")
+        .control_port(server.control_addr().port())
+        .data_port(server.data_addr().port())
+        .freq(Duration::from_millis(50))
+        .duration(Duration::from_millis(300))
+        .run()
+        .await
+        .unwrap();
+    server.shutdown().await.unwrap();
+    assert!(mset.measurements.last().unwrap().received > 0);
+}
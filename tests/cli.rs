@@ -0,0 +1,244 @@
+use std::{
+    process::{Command, Output},
+    time::Duration,
+};
+
+use seismic::{
+    measurement::{Measurement, MeasurementSet},
+    Seismic,
+};
+use uuid::Uuid;
+
+fn seismic(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_seismic"))
+        .args(args)
+        .env_remove("SEISMIC_CONFIG")
+        .env_remove("SEISMIC_TARGET")
+        .output()
+        .unwrap()
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("seismic-cli-{}-{}", Uuid::new_v4(), name))
+}
+
+#[test]
+fn completions_and_man_pages_cover_subcommands() {
+    let output = seismic(&["completions", "bash"]);
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    for subcommand in [
+        "serve", "test", "mesh", "agent", "report", "compare", "export",
+    ] {
+        assert!(
+            script.contains(subcommand),
+            "no {} in completions",
+            subcommand
+        );
+    }
+
+    let dir = temp_path("man");
+    let output = seismic(&["man", dir.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(dir.join("seismic.1").exists());
+    assert!(dir.join("seismic-test.1").exists());
+    assert!(dir.join("seismic-agent.1").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn shared_flags_are_accepted_by_every_subcommand() {
+    let db = temp_path("runs.db");
+    let db = db.to_str().unwrap();
+
+    // Before and after the subcommand
+    for args in [
+        &["--format", "json", "-v", "report", "--db", db][..],
+        &["report", "--db", db, "--format", "json", "-v"][..],
+    ] {
+        let output = seismic(args);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "[]");
+    }
+    std::fs::remove_file(db).unwrap();
+}
+
+#[test]
+fn errors_are_reported_before_testing() {
+    let output = seismic(&["test"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no target given"));
+
+    let output = seismic(&["test", "--profile", "lan", "10.0.0.1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--profile requires --config"));
+
+    let config = temp_path("seismic.toml");
+    std::fs::write(&config, "[client]\nstreams = 0\n").unwrap();
+    let output = seismic(&["--config", config.to_str().unwrap(), "test", "10.0.0.1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("streams must be positive"));
+    std::fs::remove_file(config).unwrap();
+}
+
+#[test]
+fn report_rejects_times_out_of_range() {
+    let db = temp_path("runs.db");
+    let output = seismic(&[
+        "report",
+        "--db",
+        db.to_str().unwrap(),
+        "--since",
+        "300000000000years",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+    assert!(!db.exists());
+}
+
+#[test]
+fn invalid_log_filter_is_an_error() {
+    let db = temp_path("runs.db");
+    let output = seismic(&[
+        "--log-filter",
+        "seismic=[bad",
+        "report",
+        "--db",
+        db.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to set up tracing"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn logs_as_json_to_stderr_and_file() {
+    let dir = temp_path("logs");
+    let file = dir.join("seismic.log");
+    // Nothing listens on port 1, so the test fails and logs an error
+    let output = seismic(&[
+        "--log-format",
+        "json",
+        "--log-file",
+        file.to_str().unwrap(),
+        "--log-rotation",
+        "never",
+        "test",
+        "127.0.0.1",
+        "--control-port",
+        "1",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line = stderr
+        .lines()
+        .find(|line| line.contains("test of 127.0.0.1 failed"))
+        .expect("no error logged");
+    let event: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert_eq!(event["level"], "ERROR");
+
+    let logged = std::fs::read_to_string(&file).unwrap();
+    let line = logged
+        .lines()
+        .find(|line| line.contains("test of 127.0.0.1 failed"))
+        .expect("no error in the log file");
+    serde_json::from_str::<serde_json::Value>(line).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn log_filter_silences_logs() {
+    let output = seismic(&[
+        "--log-filter",
+        "off",
+        "test",
+        "127.0.0.1",
+        "--control-port",
+        "1",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("test of 127.0.0.1 failed"), "{}", stderr);
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let output = seismic(&["test", "--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = seismic(&["test", "127.0.0.1", "--min-throughput", "abc"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn violated_thresholds_exit_with_3() {
+    let server = Seismic::server([127, 0, 0, 1])
+        .control_port(0)
+        .data_port(0)
+        .spawn()
+        .await
+        .unwrap();
+    let control_port = server.control_addr().port().to_string();
+    let data_port = server.data_addr().port().to_string();
+
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_seismic"))
+        .args([
+            "test",
+            "127.0.0.1",
+            "--control-port",
+            &control_port,
+            "--data-port",
+            &data_port,
+            "--length-secs",
+            "1",
+            "--quiet",
+            "--min-throughput",
+            "1000000000",
+        ])
+        .env_remove("SEISMIC_CONFIG")
+        .output()
+        .await
+        .unwrap();
+    server.shutdown().await.unwrap();
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+}
+
+/// Export a run sampled every 100ms, receiving these many bytes per
+/// interval
+fn export(name: &str, rates: &[u64]) -> std::path::PathBuf {
+    let mut mset = MeasurementSet::new(1, false);
+    let mut bytes = 0;
+    for (i, rate) in [0].iter().chain(rates).enumerate() {
+        bytes += rate;
+        mset.measurements.push(Measurement {
+            dt: Duration::from_millis(100 * i as u64),
+            sent: bytes,
+            received: bytes,
+            sent_bytes: bytes,
+            received_bytes: bytes,
+            transport: None,
+        });
+    }
+    let path = temp_path(name);
+    mset.save(&path).unwrap();
+    path
+}
+
+#[test]
+fn compare_exits_with_3_on_regression() {
+    let rates = [1000, 1100, 900, 1050, 950, 1000, 1100, 900, 1050, 950];
+    let before = export("before.json", &rates);
+    let after = export("after.json", &rates.map(|rate| rate / 2));
+    let (before, after) = (before.to_str().unwrap(), after.to_str().unwrap());
+
+    let output = seismic(&["compare", before, before, "--no-plot"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let output = seismic(&["compare", before, after, "--no-plot"]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    let output = seismic(&["--format", "json", "compare", before, after]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+
+    std::fs::remove_file(before).unwrap();
+    std::fs::remove_file(after).unwrap();
+}
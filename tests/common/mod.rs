@@ -0,0 +1,170 @@
+//! Fixtures shared by the integration tests
+#![allow(dead_code)]
+
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+use seismic::{
+    measurer::SamplingConfig,
+    metrics::MetricsConfig,
+    quic,
+    receiver::ReceiverConfig,
+    sender::SenderConfig,
+    server::{listen_control, listen_data, listen_quic, ServerState, SharedStore},
+    store::Store,
+    tls::{TlsClientConfig, TlsServerConfig},
+};
+
+/// Sample every 100ms in 1 KiB chunks, echoing everything
+pub fn receiver_config() -> ReceiverConfig {
+    ReceiverConfig {
+        freq: Duration::from_millis(100),
+        sampling: SamplingConfig::default(),
+        chunk_size: 1024,
+        echo: true,
+        print_live: false,
+        metrics: MetricsConfig::default(),
+    }
+}
+
+/// A server without a store; tests add access, limits or TLS to it
+pub fn server_state() -> ServerState {
+    ServerState::new(receiver_config(), None)
+}
+
+/// A server recording its runs in an in-memory store, without
+/// printing reports
+pub fn recording_server_state() -> (ServerState, SharedStore) {
+    let store = Arc::new(Mutex::new(Store::open(":memory:").unwrap()));
+    let state = ServerState::new(receiver_config(), Some(store.clone())).without_report();
+    (state, store)
+}
+
+/// Serve `state` over TCP on ephemeral ports, returning (control, data)
+/// addresses
+pub async fn start_server(state: ServerState) -> (SocketAddr, SocketAddr) {
+    let control = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let data = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = (control.local_addr().unwrap(), data.local_addr().unwrap());
+
+    tokio::spawn(listen_control(control, state.clone()));
+    tokio::spawn(listen_data(data, state));
+
+    addrs
+}
+
+/// Serve `state` with data over QUIC, returning (control, data) addresses
+pub async fn start_quic_server(
+    state: ServerState,
+    tls: &TlsServerConfig,
+) -> (SocketAddr, SocketAddr) {
+    let control = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = quic::server_endpoint("127.0.0.1:0".parse().unwrap(), tls).unwrap();
+    let addrs = (
+        control.local_addr().unwrap(),
+        endpoint.local_addr().unwrap(),
+    );
+
+    let state = state.with_tls(tls.acceptor().unwrap());
+    tokio::spawn(listen_control(control, state.clone()));
+    tokio::spawn(listen_quic(endpoint, state));
+
+    addrs
+}
+
+/// A half-second test against the server at (control, data) addresses
+pub fn sender_config(addrs: (SocketAddr, SocketAddr)) -> SenderConfig {
+    SenderConfig {
+        addr: addrs.1.to_string(),
+        control_addr: addrs.0.to_string(),
+        ..test_config()
+    }
+}
+
+/// A half-second test sampled every 100ms, for callers that pick the
+/// addresses or connectors themselves
+pub fn test_config() -> SenderConfig {
+    SenderConfig {
+        freq: Duration::from_millis(100),
+        length: Duration::from_millis(500),
+        ..SenderConfig::default()
+    }
+}
+
+/// A throwaway CA with a server certificate for localhost and a client
+/// certificate
+pub struct Pki {
+    dir: PathBuf,
+}
+
+impl Pki {
+    pub fn generate() -> Self {
+        let dir = std::env::temp_dir().join(format!("seismic-pki-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let pki = Self { dir };
+        pki.write("ca.pem", &ca.pem());
+        pki.issue("server", vec!["localhost".into()], &ca, &ca_key);
+        pki.issue("client", vec!["client".into()], &ca, &ca_key);
+        pki
+    }
+
+    fn issue(&self, name: &str, names: Vec<String>, ca: &Certificate, ca_key: &KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(names).unwrap();
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        let cert = params.signed_by(&key, ca, ca_key).unwrap();
+        self.write(&format!("{}.pem", name), &cert.pem());
+        self.write(&format!("{}.key", name), &key.serialize_pem());
+    }
+
+    fn write(&self, name: &str, contents: &str) {
+        std::fs::write(self.path(name), contents).unwrap();
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// The server certificate, without client authentication
+    pub fn server_config(&self) -> TlsServerConfig {
+        TlsServerConfig {
+            cert: self.path("server.pem"),
+            key: self.path("server.key"),
+            client_ca: None,
+        }
+    }
+
+    /// Trust only this CA, without a client certificate
+    pub fn client_config(&self) -> TlsClientConfig {
+        TlsClientConfig {
+            server_name: "localhost".into(),
+            ca: Some(self.path("ca.pem")),
+            identity: None,
+        }
+    }
+}
+
+impl Drop for Pki {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
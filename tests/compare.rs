@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use seismic::{
+    compare::{Comparison, Run},
+    measurement::{Measurement, MeasurementSet},
+    stats::{ProportionTest, WelchTest},
+};
+
+/// A steady run receiving `rate` bytes per second, sampled every
+/// `step` for a second
+fn steady(label: &str, rate: u64, step: Duration) -> Run {
+    let mut mset = MeasurementSet::new(1, false);
+    let mut dt = Duration::ZERO;
+    while dt <= Duration::from_secs(1) {
+        let bytes = (rate as f64 * dt.as_secs_f64()) as u64;
+        mset.measurements.push(Measurement {
+            dt,
+            sent: bytes,
+            received: bytes,
+            sent_bytes: bytes,
+            received_bytes: bytes,
+            transport: None,
+        });
+        dt += step;
+    }
+    Run {
+        label: label.into(),
+        mset,
+    }
+}
+
+/// A run sampled every 100ms, receiving these many bytes per interval
+fn varying(label: &str, rates: &[u64]) -> Run {
+    let mut mset = MeasurementSet::new(1, false);
+    let mut bytes = 0;
+    for (i, rate) in [0].iter().chain(rates).enumerate() {
+        bytes += rate;
+        mset.measurements.push(Measurement {
+            dt: Duration::from_millis(100 * i as u64),
+            sent: bytes,
+            received: bytes,
+            sent_bytes: bytes,
+            received_bytes: bytes,
+            transport: None,
+        });
+    }
+    Run {
+        label: label.into(),
+        mset,
+    }
+}
+
+#[test]
+fn welch_test() {
+    let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+    let b = [3.0, 4.0, 5.0, 6.0, 7.0];
+    let test = WelchTest::new(&a, &b).unwrap();
+    assert!((test.t - 2.0).abs() < 1e-9);
+    assert!((test.df - 8.0).abs() < 1e-9);
+    assert!((test.p_value - 0.0805).abs() < 1e-3, "{}", test.p_value);
+    assert!(!test.is_significant(0.05));
+    assert!(test.is_significant(0.1));
+
+    // Swapping the samples flips the sign only
+    let swapped = WelchTest::new(&b, &a).unwrap();
+    assert!((swapped.t + 2.0).abs() < 1e-9);
+    assert!((swapped.p_value - test.p_value).abs() < 1e-12);
+
+    assert!(WelchTest::new(&a, &[1.0]).is_none());
+    assert!(WelchTest::new(&[2.0, 2.0], &[3.0, 3.0]).is_none());
+}
+
+#[test]
+fn proportion_test() {
+    let test = ProportionTest::new(10, 100, 20, 100).unwrap();
+    assert!((test.z - 1.980).abs() < 1e-3, "{}", test.z);
+    assert!((test.p_value - 0.0477).abs() < 1e-3, "{}", test.p_value);
+    assert!(test.is_significant(0.05));
+
+    let same = ProportionTest::new(10, 100, 10, 100).unwrap();
+    assert_eq!(same.z, 0.0);
+    assert!((same.p_value - 1.0).abs() < 1e-12);
+
+    assert!(ProportionTest::new(0, 0, 1, 10).is_none());
+    assert!(ProportionTest::new(0, 10, 0, 10).is_none());
+    assert!(ProportionTest::new(10, 10, 10, 10).is_none());
+}
+
+#[test]
+fn runs_are_aligned_on_the_baseline_grid() {
+    // The second run is sampled less often, at twice the rate
+    let runs = vec![
+        steady("baseline", 10_000, Duration::from_millis(100)),
+        steady("faster", 20_000, Duration::from_millis(250)),
+    ];
+    let comparison = Comparison::new(runs, 0.05).unwrap();
+    let throughput = comparison.aligned_throughput();
+
+    assert_eq!(throughput.len(), 2);
+    for (run, expected) in throughput.iter().zip([0.01, 0.02]) {
+        assert_eq!(run.len(), 10);
+        for mbps in run {
+            assert!((mbps - expected).abs() < 1e-9, "{:?}", run);
+        }
+    }
+}
+
+#[test]
+fn runs_must_overlap() {
+    let short = Run {
+        label: "short".into(),
+        mset: MeasurementSet::default(),
+    };
+    let runs = vec![steady("a", 1000, Duration::from_millis(100)), short];
+    assert!(Comparison::new(runs, 0.05).is_err());
+}
+
+#[test]
+fn regressions_are_detected() {
+    let baseline = [1000, 1100, 900, 1050, 950, 1000, 1100, 900, 1050, 950];
+    let slower = baseline.map(|rate| rate / 2);
+
+    let comparison = Comparison::new(
+        vec![varying("before", &baseline), varying("after", &slower)],
+        0.05,
+    )
+    .unwrap();
+    assert!(comparison.has_regression());
+
+    let comparison = Comparison::new(
+        vec![varying("before", &baseline), varying("again", &baseline)],
+        0.05,
+    )
+    .unwrap();
+    assert!(!comparison.has_regression());
+}
+
+#[test]
+fn loss_is_tested_in_bytes() {
+    // No whole chunk was lost, but part of the last one was
+    let baseline = steady("baseline", 10_000, Duration::from_millis(100));
+    let mut partial = steady("partial", 10_000, Duration::from_millis(100));
+    let last = partial.mset.measurements.last_mut().unwrap();
+    let sent_bytes = last.sent_bytes;
+    last.received_bytes -= 500;
+
+    let comparison = Comparison::new(vec![baseline, partial], 0.05).unwrap();
+    let deltas = &comparison.deltas()[0];
+    let loss = deltas.iter().find(|d| d.name == "loss").unwrap();
+    let expected = ProportionTest::new(0, sent_bytes, 500, sent_bytes).unwrap();
+    assert_eq!(loss.p_value, Some(expected.p_value));
+    assert!((loss.value - 5.0).abs() < 1e-9, "{}", loss.value);
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use clap::{CommandFactory, FromArgMatches, Parser};
+
+use seismic::{
+    config::{ConfigFile, Layer},
+    measurement::Warmup,
+    sender::Transport,
+};
+
+const EXAMPLE: &str = r#"
+[client]
+target = "lab"
+freq = "250us"
+chunk-size = 4096
+bytes = "10GiB"
+tag = { site = "ams" }
+tag-keys = ["role", "peer"]
+
+[profiles.quic]
+transport = "quic"
+streams = 4
+freq = 50
+warmup = "auto"
+
+[server]
+no-echo = true
+allow = ["10.0.0.0/8"]
+max-sessions = 8
+
+[targets]
+lab = ["10.0.0.1", "10.0.0.2"]
+"#;
+
+fn error(text: &str) -> String {
+    format!("{:#}", ConfigFile::parse(text).unwrap_err())
+}
+
+#[test]
+fn example_parses() {
+    let config = ConfigFile::parse(EXAMPLE).unwrap();
+
+    let client = config.client(None).unwrap();
+    assert_eq!(client.target.as_deref(), Some("lab"));
+    assert_eq!(client.freq, Some(Duration::from_micros(250)));
+    assert_eq!(client.chunk_size, Some(4096));
+    assert_eq!(client.bytes, Some(10 << 30));
+    assert_eq!(client.tag.unwrap()["site"], "ams");
+    assert_eq!(client.tag_keys.unwrap(), ["role", "peer"]);
+
+    let server = config.server();
+    assert_eq!(server.no_echo, Some(true));
+    assert_eq!(server.allow.as_ref().unwrap()[0].to_string(), "10.0.0.0/8");
+    assert_eq!(server.max_sessions, Some(8));
+
+    assert_eq!(config.targets("lab").unwrap(), ["10.0.0.1", "10.0.0.2"]);
+    assert!(config.targets("10.0.0.1").is_none());
+}
+
+#[test]
+fn profiles_override_client() {
+    let config = ConfigFile::parse(EXAMPLE).unwrap();
+    let client = config.client(Some("quic")).unwrap();
+    assert_eq!(client.transport, Some(Transport::Quic));
+    assert_eq!(client.streams, Some(4));
+    assert_eq!(client.freq, Some(Duration::from_millis(50)));
+    assert_eq!(client.warmup, Some(Warmup::Auto));
+    // Inherited from [client]
+    assert_eq!(client.chunk_size, Some(4096));
+
+    let err = format!("{:#}", config.client(Some("udp")).unwrap_err());
+    assert!(err.contains("no profile 'udp'; available: quic"), "{}", err);
+}
+
+#[test]
+fn mistakes_are_reported() {
+    assert!(error("[client]\nchunk_size = 1").contains("unknown field `chunk_size`"));
+    // Clients choose the chunk size
+    assert!(error("[server]\nchunk-size = 1").contains("unknown field `chunk-size`"));
+    assert!(error("[clinet]\n").contains("unknown field `clinet`"));
+    assert!(error("[client]\nfreq = \"soon\"").contains("freq"));
+    assert!(error("[client]\nwarmup = \"later\"").contains("warmup"));
+    assert!(error("[client]\ntransport = \"carrier-pigeon\"").contains("unknown variant"));
+    assert!(error("[server]\nallow = [\"10.0.0.0/33\"]").contains("allow"));
+}
+
+#[test]
+fn invalid_combinations_are_rejected() {
+    let err = error("[client]\nstreams = 2");
+    assert!(err.contains("in [client]") && err.contains("requires transport"));
+
+    let err = error("[profiles.fast]\nchunk-size = 0");
+    assert!(err.contains("in [profiles.fast]") && err.contains("chunk-size must be positive"));
+
+    let err = error("[server]\nquic = true");
+    assert!(err.contains("quic requires tls-cert"));
+
+    let err = error("[server]\ntls-cert = \"cert.pem\"");
+    assert!(err.contains("tls-cert and tls-key must be given together"));
+
+    let err = error("[client]\nauth-token = \"a\"\nauth-token-file = \"b\"");
+    assert!(err.contains("can't both be given"));
+
+    let err = error("[client]\nbytes = \"1GiB\"\nchunks = 10");
+    assert!(err.contains("bytes and chunks can't both be given"));
+
+    let err = error("[client]\nbytes = \"lots\"");
+    assert!(err.contains("unknown unit"), "{}", err);
+
+    let err = error("[targets]\nnone = []");
+    assert!(err.contains("target list 'none' is empty"));
+}
+
+#[derive(Parser)]
+struct Opts {
+    #[clap(short, long, default_value = "1024")]
+    chunk_size: usize,
+    #[clap(long, default_value = "5")]
+    length_secs: u16,
+    #[clap(long)]
+    output: Option<String>,
+}
+
+#[test]
+fn command_line_takes_precedence() {
+    let matches = Opts::command().get_matches_from(["test", "-c", "512"]);
+    let mut opts = Opts::from_arg_matches(&matches).unwrap();
+
+    let layer = Layer::new(&matches);
+    layer.set("chunk-size", &mut opts.chunk_size, Some(4096));
+    layer.set("length-secs", &mut opts.length_secs, Some(10));
+    layer.set_opt("output", &mut opts.output, Some("run.json".into()));
+
+    assert_eq!(opts.chunk_size, 512);
+    assert_eq!(opts.length_secs, 10);
+    assert_eq!(opts.output.as_deref(), Some("run.json"));
+}
@@ -0,0 +1,44 @@
+use opentelemetry::{
+    global,
+    sdk::{propagation::TraceContextPropagator, trace::TracerProvider},
+    trace::{TraceContextExt, TracerProvider as _},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::prelude::*;
+
+use seismic::control;
+
+#[test]
+fn trace_context_round_trip() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = TracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("seismic")));
+
+    tracing::subscriber::with_default(subscriber, || {
+        // The client serializes its span's context into the hello...
+        let client = tracing::info_span!("client");
+        let trace_context = client.in_scope(control::current_trace_context);
+        let client = client.context().span().span_context().clone();
+        assert!(client.is_valid());
+        let traceparent = &trace_context["traceparent"];
+        assert!(
+            traceparent.contains(&client.trace_id().to_string()),
+            "{}",
+            traceparent
+        );
+
+        // ...and the server's session span joins the client's trace
+        let server = tracing::info_span!("session");
+        control::set_remote_parent(&server, &trace_context);
+        let server = server.context().span().span_context().clone();
+        assert_eq!(server.trace_id(), client.trace_id());
+        assert_ne!(server.span_id(), client.span_id());
+    });
+}
+
+#[test]
+fn without_a_trace_there_is_nothing_to_propagate() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    assert!(control::current_trace_context().is_empty());
+}
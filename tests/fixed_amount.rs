@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use seismic::{
+    api::{ClientBuilder, ServerHandle},
+    generator::parse_size,
+    store::{RunFilter, Store},
+    Seismic,
+};
+use uuid::Uuid;
+
+async fn server(store: Option<Store>) -> ServerHandle {
+    let mut server = Seismic::server([127, 0, 0, 1]).control_port(0).data_port(0);
+    if let Some(store) = store {
+        server = server.store(store);
+    }
+    server.spawn().await.unwrap()
+}
+
+fn client(server: &ServerHandle) -> ClientBuilder {
+    Seismic::client("127.0.0.1")
+        .control_port(server.control_addr().port())
+        .data_port(server.data_addr().port())
+        .freq(Duration::from_millis(50))
+        .chunk_size(1000)
+}
+
+#[tokio::test]
+async fn sends_exactly_the_total() {
+    let server = server(None).await;
+    // Much longer than the transfer takes
+    let mset = client(&server)
+        .duration(Duration::from_secs(60))
+        .total_bytes(2_000_000)
+        .run()
+        .await
+        .unwrap();
+    server.shutdown().await.unwrap();
+
+    let last = mset.measurements.last().unwrap();
+    assert_eq!(last.sent_bytes, 2_000_000);
+    assert_eq!(last.received_bytes, 2_000_000);
+    assert_eq!(mset.metadata["completion"], "clean");
+    assert_eq!(mset.metadata["transfer_bytes"], "2000000");
+    let time = mset.transfer_time().unwrap();
+    assert!(time < Duration::from_secs(30), "{:?}", time);
+    let throughput = mset.effective_throughput().unwrap();
+    assert!((throughput - 2e6 / time.as_secs_f64()).abs() / throughput < 0.01);
+}
+
+#[tokio::test]
+async fn total_is_rounded_up_to_whole_chunks() {
+    let server = server(None).await;
+    let mset = client(&server).total_bytes(1500).run().await.unwrap();
+    server.shutdown().await.unwrap();
+
+    assert_eq!(mset.measurements.last().unwrap().sent_bytes, 2000);
+    assert_eq!(mset.metadata["expected_bytes"], "2000");
+    assert_eq!(mset.metadata["completion"], "clean");
+}
+
+#[tokio::test]
+async fn server_verifies_the_total() {
+    let path = std::env::temp_dir().join(format!("seismic-fixed-{}.db", Uuid::new_v4()));
+    let server = server(Some(Store::open(&path).unwrap())).await;
+    client(&server).total_bytes(300_000).run().await.unwrap();
+    // Give the server a moment to record its side of the run
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    server.shutdown().await.unwrap();
+
+    let store = Store::open(&path).unwrap();
+    let runs = store.list(&RunFilter::default()).unwrap();
+    assert_eq!(runs.len(), 1);
+    let mset = store.load(runs[0].id).unwrap().unwrap();
+    assert_eq!(mset.metadata["expected_bytes"], "300000");
+    assert_eq!(mset.metadata["completion"], "clean");
+    assert_eq!(mset.measurements.last().unwrap().received_bytes, 300_000);
+    drop(store);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn cancelled_transfer_is_incomplete() {
+    let server = server(None).await;
+    let client = client(&server).total_bytes(u64::MAX / 2).spawn();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    client.cancel();
+    let mset = client.join().await.unwrap();
+    server.shutdown().await.unwrap();
+
+    assert_eq!(mset.metadata["completion"], "incomplete");
+    assert!(mset.is_incomplete());
+    assert!(mset.transfer_time().is_none());
+}
+
+#[test]
+fn sizes_parse() {
+    assert_eq!(parse_size("1234").unwrap(), 1234);
+    assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+    assert_eq!(parse_size("10GiB").unwrap(), 10 << 30);
+    assert_eq!(parse_size("1.5 KiB").unwrap(), 1536);
+    assert_eq!(parse_size("2k").unwrap(), 2000);
+    assert!(parse_size("0").is_err());
+    assert!(parse_size("10 parsecs").is_err());
+    assert!(parse_size("GiB").is_err());
+}
@@ -0,0 +1,294 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+use seismic::{
+    impair::{proxy_tcp, proxy_udp, Impairment, Stall},
+    sender::Sender,
+};
+
+use common::{sender_config, server_state, start_server};
+
+/// Long enough that no test's UDP client is forgotten
+const UDP_IDLE: Duration = Duration::from_secs(60);
+
+/// Start a TCP echo server, returning its address
+async fn tcp_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                tokio::io::copy(&mut read, &mut write).await.ok();
+            });
+        }
+    });
+    addr
+}
+
+/// Start a UDP echo server, returning its address
+async fn udp_echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = vec![0; 2048];
+        while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+            socket.send_to(&buf[..n], from).await.ok();
+        }
+    });
+    addr
+}
+
+/// Start a UDP server that replies with the address each datagram came
+/// from, returning its address
+async fn udp_whoami() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = vec![0; 2048];
+        while let Ok((_, from)) = socket.recv_from(&mut buf).await {
+            socket.send_to(from.to_string().as_bytes(), from).await.ok();
+        }
+    });
+    addr
+}
+
+/// The upstream address the proxy used for a client connected to it
+async fn seen_as(client: &UdpSocket) -> String {
+    let mut buf = vec![0; 2048];
+    client.send(b"who").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    String::from_utf8(buf[..n].to_vec()).unwrap()
+}
+
+async fn start_tcp_proxy(upstream: SocketAddr, impairment: Impairment) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(proxy_tcp(listener, upstream.to_string(), impairment));
+    addr
+}
+
+async fn start_udp_proxy(upstream: SocketAddr, impairment: Impairment) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(proxy_udp(socket, upstream, impairment, UDP_IDLE));
+    addr
+}
+
+/// Time for a message to go through the proxy and back
+async fn tcp_round_trip(proxy: SocketAddr, len: usize) -> Duration {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let msg = vec![7; len];
+    let mut buf = vec![0; len];
+    let start = Instant::now();
+    stream.write_all(&msg).await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, msg);
+    start.elapsed()
+}
+
+#[tokio::test]
+async fn tcp_delay() {
+    let proxy = start_tcp_proxy(
+        tcp_echo().await,
+        Impairment {
+            delay: Duration::from_millis(50),
+            ..Impairment::default()
+        },
+    )
+    .await;
+
+    let rtt = tcp_round_trip(proxy, 100).await;
+    assert!(rtt >= Duration::from_millis(100), "{:?}", rtt);
+}
+
+#[tokio::test]
+async fn tcp_bandwidth_cap() {
+    let proxy = start_tcp_proxy(
+        tcp_echo().await,
+        Impairment {
+            bandwidth: Some(1.0),
+            ..Impairment::default()
+        },
+    )
+    .await;
+
+    // 200 kB each way at 1 MB/s
+    let elapsed = tcp_round_trip(proxy, 200_000).await;
+    assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn tcp_stall() {
+    let start = Instant::now();
+    let proxy = start_tcp_proxy(
+        tcp_echo().await,
+        Impairment {
+            stall: Some(Stall {
+                every: Duration::from_secs(10),
+                length: Duration::from_millis(200),
+            }),
+            ..Impairment::default()
+        },
+    )
+    .await;
+
+    // Nothing gets through during the first stall
+    tcp_round_trip(proxy, 100).await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn udp_delay_and_loss() {
+    let echo = udp_echo().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = vec![0; 2048];
+
+    let delayed = start_udp_proxy(
+        echo,
+        Impairment {
+            delay: Duration::from_millis(50),
+            ..Impairment::default()
+        },
+    )
+    .await;
+    let start = Instant::now();
+    client.send_to(b"ping", delayed).await.unwrap();
+    let (n, _) = client.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    let lossy = start_udp_proxy(
+        echo,
+        Impairment {
+            loss_pct: 100.0,
+            ..Impairment::default()
+        },
+    )
+    .await;
+    client.send_to(b"ping", lossy).await.unwrap();
+    let reply = tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buf)).await;
+    assert!(reply.is_err());
+}
+
+/// Send `count` datagrams of 1000 bytes through `proxy`, returning
+/// how many came back (waiting up to `quiet` for each) and how long
+/// the last one took
+async fn udp_burst(proxy: SocketAddr, count: usize, quiet: Duration) -> (usize, Duration) {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(proxy).await.unwrap();
+    let start = Instant::now();
+    for i in 0..count {
+        client.send(&[0; 1000]).await.unwrap();
+        // Much faster than the caps, without overflowing socket buffers
+        if i % 10 == 9 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+    let mut buf = vec![0; 2048];
+    let mut replies = 0;
+    let mut last = Duration::ZERO;
+    while replies < count {
+        match tokio::time::timeout(quiet, client.recv(&mut buf)).await {
+            Ok(reply) => {
+                reply.unwrap();
+                replies += 1;
+                last = start.elapsed();
+            }
+            Err(_elapsed) => break,
+        }
+    }
+    (replies, last)
+}
+
+#[tokio::test]
+async fn udp_bandwidth_is_per_client() {
+    // 30 kB each way at 50 kB/s takes 600 ms, or twice that if
+    // both clients shared the cap
+    let proxy = start_udp_proxy(
+        udp_echo().await,
+        Impairment {
+            bandwidth: Some(0.05),
+            ..Impairment::default()
+        },
+    )
+    .await;
+    let quiet = Duration::from_secs(2);
+    let (a, b) = tokio::join!(udp_burst(proxy, 30, quiet), udp_burst(proxy, 30, quiet));
+
+    for (replies, last) in [a, b] {
+        assert_eq!(replies, 30);
+        assert!(last >= Duration::from_millis(500), "{:?}", last);
+        assert!(last < Duration::from_millis(950), "{:?}", last);
+    }
+}
+
+#[tokio::test]
+async fn udp_bandwidth_queue_overflows() {
+    // Far more than the queue holds, so most are dropped
+    let proxy = start_udp_proxy(
+        udp_echo().await,
+        Impairment {
+            bandwidth: Some(0.2),
+            ..Impairment::default()
+        },
+    )
+    .await;
+    let (replies, _last) = udp_burst(proxy, 500, Duration::from_millis(500)).await;
+    assert!(replies > 0);
+    assert!(replies < 200, "{} of 500 datagrams got through", replies);
+}
+
+#[tokio::test]
+async fn idle_udp_clients_are_forgotten() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let proxy = socket.local_addr().unwrap();
+    tokio::spawn(proxy_udp(
+        socket,
+        udp_whoami().await,
+        Impairment::default(),
+        Duration::from_millis(200),
+    ));
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(proxy).await.unwrap();
+
+    let first = seen_as(&client).await;
+    assert_eq!(seen_as(&client).await, first);
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_ne!(seen_as(&client).await, first);
+}
+
+#[tokio::test]
+async fn sender_through_proxy() {
+    let (control_addr, data_addr) = start_server(server_state()).await;
+
+    let proxy = start_tcp_proxy(
+        data_addr,
+        Impairment {
+            delay: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            bandwidth: Some(10.0),
+            ..Impairment::default()
+        },
+    )
+    .await;
+
+    let sender = Sender::new(sender_config((control_addr, proxy)))
+        .await
+        .unwrap();
+    let mset = sender.run().await.unwrap();
+
+    let last = mset.measurements.last().unwrap();
+    assert!(last.received > 0);
+    assert_eq!(last.received, last.sent);
+}
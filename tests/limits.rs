@@ -0,0 +1,164 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use seismic::{
+    limits::{Limits, RateLimiter},
+    sender::{Sender, SenderConfig},
+};
+
+use common::{sender_config, server_state, start_server};
+
+async fn session_error(config: SenderConfig) -> String {
+    Sender::new(config).await.err().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn max_sessions() {
+    let limits = Limits {
+        max_sessions: Some(1),
+        ..Limits::default()
+    };
+    let addrs = start_server(server_state().with_limits(limits)).await;
+
+    let first = Sender::new(sender_config(addrs)).await.unwrap();
+    let err = session_error(sender_config(addrs)).await;
+    assert!(err.contains("busy"), "{}", err);
+
+    // The slot frees up once the first session is over
+    first.run().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Sender::new(sender_config(addrs)).await.unwrap();
+}
+
+#[tokio::test]
+async fn max_sessions_per_client() {
+    let limits = Limits {
+        max_sessions_per_client: Some(1),
+        ..Limits::default()
+    };
+    let addrs = start_server(server_state().with_limits(limits)).await;
+
+    let _first = Sender::new(sender_config(addrs)).await.unwrap();
+    let err = session_error(sender_config(addrs)).await;
+    assert!(err.contains("busy"), "{}", err);
+}
+
+#[tokio::test]
+async fn request_limits() {
+    let limits = Limits {
+        max_duration: Some(Duration::from_secs(1)),
+        max_chunk_size: Some(4096),
+        ..Limits::default()
+    };
+    let addrs = start_server(server_state().with_limits(limits)).await;
+
+    let err = session_error(SenderConfig {
+        chunk_size: 8192,
+        ..sender_config(addrs)
+    })
+    .await;
+    assert!(
+        err.contains("rejected") && err.contains("chunk size"),
+        "{}",
+        err
+    );
+
+    let err = session_error(SenderConfig {
+        length: Duration::from_secs(2),
+        ..sender_config(addrs)
+    })
+    .await;
+    assert!(
+        err.contains("rejected") && err.contains("duration"),
+        "{}",
+        err
+    );
+
+    let sender = Sender::new(SenderConfig {
+        chunk_size: 4096,
+        ..sender_config(addrs)
+    })
+    .await
+    .unwrap();
+    let mset = sender.run().await.unwrap();
+    assert!(mset.measurements.last().unwrap().received > 0);
+}
+
+#[tokio::test]
+async fn totals_too_big_for_the_bandwidth_are_rejected() {
+    let limits = Limits {
+        max_duration: Some(Duration::from_secs(1)),
+        max_bandwidth: Some(1.0),
+        ..Limits::default()
+    };
+    let addrs = start_server(server_state().with_limits(limits)).await;
+
+    let err = session_error(SenderConfig {
+        total_bytes: Some(10_000_000),
+        ..sender_config(addrs)
+    })
+    .await;
+    assert!(err.contains("rejected") && err.contains("MB/s"), "{}", err);
+
+    Sender::new(SenderConfig {
+        total_bytes: Some(100_000),
+        ..sender_config(addrs)
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn sessions_past_the_maximum_duration_are_cut_off() {
+    let limits = Limits {
+        max_duration: Some(Duration::from_millis(100)),
+        ..Limits::default()
+    };
+    let addrs = start_server(server_state().with_limits(limits)).await;
+
+    // Without a bandwidth limit, a fixed amount is only bounded at runtime
+    let sender = Sender::new(SenderConfig {
+        total_bytes: Some(u64::MAX / 2),
+        ..sender_config(addrs)
+    })
+    .await
+    .unwrap();
+    let start = Instant::now();
+    // The server closes the data connection after the few seconds' grace
+    let run = tokio::time::timeout(Duration::from_secs(30), sender.run()).await;
+    assert!(run.is_ok(), "still sending after {:?}", start.elapsed());
+    assert!(start.elapsed() >= Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn rate_limiter() {
+    // 1 MB/s for 200 kB should take about 200 ms
+    let limiter = RateLimiter::new(1e6);
+    let start = Instant::now();
+    for _ in 0..200 {
+        limiter.acquire(1000).await;
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(180), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn bandwidth_limit() {
+    let limits = Limits {
+        max_bandwidth: Some(1.0),
+        ..Limits::default()
+    };
+    let addrs = start_server(server_state().with_limits(limits)).await;
+
+    let sender = Sender::new(sender_config(addrs)).await.unwrap();
+    let mset = sender.run().await.unwrap();
+
+    // Buffered data keeps trickling back after the sender
+    // stops writing, but no faster than the limit
+    let last = mset.measurements.last().unwrap();
+    let rate = (last.received * 1024) as f64 / last.dt.as_secs_f64();
+    assert!(rate > 0.5e6, "{} B/s", rate);
+    assert!(rate < 1.5e6, "{} B/s", rate);
+}
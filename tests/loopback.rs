@@ -0,0 +1,291 @@
+mod common;
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use uuid::Uuid;
+
+use seismic::{
+    control::{self, ClientMessage, ControlChannel, ServerMessage},
+    measurement::MeasurementSet,
+    sender::{Sender, SenderConfig},
+    server::SharedStore,
+    store::RunFilter,
+};
+
+use common::{recording_server_state, sender_config, start_server};
+
+const CHUNK_SIZE: usize = 1024;
+
+/// A server on ephemeral loopback ports, recording its runs
+struct Server {
+    addrs: (SocketAddr, SocketAddr),
+    store: SharedStore,
+}
+
+impl Server {
+    async fn start() -> Self {
+        let (state, store) = recording_server_state();
+        let addrs = start_server(state).await;
+        Self { addrs, store }
+    }
+
+    fn sender_config(&self) -> SenderConfig {
+        SenderConfig {
+            chunk_size: CHUNK_SIZE,
+            ..sender_config(self.addrs)
+        }
+    }
+
+    /// Wait for the server to record its only run
+    async fn run(&self) -> MeasurementSet {
+        for _ in 0..100 {
+            if let Some(mset) = self.load_run() {
+                return mset;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("server recorded no run");
+    }
+
+    fn load_run(&self) -> Option<MeasurementSet> {
+        let store = self.store.lock().unwrap();
+        let run = store
+            .list(&RunFilter::default())
+            .unwrap()
+            .into_iter()
+            .next()?;
+        store.load(run.id).unwrap()
+    }
+
+    /// Open a session over the control channel by hand
+    async fn session(&self) -> (ControlChannel<TcpStream>, Uuid) {
+        let mut control = ControlChannel::new(TcpStream::connect(self.addrs.0).await.unwrap());
+        control
+            .send(&ClientMessage::Hello {
+                trace_context: HashMap::new(),
+                chunk_size: CHUNK_SIZE,
+                duration_ms: 1000,
+                streams: 1,
+                total_bytes: None,
+            })
+            .await
+            .unwrap();
+        match control.expect().await.unwrap() {
+            ServerMessage::Session { session_id, .. } => (control, session_id),
+            msg => panic!("expected a session, got {:?}", msg),
+        }
+    }
+
+    /// Connect to the data port and identify the session
+    async fn data_stream(&self, session_id: Uuid) -> TcpStream {
+        let mut stream = TcpStream::connect(self.addrs.1).await.unwrap();
+        control::write_session_id(&mut stream, session_id)
+            .await
+            .unwrap();
+        stream
+    }
+}
+
+#[tokio::test]
+async fn counters_are_consistent() {
+    let server = Server::start().await;
+
+    let sender = Sender::new(server.sender_config()).await.unwrap();
+    let client = sender.run().await.unwrap();
+    let server = server.run().await;
+
+    let client = client.measurements.last().unwrap();
+    let server = server.measurements.last().unwrap();
+    assert!(client.sent > 0);
+    // Everything sent arrived, and every chunk was echoed back
+    assert_eq!(server.received, client.sent);
+    assert_eq!(server.sent, server.received);
+    assert_eq!(client.received, server.sent);
+    assert_eq!(server.received_bytes, client.sent_bytes);
+    assert_eq!(client.received_bytes, client.sent_bytes);
+}
+
+#[tokio::test]
+async fn counters_only_grow() {
+    let server = Server::start().await;
+
+    let sender = Sender::new(server.sender_config()).await.unwrap();
+    let mset = sender.run().await.unwrap();
+
+    for pair in mset.measurements.windows(2) {
+        assert!(pair[0].dt <= pair[1].dt);
+        assert!(pair[0].sent <= pair[1].sent);
+        assert!(pair[0].received <= pair[1].received);
+    }
+    let last = mset.measurements.last().unwrap();
+    assert!(last.received <= last.sent);
+}
+
+#[tokio::test]
+async fn eof_ends_the_run() {
+    let server = Server::start().await;
+    let (_control, session_id) = server.session().await;
+
+    let mut stream = server.data_stream(session_id).await;
+    stream.write_all(&[0; 3 * CHUNK_SIZE]).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mset = server.run().await;
+    assert_eq!(mset.measurements.last().unwrap().received, 3);
+}
+
+#[tokio::test]
+async fn reset_ends_the_run() {
+    let server = Server::start().await;
+    let (_control, session_id) = server.session().await;
+
+    let mut stream = server.data_stream(session_id).await;
+    stream.write_all(&[0; 3 * CHUNK_SIZE]).await.unwrap();
+    // Give the server time to read the data, then abort the connection
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    stream.set_linger(Some(Duration::ZERO)).unwrap();
+    drop(stream);
+
+    let mset = server.run().await;
+    assert_eq!(mset.measurements.last().unwrap().received, 3);
+}
+
+#[tokio::test]
+async fn partial_chunk_is_reported() {
+    let server = Server::start().await;
+    let (_control, session_id) = server.session().await;
+
+    let mut stream = server.data_stream(session_id).await;
+    stream
+        .write_all(&[0; 2 * CHUNK_SIZE + CHUNK_SIZE / 2])
+        .await
+        .unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut mset = server.run().await;
+    let last = mset.measurements.last().unwrap();
+    assert_eq!(last.received, 2);
+    assert_eq!(
+        last.received_bytes,
+        (2 * CHUNK_SIZE + CHUNK_SIZE / 2) as u64
+    );
+    // The partial chunk is echoed back too
+    assert_eq!(last.sent_bytes, last.received_bytes);
+    assert_eq!(mset.partial_chunk(), (CHUNK_SIZE / 2) as u64);
+    assert!(mset.is_truncated());
+
+    // Recorded by the reader, so it doesn't depend on the chunk size
+    assert_eq!(
+        mset.metadata["partial_chunk_bytes"],
+        (CHUNK_SIZE / 2).to_string()
+    );
+    mset.chunk_size = 0;
+    assert_eq!(mset.partial_chunk(), (CHUNK_SIZE / 2) as u64);
+}
+
+#[tokio::test]
+async fn sender_ends_cleanly() {
+    let server = Server::start().await;
+
+    let sender = Sender::new(server.sender_config()).await.unwrap();
+    let client = sender.run().await.unwrap();
+    let server = server.run().await;
+
+    assert_eq!(client.metadata["completion"], "clean");
+    assert_eq!(server.metadata["completion"], "clean");
+    assert_eq!(server.partial_chunk(), 0);
+    assert_eq!(server.metadata["partial_chunk_bytes"], "0");
+    assert_eq!(client.metadata["partial_chunk_bytes"], "0");
+}
+
+#[tokio::test]
+async fn end_of_test_marker() {
+    let server = Server::start().await;
+    let (mut control, session_id) = server.session().await;
+
+    let mut stream = server.data_stream(session_id).await;
+    stream.write_all(&[0; 3 * CHUNK_SIZE]).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let sent_bytes = 3 * CHUNK_SIZE as u64;
+    control
+        .send(&ClientMessage::Done { sent_bytes })
+        .await
+        .unwrap();
+
+    let mset = server.run().await;
+    assert_eq!(mset.metadata["completion"], "clean");
+    assert!(!mset.is_truncated());
+}
+
+#[tokio::test]
+async fn missing_data_is_truncated() {
+    let server = Server::start().await;
+    let (mut control, session_id) = server.session().await;
+
+    let mut stream = server.data_stream(session_id).await;
+    stream.write_all(&[0; 3 * CHUNK_SIZE]).await.unwrap();
+    stream.shutdown().await.unwrap();
+    // Claim more than actually arrived
+    let sent_bytes = 4 * CHUNK_SIZE as u64;
+    control
+        .send(&ClientMessage::Done { sent_bytes })
+        .await
+        .unwrap();
+
+    let mset = server.run().await;
+    assert!(mset.is_truncated());
+}
+
+#[tokio::test]
+async fn zero_copy_sender() {
+    let server = Server::start().await;
+
+    let sender = Sender::new(SenderConfig {
+        zero_copy: true,
+        ..server.sender_config()
+    })
+    .await
+    .unwrap();
+    let client = sender.run().await.unwrap();
+    let server = server.run().await;
+
+    let last = client.measurements.last().unwrap();
+    assert!(last.sent > 0);
+    assert_eq!(last.sent_bytes % CHUNK_SIZE as u64, 0);
+    assert_eq!(client.metadata["completion"], "clean");
+    assert_eq!(server.metadata["completion"], "clean");
+}
+
+#[tokio::test]
+async fn pinned_sender() {
+    let server = Server::start().await;
+
+    let sender = Sender::new(SenderConfig {
+        pin_cores: vec![0],
+        ..server.sender_config()
+    })
+    .await
+    .unwrap();
+    let client = sender.run().await.unwrap();
+    let server = server.run().await;
+
+    assert!(client.measurements.last().unwrap().sent > 0);
+    assert_eq!(server.metadata["completion"], "clean");
+}
+
+#[cfg(not(feature = "io-uring"))]
+#[tokio::test]
+async fn io_uring_needs_the_feature() {
+    let server = Server::start().await;
+
+    let err = Sender::new(SenderConfig {
+        backend: seismic::sender::Backend::IoUring,
+        ..server.sender_config()
+    })
+    .await
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(err.contains("io-uring feature"), "{}", err);
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use seismic::{
+    compare::{Comparison, Run},
+    measurement::{Measurement, MeasurementSet},
+};
+
+const CHUNK_SIZE: u64 = 1000;
+
+/// A run in 1000-byte chunks, from (milliseconds, sent bytes, received
+/// bytes) samples
+fn run(samples: &[(u64, u64, u64)]) -> MeasurementSet {
+    let mut mset = MeasurementSet::new(CHUNK_SIZE as usize, false);
+    for &(ms, sent_bytes, received_bytes) in samples {
+        mset.measurements.push(Measurement {
+            dt: Duration::from_millis(ms),
+            sent: sent_bytes / CHUNK_SIZE,
+            received: received_bytes / CHUNK_SIZE,
+            sent_bytes,
+            received_bytes,
+            transport: None,
+        });
+    }
+    mset
+}
+
+#[test]
+fn partial_final_chunk_counts() {
+    // Three chunks sent and two and a half of them received;
+    // the last half chunk only arrives in the final second
+    let samples = [(0, 0, 0), (1000, 3000, 2000), (2000, 3000, 2500)];
+    let mset = run(&samples);
+
+    assert_eq!(mset.throughput(), vec![2000.0, 500.0]);
+    assert!((mset.mean_throughput() - 1250.0).abs() < 1e-9);
+    assert!((mset.loss() - 1.0 / 6.0).abs() < 1e-9);
+    assert_eq!(mset.longest_stall(), Duration::ZERO);
+    assert_eq!(mset.interpolate_bytes(1.5), Some((3000.0, 2250.0)));
+    assert_eq!(mset.interpolate(1.5), Some((3.0, 2.0)));
+
+    let comparison = Comparison::new(
+        vec![
+            Run {
+                label: "a".into(),
+                mset,
+            },
+            Run {
+                label: "b".into(),
+                mset: run(&samples),
+            },
+        ],
+        0.05,
+    )
+    .unwrap();
+    let throughput = comparison.aligned_throughput();
+    assert_eq!(throughput[1].len(), 2);
+    assert!((throughput[1][0] - 2000e-6).abs() < 1e-12);
+    assert!((throughput[1][1] - 500e-6).abs() < 1e-12);
+}
+
+#[test]
+fn interpolation() {
+    let mset = run(&[(250, 1000, 0), (750, 3000, 2000)]);
+
+    assert_eq!(mset.interpolate(0.25), Some((1.0, 0.0)));
+    assert_eq!(mset.interpolate_bytes(0.5), Some((2000.0, 1000.0)));
+    assert_eq!(mset.interpolate(0.75), Some((3.0, 2.0)));
+    // Outside the measured range
+    assert_eq!(mset.interpolate(0.0), None);
+    assert_eq!(mset.interpolate(1.0), None);
+    assert_eq!(MeasurementSet::default().interpolate(0.0), None);
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+};
+
+use seismic::{
+    measurement::MeasurementSet,
+    metrics::{MetricsBackend, MetricsConfig, MetricsPusher},
+};
+
+fn sample_mset() -> MeasurementSet {
+    let mut mset = MeasurementSet::new(1024, false);
+    mset.peer = Some("10.0.0.2:7225".into());
+    mset.metadata.insert("role".into(), "sender".into());
+    mset.record(10, 8);
+    mset
+}
+
+fn config(backend: MetricsBackend) -> MetricsConfig {
+    MetricsConfig {
+        backends: vec![backend],
+        tags: [("site".to_string(), "lab 1".to_string())].into(),
+        ..MetricsConfig::default()
+    }
+}
+
+async fn push_one(config: MetricsConfig, mset: &MeasurementSet) {
+    let pusher = MetricsPusher::connect(config).await.unwrap();
+    pusher.push(mset, &mset.measurements[0]);
+    pusher.finish().await;
+}
+
+async fn recv_udp(socket: &UdpSocket) -> String {
+    let mut buf = vec![0; 4096];
+    let n = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+        .await
+        .expect("timed out waiting for datagram")
+        .unwrap();
+    String::from_utf8(buf[..n].to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn influx_udp_line_protocol() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = receiver.local_addr().unwrap().to_string();
+    let mset = sample_mset();
+
+    push_one(config(MetricsBackend::InfluxUdp(addr)), &mset).await;
+
+    let line = recv_udp(&receiver).await;
+    let expected_prefix = "seismic,role=sender,site=lab\\ 1 \
+         sent=10i,received=8i,sent_bytes=10240i,received_bytes=8192i ";
+    assert!(line.starts_with(expected_prefix), "{}", line);
+}
+
+#[tokio::test]
+async fn tag_keys_pick_the_tags() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = receiver.local_addr().unwrap().to_string();
+    let mut mset = sample_mset();
+    mset.metadata.insert("streams".into(), "1".into());
+
+    let config = MetricsConfig {
+        tag_keys: vec!["peer".into(), "run_id".into(), "streams".into()],
+        ..config(MetricsBackend::InfluxUdp(addr))
+    };
+    push_one(config, &mset).await;
+
+    let line = recv_udp(&receiver).await;
+    let expected_prefix = format!(
+        "seismic,peer=10.0.0.2:7225,run_id={},site=lab\\ 1,streams=1 ",
+        mset.id
+    );
+    assert!(line.starts_with(&expected_prefix), "{}", line);
+}
+
+#[tokio::test]
+async fn udp_over_ipv6() {
+    let receiver = match UdpSocket::bind("[::1]:0").await {
+        Ok(receiver) => receiver,
+        // No IPv6 loopback here
+        Err(_) => return,
+    };
+    let addr = receiver.local_addr().unwrap().to_string();
+    let mset = sample_mset();
+
+    push_one(config(MetricsBackend::Statsd(addr.clone())), &mset).await;
+    assert!(recv_udp(&receiver).await.starts_with("seismic.sent:10|g|#"));
+    push_one(config(MetricsBackend::InfluxUdp(addr)), &mset).await;
+    assert!(recv_udp(&receiver).await.starts_with("seismic,"));
+}
+
+#[tokio::test]
+async fn statsd_gauges() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = receiver.local_addr().unwrap().to_string();
+    let mset = sample_mset();
+
+    push_one(config(MetricsBackend::Statsd(addr)), &mset).await;
+
+    let payload = recv_udp(&receiver).await;
+    let lines: Vec<&str> = payload.lines().collect();
+    let tags = "role:sender,site:lab 1";
+    assert_eq!(
+        lines,
+        vec![
+            format!("seismic.sent:10|g|#{}", tags),
+            format!("seismic.received:8|g|#{}", tags),
+            format!("seismic.sent_bytes:10240|g|#{}", tags),
+            format!("seismic.received_bytes:8192|g|#{}", tags),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn influx_http_write() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://{}/api/v2/write?org=o&bucket=b",
+        listener.local_addr().unwrap()
+    );
+
+    // Minimal stand-in for the InfluxDB write endpoint
+    let server = tokio::spawn(async move {
+        let (mut stream, _addr) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = vec![0; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(String::from)
+                    })
+                    .and_then(|l| l.trim().parse().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let mset = sample_mset();
+    let backend = MetricsBackend::InfluxHttp {
+        url,
+        token: Some("secret".into()),
+    };
+    push_one(config(backend), &mset).await;
+
+    let request = server.await.unwrap();
+    assert!(request.starts_with("POST /api/v2/write?org=o&bucket=b HTTP/1.1\r\n"));
+    assert!(request
+        .to_lowercase()
+        .contains("authorization: token secret\r\n"));
+    assert!(request.contains("sent=10i,received=8i"));
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use seismic::{
+    limits::Limits,
+    sender::{Sender, SenderConfig, Transport},
+};
+
+use common::{sender_config, server_state, start_quic_server, Pki};
+
+/// Start a QUIC server, returning (control, data) addresses
+async fn start(pki: &Pki, limits: Limits) -> (SocketAddr, SocketAddr) {
+    start_quic_server(server_state().with_limits(limits), &pki.server_config()).await
+}
+
+fn quic_config(addrs: (SocketAddr, SocketAddr), pki: &Pki, streams: usize) -> SenderConfig {
+    SenderConfig {
+        tls: Some(pki.client_config()),
+        transport: Transport::Quic,
+        streams,
+        ..sender_config(addrs)
+    }
+}
+
+#[tokio::test]
+async fn quic_round_trip_with_transport_stats() {
+    let pki = Pki::generate();
+    let addrs = start(&pki, Limits::default()).await;
+
+    let sender = Sender::new(quic_config(addrs, &pki, 1)).await.unwrap();
+    let mset = sender.run().await.unwrap();
+
+    assert_eq!(
+        mset.metadata.get("transport").map(String::as_str),
+        Some("quic")
+    );
+    assert!(mset.is_encrypted());
+    let last = mset.measurements.last().unwrap();
+    assert!(last.sent > 0);
+    assert!(last.received > 0);
+    assert!(mset.measurements.iter().any(|m| m.transport.is_some()));
+}
+
+#[tokio::test]
+async fn quic_multiple_streams() {
+    let pki = Pki::generate();
+    let addrs = start(&pki, Limits::default()).await;
+
+    let sender = Sender::new(quic_config(addrs, &pki, 3)).await.unwrap();
+    let mset = sender.run().await.unwrap();
+
+    assert_eq!(mset.metadata.get("streams").map(String::as_str), Some("3"));
+    assert!(mset.measurements.last().unwrap().received > 0);
+}
+
+#[tokio::test]
+async fn quic_streams_share_a_fixed_total() {
+    let pki = Pki::generate();
+    let addrs = start(&pki, Limits::default()).await;
+
+    // 10 chunks over 3 streams: 4, 3 and 3
+    let config = SenderConfig {
+        total_bytes: Some(10 * 1024),
+        ..quic_config(addrs, &pki, 3)
+    };
+    let mset = Sender::new(config).await.unwrap().run().await.unwrap();
+
+    let last = mset.measurements.last().unwrap();
+    assert_eq!(last.sent_bytes, 10 * 1024);
+    assert_eq!(last.received_bytes, 10 * 1024);
+    assert_eq!(mset.metadata["completion"], "clean");
+    assert!(mset.transfer_time().is_some());
+}
+
+#[tokio::test]
+async fn too_many_streams_are_rejected() {
+    let pki = Pki::generate();
+    let limits = Limits {
+        max_streams: Some(2),
+        ..Limits::default()
+    };
+    let addrs = start(&pki, limits).await;
+
+    assert!(Sender::new(quic_config(addrs, &pki, 3)).await.is_err());
+}
@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use seismic::{
+    measurement::MeasurementSet,
+    measurer::{parse_interval, Measurer, MeasurerStopper},
+};
+
+fn measurer(freq: Duration) -> (Measurer, MeasurerStopper, Arc<AtomicU64>) {
+    let received = Arc::new(AtomicU64::new(0));
+    let (measurer, stopper) = Measurer::new(
+        freq,
+        1024,
+        false,
+        Arc::new(AtomicU64::new(0)),
+        received.clone(),
+    );
+    (measurer, stopper, received)
+}
+
+fn gaps(mset: &MeasurementSet) -> Vec<Duration> {
+    mset.measurements
+        .windows(2)
+        .map(|pair| pair[1].dt - pair[0].dt)
+        .collect()
+}
+
+#[test]
+fn intervals_parse() {
+    assert_eq!(parse_interval("200").unwrap(), Duration::from_millis(200));
+    assert_eq!(parse_interval("1.5").unwrap(), Duration::from_micros(1500));
+    assert_eq!(parse_interval("250us").unwrap(), Duration::from_micros(250));
+    assert_eq!(parse_interval("2s").unwrap(), Duration::from_secs(2));
+    assert!(parse_interval("0").is_err());
+    assert!(parse_interval("-1").is_err());
+    assert!(parse_interval("often").is_err());
+}
+
+#[test]
+fn ring_buffer_keeps_the_latest() {
+    let mut mset = MeasurementSet::new(1, false).with_max_samples(3);
+    for received in 1..=5 {
+        mset.record(received, received);
+    }
+    mset.finish();
+
+    let received: Vec<_> = mset.measurements.iter().map(|m| m.received).collect();
+    assert_eq!(received, [3, 4, 5]);
+    assert_eq!(
+        mset.metadata.get("samples_dropped").map(String::as_str),
+        Some("2")
+    );
+}
+
+#[tokio::test]
+async fn sub_millisecond_sampling() {
+    let (measurer, stopper, _) = measurer(Duration::from_micros(250));
+    let run = tokio::spawn(measurer.run());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stopper.stop();
+    let mset = run.await.unwrap();
+
+    assert!(
+        mset.measurements.len() > 20,
+        "only {} samples",
+        mset.measurements.len()
+    );
+    // Each sample keeps its actual time, which only moves forward
+    assert!(gaps(&mset).iter().all(|gap| !gap.is_zero()));
+}
+
+#[tokio::test]
+async fn adaptive_sampling_backs_off_and_resets() {
+    let (measurer, stopper, received) = measurer(Duration::from_millis(1));
+    let max = Duration::from_millis(32);
+    let run = tokio::spawn(measurer.with_adaptive(max).run());
+
+    // Idle, hence steady: the interval grows to the maximum
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    received.store(1 << 20, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stopper.stop();
+    let mset = run.await.unwrap();
+
+    let gaps = gaps(&mset);
+    assert!(mset.measurements.len() < 100, "{} samples", gaps.len() + 1);
+    assert!(gaps.iter().any(|gap| *gap >= max));
+    // Sampling is dense again just after the change
+    let change = mset
+        .measurements
+        .iter()
+        .position(|m| m.received_bytes > 0)
+        .unwrap();
+    assert!(gaps[change] < max, "gap after change: {:?}", gaps[change]);
+}
+
+#[tokio::test]
+async fn measurer_bounds_samples() {
+    let (measurer, stopper, received) = measurer(Duration::from_millis(1));
+    let run = tokio::spawn(measurer.with_max_samples(5).run());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    received.store(1024, Ordering::SeqCst);
+    stopper.stop();
+    let mset = run.await.unwrap();
+
+    assert_eq!(mset.measurements.len(), 5);
+    assert!(mset.metadata.contains_key("samples_dropped"));
+    assert!(gaps(&mset).iter().all(|gap| !gap.is_zero()));
+    // The final sample, taken on stop, comes last
+    assert_eq!(mset.measurements.last().unwrap().received_bytes, 1024);
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::broadcast::error::RecvError;
+
+use seismic::{
+    measurement::{Measurement, MeasurementSet},
+    measurer::Measurer,
+    receiver::ReceiverConfig,
+    sender::{Sender, SenderConfig},
+    server::{listen_control, listen_data, ServerState},
+    sink::MeasurementSink,
+    transport,
+};
+
+use common::receiver_config;
+
+/// Collects what it's given, noting when it's finished
+#[derive(Clone, Default)]
+struct Collect {
+    received: Arc<Mutex<Vec<u64>>>,
+    finished: Arc<AtomicBool>,
+}
+
+impl MeasurementSink for Collect {
+    fn record(&mut self, _mset: &MeasurementSet, measurement: &Measurement) {
+        self.received
+            .lock()
+            .unwrap()
+            .push(measurement.received_bytes);
+    }
+
+    fn finish(self: Box<Self>) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+        self.finished.store(true, Ordering::SeqCst);
+        Box::pin(async {})
+    }
+}
+
+#[tokio::test]
+async fn measurer_feeds_sinks() {
+    let received = Arc::new(AtomicU64::new(0));
+    let (measurer, stopper) = Measurer::new(
+        Duration::from_millis(10),
+        1024,
+        false,
+        Arc::new(AtomicU64::new(0)),
+        received.clone(),
+    );
+    let sink = Collect::default();
+    let run = tokio::spawn(measurer.with_sink(sink.clone()).run());
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    received.store(4096, Ordering::SeqCst);
+    stopper.stop();
+    let mset = run.await.unwrap();
+
+    let live: Vec<_> = mset.measurements.iter().map(|m| m.received_bytes).collect();
+    assert_eq!(*sink.received.lock().unwrap(), live);
+    assert_eq!(live.last(), Some(&4096));
+    assert!(sink.finished.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn sender_streams_measurements() {
+    let (control, control_listener) = transport::duplex("control", 64 * 1024);
+    let (data, data_listener) = transport::duplex("data", 64 * 1024);
+    let server_config = ReceiverConfig {
+        freq: Duration::from_millis(50),
+        ..receiver_config()
+    };
+    let state = ServerState::new(server_config, None);
+    tokio::spawn(listen_control(control_listener, state.clone()));
+    tokio::spawn(listen_data(data_listener, state));
+
+    let config = SenderConfig {
+        freq: Duration::from_millis(50),
+        length: Duration::from_millis(300),
+        ..SenderConfig::default()
+    };
+    let sender = Sender::connect(config, &control, Arc::new(data))
+        .await
+        .unwrap();
+    let mut live = sender.subscribe();
+    let mset = sender.run().await.unwrap();
+
+    let mut streamed = Vec::new();
+    loop {
+        match live.recv().await {
+            Ok(measurement) => streamed.push(measurement.dt),
+            Err(RecvError::Closed) => break,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    let recorded: Vec<_> = mset.measurements.iter().map(|m| m.dt).collect();
+    assert_eq!(streamed, recorded);
+}
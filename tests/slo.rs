@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use seismic::{
+    measurement::{Measurement, MeasurementSet},
+    slo::{self, Check, SloReport, Thresholds},
+};
+
+/// Ten thousand bytes per second for a second, sampled every 100ms,
+/// with everything echoed back within the interval it was sent in
+fn steady() -> MeasurementSet {
+    let mut mset = MeasurementSet::new(1000, false);
+    for i in 0..=10 {
+        let bytes = 1000 * i;
+        mset.measurements.push(Measurement {
+            dt: Duration::from_millis(100 * i),
+            sent: i,
+            received: i,
+            sent_bytes: bytes,
+            received_bytes: bytes,
+            transport: None,
+        });
+    }
+    mset
+}
+
+#[test]
+fn thresholds_pass() {
+    let thresholds = Thresholds {
+        min_throughput: Some(0.005),
+        max_rtt_p99: Some(Duration::from_millis(10)),
+        max_loss_pct: Some(0.0),
+        max_stall: Some(Duration::from_millis(50)),
+    };
+    let report = thresholds.check(&steady());
+
+    let names: Vec<_> = report.checks.iter().map(|c| c.name).collect();
+    assert_eq!(names, ["throughput", "rtt_p99", "loss", "stall"]);
+    assert!(report.passed());
+    assert_eq!(report.failures(), 0);
+    assert!((report.checks[0].actual - 0.01).abs() < 1e-9);
+}
+
+#[test]
+fn thresholds_fail() {
+    let mut mset = steady();
+    // The last second's data never arrives
+    let last = mset.measurements.last_mut().unwrap();
+    last.received_bytes -= 1000;
+    last.received -= 1;
+
+    let thresholds = Thresholds {
+        min_throughput: Some(0.02),
+        max_loss_pct: Some(5.0),
+        max_stall: Some(Duration::from_millis(50)),
+        ..Thresholds::default()
+    };
+    let report = thresholds.check(&mset);
+
+    assert!(!report.passed());
+    assert_eq!(report.failures(), 3);
+    let loss = &report.checks[1];
+    assert_eq!(loss.name, "loss");
+    assert!((loss.actual - 10.0).abs() < 1e-9);
+    assert_eq!(report.checks[2].actual, 100.0);
+
+    // Nothing echoed means no RTT at all
+    let thresholds = Thresholds {
+        max_rtt_p99: Some(Duration::from_secs(1)),
+        ..Thresholds::default()
+    };
+    assert!(!thresholds.check(&MeasurementSet::default()).passed());
+    assert!(Thresholds::default().is_empty());
+    assert!(Thresholds::default().check(&mset).passed());
+}
+
+#[test]
+fn junit_report() {
+    let report = SloReport {
+        checks: vec![
+            Check {
+                name: "throughput",
+                unit: "MB/s",
+                expected: ">= 1".into(),
+                actual: 2.0,
+                passed: true,
+            },
+            Check {
+                name: "stall",
+                unit: "ms",
+                expected: "<= 100".into(),
+                actual: 250.0,
+                passed: false,
+            },
+        ],
+    };
+
+    assert_eq!(
+        report.to_junit("seismic"),
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="seismic" tests="2" failures="1">
+  <testcase classname="seismic" name="throughput"/>
+  <testcase classname="seismic" name="stall">
+    <failure message="stall was 250.000 ms, expected &lt;= 100 ms"/>
+  </testcase>
+</testsuite>
+"#
+    );
+}
+
+#[test]
+fn junit_escapes_xml() {
+    let report = SloReport { checks: vec![] };
+    let xml = report.to_junit(r#"<&">'"#);
+    assert!(
+        xml.contains(r#"<testsuite name="&lt;&amp;&quot;&gt;&apos;" tests="0" failures="0">"#),
+        "{}",
+        xml
+    );
+
+    let path = std::env::temp_dir().join(format!("seismic-junit-{}.xml", std::process::id()));
+    slo::write_junit_error("seismic", r#"refused: <&">"#, &path).unwrap();
+    let xml = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        xml.contains(r#"<error message="refused: &lt;&amp;&quot;&gt;"/>"#),
+        "{}",
+        xml
+    );
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use seismic::{
+    measurement::MeasurementSet,
+    store::{RunFilter, Store},
+};
+
+/// A store with one empty run per (peer, start time in seconds)
+fn store(runs: &[(&str, u64)]) -> Store {
+    let mut store = Store::open(":memory:").unwrap();
+    for &(peer, start) in runs {
+        let mut mset = MeasurementSet::default();
+        mset.peer = Some(peer.into());
+        mset.start_time = at(start);
+        store.insert(&mset).unwrap();
+    }
+    store
+}
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Peers of the runs matching `filter`, oldest first
+fn peers(store: &Store, filter: RunFilter) -> Vec<String> {
+    store
+        .list(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|run| run.peer.unwrap())
+        .collect()
+}
+
+#[test]
+fn filter_by_peer() {
+    let store = store(&[
+        ("10.0.0.1:5000", 1),
+        ("10.0.0.10:5000", 2),
+        ("10.0.0.1:6000", 3),
+        ("host-a:5000", 4),
+        ("host_a:5000", 5),
+    ]);
+    let by_peer = |peer: &str| {
+        peers(
+            &store,
+            RunFilter {
+                peer: Some(peer.into()),
+                ..RunFilter::default()
+            },
+        )
+    };
+
+    assert_eq!(by_peer("10.0.0.1"), ["10.0.0.1:5000", "10.0.0.1:6000"]);
+    assert_eq!(by_peer("10.0.0.1:6000"), ["10.0.0.1:6000"]);
+    assert!(by_peer("10.0.0").is_empty());
+    // LIKE wildcards are taken literally
+    assert_eq!(by_peer("host_a"), ["host_a:5000"]);
+    assert!(by_peer("%").is_empty());
+    assert!(by_peer("10.0.0.1%").is_empty());
+    assert_eq!(peers(&store, RunFilter::default()).len(), 5);
+}
+
+#[test]
+fn filter_by_time() {
+    let store = store(&[("a:1", 100), ("b:1", 200), ("c:1", 300)]);
+
+    let since = RunFilter {
+        since: Some(at(200)),
+        ..RunFilter::default()
+    };
+    assert_eq!(peers(&store, since), ["b:1", "c:1"]);
+
+    let until = RunFilter {
+        until: Some(at(200)),
+        ..RunFilter::default()
+    };
+    assert_eq!(peers(&store, until), ["a:1", "b:1"]);
+
+    let between = RunFilter {
+        peer: Some("b".into()),
+        since: Some(at(150)),
+        until: Some(at(250)),
+    };
+    assert_eq!(peers(&store, between), ["b:1"]);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use seismic::{
+    sender::{Sender, SenderConfig},
+    tls::{TlsClientConfig, TlsServerConfig},
+};
+
+use common::{sender_config, server_state, start_server, Pki};
+
+/// Start a TLS server, returning (control, data) addresses
+async fn start(tls: TlsServerConfig) -> (SocketAddr, SocketAddr) {
+    start_server(server_state().with_tls(tls.acceptor().unwrap())).await
+}
+
+fn with_tls(addrs: (SocketAddr, SocketAddr), tls: TlsClientConfig) -> SenderConfig {
+    SenderConfig {
+        tls: Some(tls),
+        ..sender_config(addrs)
+    }
+}
+
+#[tokio::test]
+async fn tls_round_trip() {
+    let pki = Pki::generate();
+    let addrs = start(pki.server_config()).await;
+
+    let sender = Sender::new(with_tls(addrs, pki.client_config()))
+        .await
+        .unwrap();
+    let mset = sender.run().await.unwrap();
+
+    assert!(mset.is_encrypted());
+    assert!(!mset.is_host_internal());
+    let last = mset.measurements.last().unwrap();
+    assert!(last.sent > 0);
+    assert!(last.received > 0);
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_client_certificate() {
+    let pki = Pki::generate();
+    let addrs = start(TlsServerConfig {
+        client_ca: Some(pki.path("ca.pem")),
+        ..pki.server_config()
+    })
+    .await;
+
+    let anonymous = pki.client_config();
+    assert!(Sender::new(with_tls(addrs, anonymous.clone()))
+        .await
+        .is_err());
+
+    let authenticated = TlsClientConfig {
+        identity: Some((pki.path("client.pem"), pki.path("client.key"))),
+        ..anonymous
+    };
+    let sender = Sender::new(with_tls(addrs, authenticated)).await.unwrap();
+    let mset = sender.run().await.unwrap();
+    assert!(mset.measurements.last().unwrap().received > 0);
+}
+
+#[tokio::test]
+async fn unpinned_ca_is_rejected() {
+    let pki = Pki::generate();
+    let other = Pki::generate();
+    let addrs = start(pki.server_config()).await;
+
+    let tls = other.client_config();
+    assert!(Sender::new(with_tls(addrs, tls)).await.is_err());
+}
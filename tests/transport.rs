@@ -0,0 +1,78 @@
+mod common;
+
+use std::sync::Arc;
+
+use tokio::net::UnixListener;
+use uuid::Uuid;
+
+use seismic::{
+    sender::Sender,
+    server::{listen_control, listen_data},
+    transport::{self, Connector, UnixConnector},
+};
+
+use common::{server_state, test_config};
+
+#[tokio::test]
+async fn duplex_round_trip() {
+    let (control, control_listener) = transport::duplex("control", 64 * 1024);
+    let (data, data_listener) = transport::duplex("data", 64 * 1024);
+    let state = server_state();
+    tokio::spawn(listen_control(control_listener, state.clone()));
+    tokio::spawn(listen_data(data_listener, state));
+
+    let sender = Sender::connect(test_config(), &control, Arc::new(data))
+        .await
+        .unwrap();
+    let mset = sender.run().await.unwrap();
+
+    assert_eq!(
+        mset.metadata.get("transport").map(String::as_str),
+        Some("duplex")
+    );
+    let last = mset.measurements.last().unwrap();
+    assert!(last.sent > 0);
+    assert!(last.received > 0);
+}
+
+#[tokio::test]
+async fn unix_socket_round_trip() {
+    let dir = std::env::temp_dir().join(format!("seismic-uds-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let control_path = dir.join("control.sock");
+    let data_path = dir.join("data.sock");
+
+    let state = server_state();
+    let control_listener = UnixListener::bind(&control_path).unwrap();
+    let data_listener = UnixListener::bind(&data_path).unwrap();
+    tokio::spawn(listen_control(control_listener, state.clone()));
+    tokio::spawn(listen_data(data_listener, state));
+
+    let control = UnixConnector::new(&control_path, None);
+    let data: Arc<dyn Connector> = Arc::new(UnixConnector::new(&data_path, None));
+    let sender = Sender::connect(test_config(), &control, data)
+        .await
+        .unwrap();
+    let mset = sender.run().await.unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(
+        mset.metadata.get("transport").map(String::as_str),
+        Some("unix")
+    );
+    assert!(mset.measurements.last().unwrap().received > 0);
+}
+
+#[tokio::test]
+async fn duplex_rejects_unknown_session() {
+    let (control, control_listener) = transport::duplex("control", 64 * 1024);
+    let state = server_state();
+    tokio::spawn(listen_control(control_listener, state));
+
+    // No data listener: the sender can't open its data stream
+    let (data, data_listener) = transport::duplex("data", 64 * 1024);
+    drop(data_listener);
+    assert!(Sender::connect(test_config(), &control, Arc::new(data))
+        .await
+        .is_err());
+}
@@ -0,0 +1,130 @@
+//! Run with `cargo test --features io-uring`
+#![cfg(all(target_os = "linux", feature = "io-uring"))]
+
+mod common;
+
+use std::{
+    net::SocketAddr,
+    os::fd::AsFd,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream, UnixListener},
+};
+
+use seismic::{
+    limits::RateLimiter,
+    reader::SimpleReader,
+    sender::{Backend, Sender, SenderConfig, Transport},
+    server::{listen_control, listen_data},
+    stream::{self, BoxedStream},
+    transport::{self, UNIX_CONTROL_SOCKET, UNIX_DATA_SOCKET},
+};
+
+use common::{sender_config, server_state, start_server, test_config};
+
+/// A test over TCP through io_uring
+fn io_uring_config(addrs: (SocketAddr, SocketAddr)) -> SenderConfig {
+    SenderConfig {
+        backend: Backend::IoUring,
+        ..sender_config(addrs)
+    }
+}
+
+#[tokio::test]
+async fn io_uring_over_tcp() {
+    let addrs = start_server(server_state()).await;
+
+    let config = io_uring_config(addrs);
+    let mset = Sender::new(config).await.unwrap().run().await.unwrap();
+
+    let last = mset.measurements.last().unwrap();
+    assert!(last.sent > 0);
+    // Everything sent was echoed back
+    assert_eq!(last.received_bytes, last.sent_bytes);
+    assert_eq!(mset.metadata["backend"], "io-uring");
+    assert_eq!(mset.metadata["completion"], "clean");
+}
+
+#[tokio::test]
+async fn io_uring_over_unix_sockets() {
+    let dir = std::env::temp_dir().join(format!("seismic-uring-{}", std::process::id()));
+    let (control, data): (UnixListener, UnixListener) = transport::bind_unix(&dir).unwrap();
+    let state = server_state();
+    tokio::spawn(listen_control(control, state.clone()));
+    tokio::spawn(listen_data(data, state));
+
+    let socket = |name| dir.join(name).display().to_string();
+    let config = SenderConfig {
+        addr: socket(UNIX_DATA_SOCKET),
+        control_addr: socket(UNIX_CONTROL_SOCKET),
+        transport: Transport::Unix,
+        backend: Backend::IoUring,
+        ..test_config()
+    };
+    let mset = Sender::new(config).await.unwrap().run().await.unwrap();
+
+    let last = mset.measurements.last().unwrap();
+    assert!(last.sent > 0);
+    assert_eq!(last.received_bytes, last.sent_bytes);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn io_uring_sends_a_fixed_total() {
+    let addrs = start_server(server_state()).await;
+
+    let config = SenderConfig {
+        total_bytes: Some(1_000_000),
+        ..io_uring_config(addrs)
+    };
+    let mset = Sender::new(config).await.unwrap().run().await.unwrap();
+
+    // Rounded up to whole chunks
+    let last = mset.measurements.last().unwrap();
+    assert_eq!(last.sent_bytes, 1_000_448);
+    assert_eq!(last.received_bytes, 1_000_448);
+    assert_eq!(mset.metadata["completion"], "clean");
+}
+
+#[tokio::test]
+async fn io_uring_excludes_zero_copy() {
+    let addrs = start_server(server_state()).await;
+
+    let config = SenderConfig {
+        zero_copy: true,
+        ..io_uring_config(addrs)
+    };
+    let err = Sender::new(config).await.err().unwrap().to_string();
+    assert!(err.contains("zero-copy"), "{}", err);
+}
+
+#[tokio::test]
+async fn io_uring_reader_is_rate_limited() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let socket = server.as_fd().try_clone_to_owned().unwrap();
+    let (read_half, _write_half) = stream::split(Box::new(server) as BoxedStream);
+
+    let received = Arc::new(AtomicU64::new(0));
+    let mut reader = SimpleReader::new(read_half, 1024, received.clone())
+        .with_rate_limit(Some(Arc::new(RateLimiter::new(1e6))))
+        .with_io_uring(socket);
+    let start = Instant::now();
+    let reading = tokio::spawn(async move { reader.run().await });
+
+    // 1 MB/s for 200 kB should take about 200 ms
+    client.write_all(&[0; 200_000]).await.unwrap();
+    client.shutdown().await.unwrap();
+    reading.await.unwrap().unwrap();
+    let elapsed = start.elapsed();
+
+    assert_eq!(received.load(std::sync::atomic::Ordering::SeqCst), 200_000);
+    assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use seismic::{
+    measurement::{Measurement, MeasurementSet, Warmup},
+    slo::Thresholds,
+};
+
+/// A run sampled every 100ms, receiving chunks of one byte at these
+/// rates (chunks per interval), with everything sent arriving
+fn run(rates: &[u64]) -> MeasurementSet {
+    let mut mset = MeasurementSet::new(1, false);
+    let mut received = 0;
+    for (i, rate) in [0].iter().chain(rates).enumerate() {
+        received += rate;
+        mset.measurements.push(Measurement {
+            dt: Duration::from_millis(100 * i as u64),
+            sent: received,
+            received,
+            sent_bytes: received,
+            received_bytes: received,
+            transport: None,
+        });
+    }
+    mset
+}
+
+/// Slow start, then ten chunks per interval
+const RAMP: [u64; 10] = [1, 2, 4, 8, 10, 10, 10, 10, 10, 10];
+
+#[test]
+fn slow_start_is_detected() {
+    let mut mset = run(&RAMP);
+    let slow_start = mset.slow_start().unwrap();
+    assert_eq!(slow_start.duration, Duration::from_millis(400));
+    assert_eq!(slow_start.received_bytes, 15);
+    assert!((slow_start.throughput - 37.5).abs() < 1e-9);
+
+    mset.finish();
+    assert_eq!(mset.metadata["steady_state_secs"], "0.400000");
+
+    // Already steady, and too short to tell
+    assert_eq!(run(&[10; 8]).slow_start().unwrap().duration, Duration::ZERO);
+    assert!(run(&[1, 2, 4]).slow_start().is_none());
+}
+
+#[test]
+fn warmup_is_left_out_of_summaries() {
+    let mut mset = run(&RAMP);
+    assert!((mset.mean_throughput() - 75.0).abs() < 1e-9);
+
+    mset.set_warmup(Warmup::Fixed(Duration::from_millis(400)));
+    assert_eq!(mset.warmup(), Duration::from_millis(400));
+    assert!((mset.mean_throughput() - 100.0).abs() < 1e-9);
+
+    mset.set_warmup(Warmup::Auto);
+    assert_eq!(mset.warmup(), Duration::from_millis(400));
+    let thresholds = Thresholds {
+        min_throughput: Some(90e-6),
+        ..Thresholds::default()
+    };
+    assert!(thresholds.check(&mset).passed());
+
+    mset.set_warmup(Warmup::Off);
+    assert!(!mset.metadata.contains_key("warmup_secs"));
+    assert!(!thresholds.check(&mset).passed());
+}
+
+#[test]
+fn stalls_during_warmup_are_ignored() {
+    let mut mset = run(&[0, 0, 0, 5, 5, 5, 5, 5]);
+    assert_eq!(mset.longest_stall(), Duration::from_millis(300));
+
+    mset.set_warmup(Warmup::Fixed(Duration::from_millis(300)));
+    assert_eq!(mset.longest_stall(), Duration::ZERO);
+}
+
+#[test]
+fn warmups_parse() {
+    assert_eq!("auto".parse::<Warmup>().unwrap(), Warmup::Auto);
+    assert_eq!("0".parse::<Warmup>().unwrap(), Warmup::Off);
+    assert_eq!(
+        "1.5".parse::<Warmup>().unwrap(),
+        Warmup::Fixed(Duration::from_millis(1500))
+    );
+    assert_eq!(
+        "500ms".parse::<Warmup>().unwrap(),
+        Warmup::Fixed(Duration::from_millis(500))
+    );
+    assert!("-1".parse::<Warmup>().is_err());
+    assert!("soon".parse::<Warmup>().is_err());
+}